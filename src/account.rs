@@ -0,0 +1,123 @@
+//! registered accounts users log in to, kept in the snapshot so they survive a restart
+//! names are matched without regard to case
+
+use std::collections::HashMap;
+
+use crate::tls::parse_fingerprint;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Account {
+    pub name: String,
+    /// argon2 hash of the password, see crate::auth
+    pub password: String,
+    /// sha256 fingerprints of the TLS client certificates that log in with SASL EXTERNAL
+    #[serde(default)]
+    pub fingerprints: Vec<String>,
+    /// nicknames grouped to the account besides its name, protected the same way
+    #[serde(default)]
+    pub nicks: Vec<String>,
+    /// unix timestamp of when the account was registered
+    #[serde(default)]
+    pub registered: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Accounts {
+    /// by the lowercased account name
+    accounts: HashMap<String, Account>,
+}
+
+impl Account {
+    pub fn new(name: String, password: String, registered: u64) -> Self {
+        Self {
+            name,
+            password,
+            fingerprints: Vec::new(),
+            nicks: Vec::new(),
+            registered,
+        }
+    }
+
+    /// true for the account's name and the nicknames grouped to it, ignoring case
+    pub fn owns_nick(&self, nick: &str) -> bool {
+        self.name.eq_ignore_ascii_case(nick) || self.nicks.iter().any(|grouped| grouped.eq_ignore_ascii_case(nick))
+    }
+
+    /// compares fingerprints by value so the case and colons they were written with don't matter
+    pub fn has_fingerprint(&self, fingerprint: &str) -> bool {
+        let wanted = match parse_fingerprint(fingerprint) {
+            Some(wanted) => wanted,
+            None => return false,
+        };
+        self.fingerprints.iter().any(|known| parse_fingerprint(known) == Some(wanted))
+    }
+}
+
+impl Accounts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Account> {
+        self.accounts.get(&name.to_ascii_lowercase())
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut Account> {
+        self.accounts.get_mut(&name.to_ascii_lowercase())
+    }
+
+    /// adds an account, returning false when the name is already taken
+    pub fn insert(&mut self, account: Account) -> bool {
+        let key = account.name.to_ascii_lowercase();
+        if self.accounts.contains_key(&key) {
+            return false;
+        }
+        self.accounts.insert(key, account);
+        true
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Account> {
+        self.accounts.remove(&name.to_ascii_lowercase())
+    }
+
+    /// the account a nickname is registered to
+    pub fn find_by_nick(&self, nick: &str) -> Option<&Account> {
+        self.get(nick).or_else(|| self.accounts.values().find(|account| account.owns_nick(nick)))
+    }
+
+    /// the account a TLS client certificate belongs to
+    pub fn find_by_fingerprint(&self, fingerprint: &str) -> Option<&Account> {
+        self.accounts.values().find(|account| account.has_fingerprint(fingerprint))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Account> {
+        self.accounts.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.accounts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.accounts.is_empty()
+    }
+}
+
+#[test]
+async fn accounts_test() {
+    let fingerprint = crate::tls::fingerprint(b"client certificate");
+    let mut account = Account::new(String::from("Cardinal"), String::from("$argon2id$v=19$..."), 1583157909);
+    account.fingerprints.push(fingerprint.replace(':', "").to_uppercase());
+    account.nicks.push(String::from("cardinal_away"));
+
+    let mut accounts = Accounts::new();
+    assert!(accounts.insert(account));
+    assert!(!accounts.insert(Account::new(String::from("cardinal"), String::new(), 0)));
+    assert_eq!(accounts.get("CARDINAL").unwrap().name, "Cardinal");
+    assert_eq!(accounts.find_by_fingerprint(&fingerprint).unwrap().name, "Cardinal");
+    assert!(accounts.find_by_fingerprint(&crate::tls::fingerprint(b"another certificate")).is_none());
+    assert_eq!(accounts.find_by_nick("Cardinal_Away").unwrap().name, "Cardinal");
+    assert!(accounts.find_by_nick("cardinal_").is_none());
+    assert!(accounts.remove("cardinal").is_some());
+    assert!(accounts.is_empty());
+}
@@ -0,0 +1,123 @@
+//! password hashing shared by operator blocks and accounts
+//! hashes are argon2id PHC strings such as "$argon2id$v=19$m=19456,t=2,p=1$..."
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use argon2::password_hash::{PasswordHasher, PasswordVerifier, phc::PasswordHash};
+use argon2::Argon2;
+
+/// hashes a password with a random salt
+pub fn hash_password(password: &str) -> Result<String, argon2::password_hash::Error> {
+    Ok(Argon2::default().hash_password(password.as_bytes())?.to_string())
+}
+
+/// checks a password against a PHC string, a malformed hash never matches
+pub fn verify_password(password: &str, hash: &str) -> bool {
+    match PasswordHash::new(hash) {
+        Ok(parsed) => Argon2::default().verify_password(password.as_bytes(), &parsed).is_ok(),
+        Err(_) => false,
+    }
+}
+
+/// verify_password on a blocking thread, hashing is deliberately slow
+/// so it is kept off the async workers serving every other connection
+pub async fn verify_password_async(password: &str, hash: &str) -> bool {
+    let (password, hash) = (password.to_string(), hash.to_string());
+    tokio::task::spawn_blocking(move || verify_password(&password, &hash)).await.unwrap_or(false)
+}
+
+/// hash_password on a blocking thread, None when hashing failed
+pub async fn hash_password_async(password: &str) -> Option<String> {
+    let password = password.to_string();
+    tokio::task::spawn_blocking(move || hash_password(&password).ok()).await.ok().flatten()
+}
+
+/// true when the string is a PHC hash this module can verify against
+pub fn is_password_hash(hash: &str) -> bool {
+    PasswordHash::new(hash).is_ok()
+}
+
+/// wrong passwords an account or oper block takes before logins to it are refused
+pub const MAX_ACCOUNT_FAILURES: u32 = 5;
+/// seconds logins stay refused after the failure that locked them
+pub const ACCOUNT_LOCKOUT: u64 = 300;
+/// wrong passwords a single connection may send before it is disconnected
+pub const MAX_CONNECTION_FAILURES: u32 = 3;
+/// how long a wrong password waits before it is answered, so guesses can't be made quickly
+pub const FAILURE_DELAY: Duration = Duration::from_secs(2);
+
+/// recent wrong passwords for each account and oper block, by name ignoring case
+#[derive(Debug, Default)]
+pub struct LoginFailures {
+    /// the number of failures and the unix timestamp of the last one
+    names: HashMap<String, (u32, u64)>,
+}
+
+impl LoginFailures {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// true while logins to the name are refused without checking the password
+    pub fn is_locked(&self, name: &str, now: u64) -> bool {
+        match self.names.get(&name.to_ascii_lowercase()) {
+            Some((count, last)) => *count >= MAX_ACCOUNT_FAILURES && now < last + ACCOUNT_LOCKOUT,
+            None => false,
+        }
+    }
+
+    /// counts a wrong password, attempts on a locked name don't extend the lock
+    /// so the owner can log in again once it runs out
+    pub fn fail(&mut self, name: &str, now: u64) {
+        if self.is_locked(name, now) {
+            return;
+        }
+        self.names.retain(|_, (_, last)| now < *last + ACCOUNT_LOCKOUT);
+        let (count, last) = self.names.entry(name.to_ascii_lowercase()).or_insert((0, now));
+        *count += 1;
+        *last = now;
+    }
+
+    /// forgets the failures of a name once its password has been given
+    pub fn succeed(&mut self, name: &str) {
+        self.names.remove(&name.to_ascii_lowercase());
+    }
+}
+
+#[test]
+async fn login_failures_test() {
+    let mut failures = LoginFailures::new();
+    for _ in 0..MAX_ACCOUNT_FAILURES - 1 {
+        failures.fail("Cardinal", 100);
+    }
+    assert!(!failures.is_locked("cardinal", 100));
+    failures.fail("cardinal", 100);
+    assert!(failures.is_locked("CARDINAL", 100));
+    assert!(!failures.is_locked("hephaestus", 100));
+
+    // guesses while locked don't keep the account locked
+    failures.fail("cardinal", 200);
+    assert!(!failures.is_locked("cardinal", 100 + ACCOUNT_LOCKOUT));
+
+    failures.fail("hephaestus", 100);
+    failures.succeed("hephaestus");
+    for _ in 0..MAX_ACCOUNT_FAILURES - 1 {
+        failures.fail("hephaestus", 100);
+    }
+    assert!(!failures.is_locked("hephaestus", 100));
+}
+
+#[test]
+async fn password_hash_test() {
+    let hash = hash_password("hunter42").unwrap();
+    assert!(is_password_hash(&hash));
+    assert!(verify_password("hunter42", &hash));
+    assert!(!verify_password("hunter43", &hash));
+    assert!(!is_password_hash("hunter42"));
+    assert!(!verify_password("hunter42", "hunter42"));
+
+    let hash = hash_password_async("hunter42").await.unwrap();
+    assert!(verify_password_async("hunter42", &hash).await);
+    assert!(!verify_password_async("hunter43", &hash).await);
+}
@@ -1,67 +1,169 @@
-#[macro_use]extern crate tokio;
 use tokio::net::{TcpStream};
 use std::net::SocketAddr;
 use std::str::FromStr;
 use networkingirc::ClientArgs;
 use structopt::StructOpt;
+use tokio_rustls::TlsConnector;
+use rustls_pki_types::ServerName;
 
 
 use tokio::sync::mpsc::*;
 use tokio::task;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
 
-use networkingirc::read_message;
+use networkingirc::LineBuffer;
 use networkingirc::message::Message;
 use networkingirc::command::Command;
 use networkingirc::client::*;
+use networkingirc::tls::{client_config_with_ca, client_config_with_fingerprint};
+use networkingirc::capability::{SASL, SERVER_TIME};
+use networkingirc::sasl::{encode_payload, PlainCredentials};
+
+/// the IRCv3 capabilities this client knows what to do with
+const SUPPORTED_CAPS: [&str; 2] = [SERVER_TIME, SASL];
+/// the numerics that end a SASL exchange, successful or not
+const SASL_DONE: [&str; 5] = ["903", "904", "905", "906", "907"];
+
+/// either a plain or a TLS connection to the server
+trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<S: AsyncRead + AsyncWrite + Unpin + Send> Stream for S {}
+
+/// wraps the connection in TLS, checking the server's certificate against the ca file or fingerprint
+async fn connect_tls(args: &ClientArgs, stream: TcpStream) -> Result<Box<dyn Stream>, String> {
+    let config = match (&args.ca_file, &args.fingerprint) {
+        (Some(ca_file), _) => client_config_with_ca(ca_file),
+        (None, Some(fingerprint)) => client_config_with_fingerprint(fingerprint),
+        (None, None) => return Err(String::from("--tls needs --ca-file or --fingerprint")),
+    }.map_err(|e| e.to_string())?;
+    let name = match &args.tls_name {
+        Some(name) => name.clone(),
+        None => SocketAddr::from_str(&args.address).map_err(|e| e.to_string())?.ip().to_string(),
+    };
+    let name = ServerName::try_from(name).map_err(|e| e.to_string())?;
+    let stream = TlsConnector::from(config).connect(name, stream).await.map_err(|e| e.to_string())?;
+    Ok(Box::new(stream))
+}
 
 #[tokio::main]
 async fn main() {
     let args = ClientArgs::from_args();
-    let mut stream = match TcpStream::connect(&args.address).await {
+    let stream = match TcpStream::connect(&args.address).await {
         Ok(stream) => stream,
         Err(e) => {
             println!("error occured: {}", e);
             std::process::exit(-1);
         }
     };
+    let mut stream: Box<dyn Stream> = if args.tls {
+        match connect_tls(&args, stream).await {
+            Ok(stream) => stream,
+            Err(e) => {
+                println!("couldn't connect over TLS: {}", e);
+                std::process::exit(-1);
+            }
+        }
+    }else{
+        Box::new(stream)
+    };
 
-    stream.write(format!("USER {} {} {} {}", args.username, args.hostname, SocketAddr::from_str(&args.address).unwrap().ip(), args.realname).as_bytes()).await.unwrap();
+    // registration waits until the capabilities have been agreed on
+    stream.write_all(b"CAP LS 302\r\n").await.unwrap();
+    stream.write_all(format!("USER {} {} {} {}\r\n", args.username, args.hostname, SocketAddr::from_str(&args.address).unwrap().ip(), args.realname).as_bytes()).await.unwrap();
     println!("sent join command");
-    let (mut sender, mut receiver): (Sender<String>, Receiver<String>) = channel(100);
-    let (mut read, mut write) = stream.into_split();
+    let (sender, mut receiver): (Sender<String>, Receiver<String>) = channel(100);
+    let (mut read, mut write) = tokio::io::split(stream);
 
     //let address = args.address.clone();
 
     let read_sender = sender.clone();
     task::spawn(async move {
+        // capabilities offered over several CAP LS lines
+        let mut offered = Vec::new();
+        let mut lines = LineBuffer::default();
         loop {
-            
-            let (data, bytes_read) = match read_message(&mut read).await {
-                Ok(bytes) => bytes,
+            let line = match lines.next_line(&mut read).await {
+                Ok(Some(line)) => line,
+                Ok(None) => {
+                    println!("server has terminated the connection exiting now...");
+                    std::process::exit(0);
+                },
                 Err(_) => {
                     eprintln!("server connection closed");
                     std::process::exit(-1);
                 },
             };
-
-            if bytes_read == 0 {
-                println!("server has terminated the connection exiting now...");
-                std::process::exit(0);
-                //break;
+            if line.trim().is_empty() {
+                continue;
             }
 
-            
-
-            let message = Message::parse(data).unwrap();
+            let message = match Message::parse(line) {
+                Ok(message) => message,
+                Err(_) => continue,
+            };
             match message.command() {
                 Command::RAW => {
-                    print!("{}", message.raw_message());
+                    // server-time is shown as the time of day, "2020-03-02T14:05:09.000Z" becomes "[14:05:09]"
+                    match message.tag("time").and_then(|time| time.get(11..19)) {
+                        Some(time) => println!("[{}] {}", time, message.raw_message()),
+                        None => println!("{}", message.raw_message()),
+                    }
+                    // registration carries on whether or not the login worked
+                    let code = message.raw_message().split(' ').next().unwrap_or_default();
+                    if SASL_DONE.contains(&code) {
+                        read_sender.send(String::from("CAP END\r\n")).await.unwrap();
+                    }
+                },
+                // the server is ready for the credentials
+                Command::AUTHENTICATE(challenge) if challenge == "+" => {
+                    if let (Some(user), Some(pass)) = (&args.sasl_user, &args.sasl_pass) {
+                        let credentials = PlainCredentials {
+                            authzid: String::new(),
+                            authcid: user.clone(),
+                            password: pass.clone(),
+                        };
+                        for chunk in encode_payload(&credentials.encode()) {
+                            read_sender.send(format!("AUTHENTICATE {}\r\n", chunk)).await.unwrap();
+                        }
+                    }
                 },
                 Command::PING(server, _) => {
                     println!("received ping from server: {} answering with pong", server);
                     read_sender.send(format!("PONG {} :12345", args.address)).await.unwrap();
-                    
+
+                },
+                // the server's replies read "CAP <nick> <subcommand> [*] :<capabilities>"
+                Command::CAP(_, _) => {
+                    let params = message.raw_message().split(' ').collect::<Vec<&str>>();
+                    let capabilities = message.trailing().unwrap_or_default();
+                    match params.get(2).copied() {
+                        Some("LS") => {
+                            offered.extend(capabilities.split_whitespace().map(|cap| cap.split('=').next().unwrap_or_default().to_string()));
+                            if params.get(3) == Some(&"*") {
+                                continue;
+                            }
+                            let wanted = SUPPORTED_CAPS.iter()
+                                .filter(|cap| **cap != SASL || args.sasl_user.is_some())
+                                .filter(|cap| offered.iter().any(|offer| offer == *cap))
+                                .copied()
+                                .collect::<Vec<&str>>();
+                            let reply = if wanted.is_empty() {
+                                String::from("CAP END\r\n")
+                            }else{
+                                format!("CAP REQ :{}\r\n", wanted.join(" "))
+                            };
+                            read_sender.send(reply).await.unwrap();
+                        },
+                        // logging in has to happen before CAP END
+                        Some("ACK") if capabilities.split_whitespace().any(|cap| cap == SASL) => {
+                            println!("capabilities ACK: {}", capabilities);
+                            read_sender.send(String::from("AUTHENTICATE PLAIN\r\n")).await.unwrap();
+                        },
+                        Some("ACK") | Some("NAK") => {
+                            println!("capabilities {}: {}", params[2], capabilities);
+                            read_sender.send(String::from("CAP END\r\n")).await.unwrap();
+                        },
+                        _ => {},
+                    }
                 },
                 _ => {},
             }
@@ -75,16 +177,20 @@ async fn main() {
                 None => break,
             };
 
-            write.write(message.as_bytes()).await;
+            // commands typed with a leading / come back without a line ending
+            let line = format!("{}\r\n", message.trim_end_matches(['\r', '\n']));
+            if write.write_all(line.as_bytes()).await.is_err() {
+                break;
+            }
         }
     });
     
-    let operator = CmdOperator::default();
-
     loop {
         //print!("=>");
         let input = CmdOperator::read_input().unwrap();
-        sender.send(input.clone()).await;
+        if sender.send(input.clone()).await.is_err() {
+            std::process::exit(-1);
+        }
         if input == "QUIT" {
             //stream.shutdown().await.unwrap();
             std::process::exit(0);
@@ -0,0 +1,95 @@
+//! IRCv3 capability negotiation, before registration the enabled capabilities are
+//! kept with the registration and afterwards on the user
+
+use tokio::io::AsyncWriteExt;
+
+use networkingirc::capability::{CapabilityRegistry, EnabledCaps, BATCH, CHATHISTORY, MESSAGE_TAGS, READ_MARKER, SASL, SERVER_TIME};
+use networkingirc::response::*;
+use networkingirc::sasl::MECHANISMS;
+use networkingirc::{format_server_time, unix_time_millis};
+
+use crate::connection::Connection;
+use crate::ServerState;
+
+/// every capability the server supports
+pub fn capability_registry() -> CapabilityRegistry {
+    let mut registry = CapabilityRegistry::new();
+    registry
+        .register(BATCH, None)
+        .register(SERVER_TIME, None)
+        .register(MESSAGE_TAGS, None)
+        .register(CHATHISTORY, None)
+        .register(READ_MARKER, None)
+        .register(SASL, Some(MECHANISMS.to_string()));
+    registry
+}
+
+/// answers CAP LS, LIST and REQ for the capabilities in `enabled`, END is left to the caller
+/// as it only matters during registration
+pub async fn negotiate(
+    state: &ServerState,
+    target: &str,
+    subcommand: &str,
+    param: &Option<String>,
+    enabled: &mut EnabledCaps,
+    stream: &mut Connection,
+) -> Result<(), std::io::Error> {
+    let mut outstring = String::new();
+    match subcommand {
+        "LS" => {
+            let version = param.as_deref().and_then(|version| version.parse::<u32>().ok()).unwrap_or(0);
+            let lines = state.capabilities.ls_lines(version >= 302);
+            for (i, line) in lines.iter().enumerate() {
+                // only 302 clients know to wait for the line without a '*'
+                let more = if version >= 302 && i + 1 < lines.len() { "* " } else { "" };
+                outstring.push_str(&format!(":{} CAP {} LS {}:{}\r\n", state.name, target, more, line));
+            }
+        },
+        "LIST" => outstring.push_str(&format!(":{} CAP {} LIST :{}\r\n", state.name, target, enabled.list())),
+        "REQ" => {
+            let request = param.as_deref().unwrap_or_default().trim();
+            match state.capabilities.request(enabled, request) {
+                Some(updated) => {
+                    *enabled = updated;
+                    outstring.push_str(&format!(":{} CAP {} ACK :{}\r\n", state.name, target, request));
+                },
+                None => outstring.push_str(&format!(":{} CAP {} NAK :{}\r\n", state.name, target, request)),
+            }
+        },
+        "END" => {},
+        _ => outstring.push_str(&numeric(&state.name, ERR_INVALIDCAPCMD, target, &format!("{} :Invalid CAP command", subcommand))),
+    }
+    stream.write_all(outstring.as_bytes()).await
+}
+
+/// CAP from a registered client
+pub async fn cap(
+    state: &ServerState,
+    id: u64,
+    subcommand: &str,
+    param: &Option<String>,
+    stream: &mut Connection,
+) -> Result<(), std::io::Error> {
+    let (nick, mut enabled) = match state.users.read().await.get(&id) {
+        Some(user) => (user.display_nick().to_string(), user.enabled_caps().clone()),
+        None => return Ok(()),
+    };
+    negotiate(state, &nick, subcommand, param, &mut enabled, stream).await?;
+    if let Some(user) = state.users.write().await.get_mut(&id) {
+        user.caps(enabled);
+    }
+    Ok(())
+}
+
+/// whether a connection has turned a capability on
+pub async fn has_cap(state: &ServerState, id: u64, name: &str) -> bool {
+    state.users.read().await.get(&id).map(|user| user.enabled_caps().has(name)).unwrap_or(false)
+}
+
+/// adds a server-time tag to every line that doesn't have tags yet
+pub fn add_server_time(lines: &str) -> String {
+    let time = format_server_time(unix_time_millis());
+    lines.split_inclusive('\n')
+        .map(|line| if line.starts_with('@') { line.to_string() } else { format!("@time={} {}", time, line) })
+        .collect()
+}
@@ -0,0 +1,447 @@
+//! joining, leaving and listing channels along with their message history, modes and topics
+
+use tokio::io::AsyncWriteExt;
+use tokio::sync::RwLock;
+
+use networkingirc::channel::ChannelMeta;
+use networkingirc::command::{ChannelMode, Mode, ModeTrait, UserMode};
+use networkingirc::config::ServerConfig;
+use networkingirc::history::HistoryStore;
+use networkingirc::network::format_uid;
+use networkingirc::response::*;
+use networkingirc::user::User;
+use std::sync::Arc;
+
+use std::collections::HashMap;
+
+use crate::chanserv::restore_access;
+use crate::chathistory::send_read_marker;
+use crate::connection::Connection;
+use crate::links::{announce_join, announce_mode, announce_topic};
+use crate::messaging::deliver;
+use crate::{find_nick, reply_target, shares_channel, ServerState};
+
+/// joins every channel in the list, creating the ones that don't exist yet
+/// returns the first channel joined, which becomes the client's active channel,
+/// and whether the client was already in it
+pub async fn join_channels(
+    state: &ServerState,
+    id: u64,
+    channellist: &[String],
+    stream: &mut Connection,
+) -> Result<Option<(String, bool)>, std::io::Error> {
+    let max_channels = state.config.read().await.limits.max_channels_per_user;
+    let (sig, account, restricted) = match state.users.read().await.get(&id) {
+        Some(user) => (user.sig(), user.account().clone(), user.has_mode(&UserMode::Restricted)),
+        None => return Ok(None),
+    };
+    let mut first = None;
+    for channel in channellist.iter().filter(|channel| !channel.is_empty()) {
+        println!("channel: {}", channel);
+        let joined = state.channels.read().await.values().filter(|meta| meta.has_user(id)).count();
+        let member = state.channels.read().await.get(channel).map(|meta| meta.has_user(id)).unwrap_or(false);
+        if !member && joined >= max_channels {
+            let target = reply_target(&state.users, id).await;
+            stream.write_all(numeric(&state.name, ERR_TOOMANYCHANNELS, &target, &format!("{} :You have joined too many channels", channel)).as_bytes()).await?;
+            continue;
+        }
+        // anyone on the access list gets past the bans
+        let banned = state.channels.read().await.get(channel)
+            .map(|meta| meta.is_banned(&sig) && account.as_deref().and_then(|account| meta.access_level(account)).is_none())
+            .unwrap_or(false);
+        if !member && banned {
+            let target = reply_target(&state.users, id).await;
+            stream.write_all(numeric(&state.name, ERR_BANNEDFROMCHAN, &target, &format!("{} :Cannot join channel (+b)", channel)).as_bytes()).await?;
+            continue;
+        }
+        let created = join_channel(&state.channels, channel, id, restricted).await;
+        if created {
+            create_message_board(channel, &state.messages).await;
+        }
+        if !member {
+            send_read_marker(state, id, channel, stream).await?;
+            restore_access(state, id, channel).await;
+            announce_join(state, id, channel).await;
+        }
+        if first.is_none() {
+            first = Some((channel.clone(), member));
+        }
+    }
+    Ok(first)
+}
+
+pub async fn leave_channels(
+    channels: &Arc<RwLock<HashMap<String, ChannelMeta>>>,
+    channellist: &[String],
+    user: u64
+) {
+    let mut write = channels.write().await;
+    for chn in channellist.iter() {
+        if let Some(room) = write.get_mut(chn) {
+            room.leave(user);
+        }
+    }
+
+}
+
+pub async fn list_all_users(
+    channels: &Arc<RwLock<HashMap<String, ChannelMeta>>>,
+    users: &Arc<RwLock<HashMap<u64, User>>>,
+    id: u64,
+    stream: &mut Connection,
+) -> Result<(), std::io::Error> {
+    let channel_read = channels.read().await;
+    let read = users.read().await;
+    let mut outstring = String::new();
+    for (uid, user) in read.iter() {
+        if *uid != id && user.has_mode(&UserMode::Invisible) && !shares_channel(&channel_read, id, *uid) {
+            continue;
+        }
+        outstring.push_str(&format!("{}\n", user.display_nick()));
+    }
+    std::mem::drop(read);
+    std::mem::drop(channel_read);
+    stream.write_all(outstring.as_bytes()).await?;
+    Ok(())
+}
+
+/// NAMES for a list of channels, a hidden channel's members are only listed to its own members
+/// and invisible users are left out for anyone outside the channel
+pub async fn list_channel_names(
+    state: &ServerState,
+    id: u64,
+    channellist: &[String],
+    stream: &mut Connection,
+) -> Result<(), std::io::Error> {
+    let target = reply_target(&state.users, id).await;
+    let channel_read = state.channels.read().await;
+    let user_read = state.users.read().await;
+    let mut outstring = String::new();
+    for name in channellist.iter() {
+        if let Some(channel) = channel_read.get(name) {
+            let member = channel.has_user(id);
+            if member || !channel.is_hidden() {
+                let names = channel.users().iter()
+                    .filter_map(|uid| user_read.get(uid).map(|user| (uid, user)))
+                    .filter(|(_, user)| member || !user.has_mode(&UserMode::Invisible))
+                    .map(|(uid, user)| {
+                        let status = if channel.is_operator(*uid) {
+                            "@"
+                        }else if channel.is_voiced(*uid) {
+                            "+"
+                        }else{
+                            ""
+                        };
+                        format!("{}{}", status, user.display_nick())
+                    })
+                    .collect::<Vec<String>>();
+                let symbol = if channel.modes().contains(&ChannelMode::SecretChannel) {
+                    "@"
+                }else if channel.modes().contains(&ChannelMode::PrivateChannel) {
+                    "*"
+                }else{
+                    "="
+                };
+                outstring.push_str(&numeric(&state.name, RPL_NAMREPLY, &target, &format!("{} {} :{}", symbol, name, names.join(" "))));
+            }
+        }
+        outstring.push_str(&numeric(&state.name, RPL_ENDOFNAMES, &target, &format!("{} :End of NAMES list", name)));
+    }
+    std::mem::drop(user_read);
+    std::mem::drop(channel_read);
+    stream.write_all(outstring.as_bytes()).await
+}
+
+pub async fn list_channels(
+    channels: &Arc<RwLock<HashMap<String, ChannelMeta>>>,
+    stream: &mut Connection
+) -> Result<(), std::io::Error> {
+    let channellist = channels.read().await;
+    let mut outstring = String::new();
+    for channel in channellist.keys() {
+        outstring.push_str(&format!("{}\n", channel));
+    }
+    std::mem::drop(channellist);
+    stream.write_all(outstring.as_bytes()).await?;
+    Ok(())
+}
+
+pub async fn list_topics(
+    channels: &Arc<RwLock<HashMap<String, ChannelMeta>>>,
+    querylist: &[String],
+    stream: &mut Connection) -> Result<(), std::io::Error>{
+        let channellist = channels.read().await;
+        let mut outstring = String::new();
+        for query in querylist.iter() {
+            if let Some(meta) = channellist.get(query) {
+                let chn = query.clone();
+                outstring.push_str(&format!("{}\n", &meta.topic().as_ref().unwrap_or(&chn)));
+            }
+        }
+        std::mem::drop(channellist);
+        stream.write_all(outstring.as_bytes()).await?;
+        Ok(())
+}
+
+pub async fn put_message(channel: &str, sender: &str, text: &str, messages: &Arc<RwLock<HistoryStore>>) {
+    messages.write().await.append(channel, sender, text);
+}
+
+/// starts the history of a channel that has none, including one kept on disk
+pub async fn create_message_board(channel: &str, messages: &Arc<RwLock<HistoryStore>>) {
+    if messages.read().await.contains(channel) {
+        return;
+    }
+    put_message(channel, "server", &format!("this is the begining of: {}", channel), messages).await;
+}
+
+/// starts the history of every configured channel that has none with its greeting
+/// and applies the retention from the config
+pub async fn load_default_channels(config: &ServerConfig, messages: &Arc<RwLock<HistoryStore>>) {
+    for channel in config.channels.iter() {
+        if messages.read().await.contains(&channel.name) {
+            continue;
+        }
+        let greeting = match &channel.greeting {
+            Some(greeting) => greeting.clone(),
+            None => format!("this is the begining of: {}", channel.name),
+        };
+        put_message(&channel.name, "server", &greeting, messages).await;
+    }
+    let mut write = messages.write().await;
+    write.set_default_retention(config.history.retention());
+    for (channel, retention) in config.channel_retention() {
+        write.set_retention(channel, retention);
+    }
+}
+
+/// this function retruns a boolean indicating wether or not a channel was created
+/// true means a channel was created, false means the channel already existed
+/// whoever joins an empty channel that isn't registered becomes its operator,
+/// unless their connection is restricted
+pub async fn join_channel(
+    channels: &Arc<RwLock<HashMap<String, ChannelMeta>>>,
+    name: &str,
+    user: u64,
+    restricted: bool,
+) -> bool {
+    let mut write_lock = channels.write().await;
+    if let Some(channel_ref) = write_lock.get_mut(name) {
+        if channel_ref.users().is_empty() && channel_ref.founder().is_none() && !restricted {
+            channel_ref.set_operator(user, true);
+        }
+        channel_ref.join(user);
+        false
+    }else{
+        let mut channel = ChannelMeta::new(name.to_string(), user);
+        channel.set_operator(user, !restricted);
+        write_lock.insert(name.to_string(), channel);
+        true
+    }
+}
+
+/// queries or changes a channel's modes, status and ban list
+/// changes are only made by channel operators and are relayed to every member
+pub async fn channel_mode(
+    state: &ServerState,
+    id: u64,
+    target: &str,
+    modes: &[Mode<ChannelMode>],
+    params: &[String],
+    stream: &mut Connection,
+) -> Result<(), std::io::Error> {
+    let (nick, sig) = match state.users.read().await.get(&id) {
+        Some(user) => (user.display_nick().to_string(), user.sig()),
+        None => return Ok(()),
+    };
+    let (flags, operator) = match state.channels.read().await.get(target) {
+        Some(channel) => (channel.modes().iter().map(|mode| mode.to_char()).collect::<String>(), channel.is_operator(id)),
+        None => return stream.write_all(numeric(&state.name, ERR_NOSUCHCHANNEL, &nick, &format!("{} :No such channel", target)).as_bytes()).await,
+    };
+    if modes.is_empty() {
+        return stream.write_all(numeric(&state.name, RPL_CHANNELMODEIS, &nick, &format!("{} +{}", target, flags)).as_bytes()).await;
+    }
+    // a lone +b is a query for the ban list
+    if params.is_empty() && modes.iter().all(|mode| *mode == Mode::Add(ChannelMode::BanSet)) {
+        return ban_list(state, &nick, target, stream).await;
+    }
+    if !operator {
+        return stream.write_all(numeric(&state.name, ERR_CHANOPRIVSNEEDED, &nick, &format!("{} :You're not channel operator", target)).as_bytes()).await;
+    }
+
+    // parameters are taken in order by the modes that need one, nicknames are resolved
+    // before the channel is locked
+    let mut params = params.iter();
+    let mut changes = Vec::new();
+    let mut outstring = String::new();
+    let user_read = state.users.read().await;
+    for mode in modes.iter() {
+        let flag = match mode {
+            Mode::Add(flag) | Mode::Sub(flag) => flag,
+        };
+        match flag {
+            ChannelMode::OperatorPrivileges | ChannelMode::VoiceToggle => {
+                let param = match params.next() {
+                    Some(param) => param,
+                    None => continue,
+                };
+                match find_nick(&user_read, param) {
+                    Some((uid, user)) => changes.push((mode.clone(), Some(*uid), Some(user.display_nick().to_string()))),
+                    None => outstring.push_str(&numeric(&state.name, ERR_NOSUCHNICK, &nick, &format!("{} :No such nick/channel", param))),
+                }
+            },
+            ChannelMode::BanSet => {
+                if let Some(mask) = params.next() {
+                    changes.push((mode.clone(), None, Some(mask.clone())));
+                }
+            },
+            ChannelMode::UserLimit | ChannelMode::ChannelPassword | ChannelMode::Unknown(_) => {
+                outstring.push_str(&numeric(&state.name, ERR_UNKNOWNMODE, &nick, &format!("{} :is unknown mode char to me for {}", flag.to_char(), target)));
+            },
+            _ => changes.push((mode.clone(), None, None)),
+        }
+    }
+    std::mem::drop(user_read);
+
+    let mut applied = Vec::new();
+    let mut applied_params = Vec::new();
+    // other servers are told which members changed by uid
+    let mut network_params = Vec::new();
+    let mut write = state.channels.write().await;
+    let channel = match write.get_mut(target) {
+        Some(channel) => channel,
+        None => return Ok(()),
+    };
+    for (mode, uid, param) in changes {
+        let changed = match (&mode, uid, param.as_deref()) {
+            (Mode::Add(ChannelMode::OperatorPrivileges), Some(uid), _) | (Mode::Sub(ChannelMode::OperatorPrivileges), Some(uid), _)
+            | (Mode::Add(ChannelMode::VoiceToggle), Some(uid), _) | (Mode::Sub(ChannelMode::VoiceToggle), Some(uid), _) if !channel.has_user(uid) => {
+                outstring.push_str(&numeric(&state.name, ERR_USERNOTINCHANNEL, &nick, &format!("{} {} :They aren't on that channel", param.as_deref().unwrap_or_default(), target)));
+                false
+            },
+            (Mode::Add(ChannelMode::OperatorPrivileges), Some(uid), _) => channel.set_operator(uid, true),
+            (Mode::Sub(ChannelMode::OperatorPrivileges), Some(uid), _) => channel.set_operator(uid, false),
+            (Mode::Add(ChannelMode::VoiceToggle), Some(uid), _) => channel.set_voiced(uid, true),
+            (Mode::Sub(ChannelMode::VoiceToggle), Some(uid), _) => channel.set_voiced(uid, false),
+            (Mode::Add(ChannelMode::BanSet), _, Some(mask)) => channel.add_ban(mask),
+            (Mode::Sub(ChannelMode::BanSet), _, Some(mask)) => channel.remove_ban(mask),
+            (Mode::Add(flag), _, _) => channel.add_mode(flag.clone()),
+            (Mode::Sub(flag), _, _) => channel.remove_mode(flag),
+        };
+        if changed {
+            network_params.extend(uid.map(format_uid).or_else(|| param.clone()));
+            applied.push(mode);
+            applied_params.extend(param);
+        }
+    }
+    let members = channel.users().iter()
+        .filter(|member| **member != id)
+        .cloned()
+        .collect::<Vec<u64>>();
+    std::mem::drop(write);
+
+    if !applied.is_empty() {
+        let mut line = format!(":{} MODE {} {}", sig, target, Mode::format_list(&applied));
+        for param in applied_params.iter() {
+            line.push(' ');
+            line.push_str(param);
+        }
+        line.push_str("\r\n");
+        deliver(&state.connections, &members, &line).await;
+        announce_mode(state, id, target, &Mode::format_list(&applied), &network_params).await;
+        outstring.push_str(&line);
+    }
+    stream.write_all(outstring.as_bytes()).await
+}
+
+async fn ban_list(
+    state: &ServerState,
+    nick: &str,
+    target: &str,
+    stream: &mut Connection,
+) -> Result<(), std::io::Error> {
+    let bans = match state.channels.read().await.get(target) {
+        Some(channel) => channel.bans().clone(),
+        None => return Ok(()),
+    };
+    let mut outstring = bans.iter()
+        .map(|ban| numeric(&state.name, RPL_BANLIST, nick, &format!("{} {}", target, ban)))
+        .collect::<String>();
+    outstring.push_str(&numeric(&state.name, RPL_ENDOFBANLIST, nick, &format!("{} :End of channel ban list", target)));
+    stream.write_all(outstring.as_bytes()).await
+}
+
+/// queries or changes a channel's topic, only operators can change it while the channel is +t
+pub async fn topic(
+    state: &ServerState,
+    id: u64,
+    target: &str,
+    text: &Option<String>,
+    stream: &mut Connection,
+) -> Result<(), std::io::Error> {
+    let (nick, sig) = match state.users.read().await.get(&id) {
+        Some(user) => (user.display_nick().to_string(), user.sig()),
+        None => return Ok(()),
+    };
+    let mut write = state.channels.write().await;
+    let channel = match write.get_mut(target) {
+        Some(channel) => channel,
+        None => {
+            std::mem::drop(write);
+            return stream.write_all(numeric(&state.name, ERR_NOSUCHCHANNEL, &nick, &format!("{} :No such channel", target)).as_bytes()).await;
+        },
+    };
+    let reply = match text {
+        None => match channel.topic() {
+            Some(current) => numeric(&state.name, RPL_TOPIC, &nick, &format!("{} :{}", target, current)),
+            None => numeric(&state.name, RPL_NOTOPIC, &nick, &format!("{} :No topic is set", target)),
+        },
+        Some(_) if !channel.has_user(id) => numeric(&state.name, ERR_NOTONCHANNEL, &nick, &format!("{} :You're not on that channel", target)),
+        Some(_) if channel.modes().contains(&ChannelMode::TopicSettability) && !channel.is_operator(id) => {
+            numeric(&state.name, ERR_CHANOPRIVSNEEDED, &nick, &format!("{} :You're not channel operator", target))
+        },
+        // an empty topic clears it
+        Some(text) => {
+            channel.set_topic(Some(text.clone()).filter(|text| !text.is_empty()));
+            let members = channel.users().iter()
+                .filter(|member| **member != id)
+                .cloned()
+                .collect::<Vec<u64>>();
+            let line = format!(":{} TOPIC {} :{}\r\n", sig, target, text);
+            std::mem::drop(write);
+            deliver(&state.connections, &members, &line).await;
+            announce_topic(state, id, target, text).await;
+            return stream.write_all(line.as_bytes()).await;
+        },
+    };
+    std::mem::drop(write);
+    stream.write_all(reply.as_bytes()).await
+}
+
+/// sends the recent messages of a room to a client joining it,
+/// leaving out those the user has marked as read
+pub async fn display_messages(
+    state: &ServerState,
+    id: u64,
+    channel: &str,
+    stream: &mut Connection
+) -> Result<(), std::io::Error> {
+    let (lines, max_age) = {
+        let config = state.config.read().await;
+        (config.history.replay_lines(), config.history.replay_max_age())
+    };
+    let account = match state.users.read().await.get(&id) {
+        Some(user) => user.account().clone(),
+        None => return Ok(()),
+    };
+    let read_marker = match account {
+        Some(account) => state.read_markers.read().await.get(&account, channel),
+        None => None,
+    };
+    let read = state.messages.read().await;
+    let outstring = read.replay(channel, lines, max_age, read_marker).iter()
+        .map(|entry| format!("{}\n", entry.display()))
+        .collect::<String>();
+    std::mem::drop(read);
+    stream.write_all(outstring.as_bytes()).await
+}
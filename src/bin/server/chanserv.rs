@@ -0,0 +1,247 @@
+//! the channel service, registers channels to an account so their topic, modes and bans
+//! are kept and the accounts on their access list get their status back whenever they join
+
+use tokio::io::AsyncWriteExt;
+
+use networkingirc::channel::AccessLevel;
+use networkingirc::command::UserMode;
+
+use crate::connection::Connection;
+use crate::messaging::deliver;
+use crate::services::{service_notice, service_prefix, CHANSERV};
+use crate::snapshots::save_snapshot;
+use crate::ServerState;
+
+/// each command's syntax is the part before the " - "
+const HELP: [&str; 7] = [
+    "REGISTER <#channel> - registers a channel you are an operator in to your account",
+    "ACCESS <#channel> LIST - shows the accounts given a status in the channel",
+    "ACCESS <#channel> ADD <account> <op|voice> - gives an account a status whenever it joins",
+    "ACCESS <#channel> DEL <account> - takes an account off the access list",
+    "INFO <#channel> - shows who a channel is registered to",
+    "DROP <#channel> - drops the registration of a channel you founded",
+    "HELP - shows this list",
+];
+
+/// the user a command came from
+struct Caller {
+    id: u64,
+    nick: String,
+    account: Option<String>,
+}
+
+pub async fn chanserv(
+    state: &ServerState,
+    id: u64,
+    text: &str,
+    stream: &mut Connection,
+) -> Result<(), std::io::Error> {
+    let caller = match state.users.read().await.get(&id) {
+        Some(user) => Caller {
+            id,
+            nick: user.display_nick().to_string(),
+            account: user.account().clone(),
+        },
+        None => return Ok(()),
+    };
+    let mut words = text.split_whitespace();
+    let command = words.next().unwrap_or_default().to_ascii_uppercase();
+    let args = words.collect::<Vec<&str>>();
+    let notice = |text: &str| service_notice(state, CHANSERV, &caller.nick, text);
+
+    let outstring = match (command.as_str(), args.as_slice()) {
+        ("REGISTER", [channel]) => register(state, &caller, channel).await,
+        ("ACCESS", [channel, list]) if list.eq_ignore_ascii_case("LIST") => access_list(state, &caller, channel).await,
+        ("ACCESS", [channel, add, account, level]) if add.eq_ignore_ascii_case("ADD") => {
+            match parse_level(level) {
+                Some(level) => access_add(state, &caller, channel, account, level).await,
+                None => notice("The level has to be op or voice"),
+            }
+        },
+        ("ACCESS", [channel, del, account]) if del.eq_ignore_ascii_case("DEL") => access_del(state, &caller, channel, account).await,
+        ("INFO", [channel]) => info(state, &caller, channel).await,
+        ("DROP", [channel]) => drop_channel(state, &caller, channel).await,
+        ("HELP", _) | ("", _) => HELP.iter().map(|line| notice(line)).collect(),
+        _ => {
+            let syntax = HELP.iter()
+                .filter(|line| line.split(' ').next() == Some(command.as_str()))
+                .map(|line| notice(&format!("Syntax: {}", line.split(" - ").next().unwrap_or_default())))
+                .collect::<String>();
+            if syntax.is_empty() {
+                notice(&format!("Unknown command {}, see HELP", command))
+            }else{
+                syntax
+            }
+        },
+    };
+    stream.write_all(outstring.as_bytes()).await
+}
+
+fn parse_level(level: &str) -> Option<AccessLevel> {
+    match level.to_ascii_lowercase().as_str() {
+        "op" => Some(AccessLevel::Op),
+        "voice" => Some(AccessLevel::Voice),
+        _ => None,
+    }
+}
+
+async fn register(state: &ServerState, caller: &Caller, name: &str) -> String {
+    let notice = |text: &str| service_notice(state, CHANSERV, &caller.nick, text);
+    let account = match &caller.account {
+        Some(account) => account,
+        None => return notice("You need to IDENTIFY with NickServ before you can register a channel"),
+    };
+    let mut write = state.channels.write().await;
+    let channel = match write.get_mut(name) {
+        Some(channel) => channel,
+        None => return notice(&format!("{} doesn't exist", name)),
+    };
+    if let Some(founder) = channel.founder() {
+        return notice(&format!("{} is already registered to {}", name, founder));
+    }
+    if !channel.is_operator(caller.id) {
+        return notice(&format!("You need to be an operator in {} to register it", name));
+    }
+    channel.register(Some(account.clone()));
+    std::mem::drop(write);
+    save_snapshot(state).await;
+    println!("ChanServ: {} registered {}", account, name);
+    notice(&format!("{} is now registered to {}", name, account))
+}
+
+/// the founder and accounts with op access can change the access list
+async fn can_manage(state: &ServerState, caller: &Caller, name: &str) -> Result<(), String> {
+    let notice = |text: &str| service_notice(state, CHANSERV, &caller.nick, text);
+    let read = state.channels.read().await;
+    let channel = match read.get(name) {
+        Some(channel) if channel.founder().is_some() => channel,
+        _ => return Err(notice(&format!("{} isn't registered", name))),
+    };
+    match caller.account.as_deref().and_then(|account| channel.access_level(account)) {
+        Some(AccessLevel::Op) => Ok(()),
+        _ => Err(notice(&format!("You don't have access to change {}", name))),
+    }
+}
+
+async fn access_list(state: &ServerState, caller: &Caller, name: &str) -> String {
+    let notice = |text: &str| service_notice(state, CHANSERV, &caller.nick, text);
+    let read = state.channels.read().await;
+    let channel = match read.get(name) {
+        Some(channel) if channel.founder().is_some() => channel,
+        _ => return notice(&format!("{} isn't registered", name)),
+    };
+    let mut outstring = notice(&format!("Access list for {}:", name));
+    if let Some(founder) = channel.founder() {
+        outstring.push_str(&notice(&format!("  {} founder", founder)));
+    }
+    for entry in channel.access().iter() {
+        let level = match entry.level {
+            AccessLevel::Op => "op",
+            AccessLevel::Voice => "voice",
+        };
+        outstring.push_str(&notice(&format!("  {} {}", entry.account, level)));
+    }
+    outstring.push_str(&notice("End of access list"));
+    outstring
+}
+
+async fn access_add(state: &ServerState, caller: &Caller, name: &str, account: &str, level: AccessLevel) -> String {
+    let notice = |text: &str| service_notice(state, CHANSERV, &caller.nick, text);
+    if let Err(denied) = can_manage(state, caller, name).await {
+        return denied;
+    }
+    let account = match state.accounts.read().await.get(account) {
+        Some(found) => found.name.clone(),
+        None => return notice(&format!("{} isn't a registered account", account)),
+    };
+    match state.channels.write().await.get_mut(name) {
+        Some(channel) if channel.founder().as_deref().map(|founder| founder.eq_ignore_ascii_case(&account)).unwrap_or(false) => {
+            return notice(&format!("{} is the founder of {}", account, name));
+        },
+        Some(channel) => channel.set_access(&account, level),
+        None => return notice(&format!("{} isn't registered", name)),
+    }
+    save_snapshot(state).await;
+    notice(&format!("{} has been added to the access list of {}", account, name))
+}
+
+async fn access_del(state: &ServerState, caller: &Caller, name: &str, account: &str) -> String {
+    let notice = |text: &str| service_notice(state, CHANSERV, &caller.nick, text);
+    if let Err(denied) = can_manage(state, caller, name).await {
+        return denied;
+    }
+    let removed = match state.channels.write().await.get_mut(name) {
+        Some(channel) => channel.remove_access(account),
+        None => false,
+    };
+    if !removed {
+        return notice(&format!("{} isn't on the access list of {}", account, name));
+    }
+    save_snapshot(state).await;
+    notice(&format!("{} has been removed from the access list of {}", account, name))
+}
+
+async fn info(state: &ServerState, caller: &Caller, name: &str) -> String {
+    let notice = |text: &str| service_notice(state, CHANSERV, &caller.nick, text);
+    match state.channels.read().await.get(name) {
+        Some(channel) => match channel.founder() {
+            Some(founder) => notice(&format!("{} is registered to {} with {} accounts on its access list", name, founder, channel.access().len())),
+            None => notice(&format!("{} isn't registered", name)),
+        },
+        None => notice(&format!("{} doesn't exist", name)),
+    }
+}
+
+async fn drop_channel(state: &ServerState, caller: &Caller, name: &str) -> String {
+    let notice = |text: &str| service_notice(state, CHANSERV, &caller.nick, text);
+    let mut write = state.channels.write().await;
+    let channel = match write.get_mut(name) {
+        Some(channel) if channel.founder().is_some() => channel,
+        _ => return notice(&format!("{} isn't registered", name)),
+    };
+    let founder = channel.founder().as_deref().map(|founder| founder.to_string()).unwrap_or_default();
+    if !caller.account.as_deref().map(|account| account.eq_ignore_ascii_case(&founder)).unwrap_or(false) {
+        return notice(&format!("Only the founder of {} can drop it", name));
+    }
+    channel.register(None);
+    std::mem::drop(write);
+    save_snapshot(state).await;
+    println!("ChanServ: {} dropped {}", founder, name);
+    notice(&format!("{} has been dropped", name))
+}
+
+/// gives a user who just joined a registered channel the status their account has on its access list
+pub async fn restore_access(state: &ServerState, id: u64, name: &str) {
+    let (nick, account, restricted) = match state.users.read().await.get(&id) {
+        Some(user) => match user.account() {
+            Some(account) => (user.display_nick().to_string(), account.clone(), user.has_mode(&UserMode::Restricted)),
+            None => return,
+        },
+        None => return,
+    };
+    let mut write = state.channels.write().await;
+    let channel = match write.get_mut(name) {
+        Some(channel) => channel,
+        None => return,
+    };
+    let changed = match channel.access_level(&account) {
+        // a restricted connection can't be made an operator, even by the access list
+        Some(AccessLevel::Op) if !restricted && channel.set_operator(id, true) => 'o',
+        Some(AccessLevel::Voice) if channel.set_voiced(id, true) => 'v',
+        _ => return,
+    };
+    let members = channel.users().iter().cloned().collect::<Vec<u64>>();
+    std::mem::drop(write);
+    let line = format!(":{} MODE {} +{} {}\r\n", service_prefix(state, CHANSERV), name, changed, nick);
+    deliver(&state.connections, &members, &line).await;
+}
+
+/// forgets a dropped account, the channels it founded lose their registration
+pub async fn forget_account(state: &ServerState, account: &str) {
+    for channel in state.channels.write().await.values_mut() {
+        if channel.founder().as_deref().map(|founder| founder.eq_ignore_ascii_case(account)).unwrap_or(false) {
+            channel.register(None);
+        }
+        channel.remove_access(account);
+    }
+}
@@ -0,0 +1,187 @@
+//! the IRCv3 draft/chathistory and draft/read-marker extensions, which let clients
+//! page through channel history and keep track of what they have read
+
+use tokio::io::AsyncWriteExt;
+
+use networkingirc::capability::{EnabledCaps, BATCH, MESSAGE_TAGS, READ_MARKER, SERVER_TIME};
+use networkingirc::history::{HistoryEntry, HistoryQuery};
+use networkingirc::{format_server_time, parse_server_time};
+
+use crate::connection::Connection;
+use crate::messaging::deliver;
+use crate::{reply_target, ServerState};
+
+/// a reference for a BATCH that won't clash with another batch sent on the same connection
+fn batch_reference() -> String {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|duration| duration.as_nanos())
+        .unwrap_or(0);
+    format!("{:x}", nanos)
+}
+
+/// a history entry as the message it was sent as, tagged with its batch, time and msgid
+/// for the clients that turned on batch, server-time and message-tags
+fn history_line(server: &str, caps: &EnabledCaps, batch: &str, channel: &str, entry: &HistoryEntry) -> String {
+    let mut tags = Vec::new();
+    if caps.has(BATCH) {
+        tags.push(format!("batch={}", batch));
+    }
+    if caps.has(SERVER_TIME) {
+        tags.push(format!("time={}", format_server_time(entry.time)));
+    }
+    if caps.has(MESSAGE_TAGS) {
+        tags.push(format!("msgid={}", entry.msgid(channel)));
+    }
+    let tags = if tags.is_empty() { String::new() } else { format!("@{} ", tags.join(";")) };
+    // greetings and channel notices are stored as coming from "server"
+    if entry.sender == "server" {
+        format!("{}:{} NOTICE {} :{}\r\n", tags, server, channel, entry.text)
+    }else{
+        format!("{}:{} PRIVMSG {} :{}\r\n", tags, entry.sender, channel, entry.text)
+    }
+}
+
+/// the opening and closing BATCH lines, left out for clients without the batch capability
+fn batch_lines(server: &str, caps: &EnabledCaps, batch: &str, kind: &str) -> (String, String) {
+    if !caps.has(BATCH) {
+        return (String::new(), String::new());
+    }
+    (format!(":{} BATCH +{} {}\r\n", server, batch, kind), format!(":{} BATCH -{}\r\n", server, batch))
+}
+
+pub async fn chathistory(
+    state: &ServerState,
+    id: u64,
+    args: &[String],
+    stream: &mut Connection,
+) -> Result<(), std::io::Error> {
+    let max_limit = state.config.read().await.history.query_limit;
+    let query = match HistoryQuery::parse(args, max_limit) {
+        Ok(query) => query,
+        Err(e) => return stream.write_all(format!(":{} FAIL CHATHISTORY {}\r\n", state.name, e).as_bytes()).await,
+    };
+    let batch = batch_reference();
+    let caps = match state.users.read().await.get(&id) {
+        Some(user) => user.enabled_caps().clone(),
+        None => return Ok(()),
+    };
+
+    let channel = match &query {
+        HistoryQuery::Targets(from, to, limit) => {
+            let channels = state.channels.read().await;
+            let read = state.messages.read().await;
+            let (start, end) = batch_lines(&state.name, &caps, &batch, "draft/chathistory-targets");
+            let mut outstring = start;
+            // only the channels the client is in are listed
+            let targets = read.targets(*from, *to).into_iter()
+                .filter(|(channel, _)| channels.get(*channel).map(|meta| meta.has_user(id)).unwrap_or(false))
+                .take(*limit);
+            for (channel, latest) in targets {
+                outstring.push_str(&format!(":{} CHATHISTORY TARGETS {} timestamp={}\r\n", state.name, channel, format_server_time(latest)));
+            }
+            outstring.push_str(&end);
+            std::mem::drop(read);
+            std::mem::drop(channels);
+            return stream.write_all(outstring.as_bytes()).await;
+        },
+        HistoryQuery::Latest(channel, ..)
+        | HistoryQuery::Before(channel, ..)
+        | HistoryQuery::After(channel, ..)
+        | HistoryQuery::Around(channel, ..)
+        | HistoryQuery::Between(channel, ..) => channel.clone(),
+    };
+
+    // the history of secret and private channels is kept to their members, a channel
+    // without metadata can't be told apart from a hidden one so its history isn't given out
+    let allowed = match state.channels.read().await.get(&channel) {
+        Some(meta) => !meta.is_hidden() || meta.has_user(id),
+        None => false,
+    };
+    if !allowed {
+        let nick = reply_target(&state.users, id).await;
+        println!("{} was refused the history of {}", nick, channel);
+        let line = format!(":{} FAIL CHATHISTORY INVALID_TARGET {} {} :Messages could not be retrieved\r\n", state.name, args[0], channel);
+        return stream.write_all(line.as_bytes()).await;
+    }
+
+    let read = state.messages.read().await;
+    let (start, end) = batch_lines(&state.name, &caps, &batch, &format!("chathistory {}", channel));
+    let mut outstring = start;
+    for entry in read.query(&query) {
+        outstring.push_str(&history_line(&state.name, &caps, &batch, &channel, entry));
+    }
+    std::mem::drop(read);
+    outstring.push_str(&end);
+    stream.write_all(outstring.as_bytes()).await
+}
+
+fn marker_line(server: &str, target: &str, marker: Option<u64>) -> String {
+    let marker = match marker {
+        Some(time) => format!("timestamp={}", format_server_time(time)),
+        None => String::from("*"),
+    };
+    format!(":{} MARKREAD {} {}\r\n", server, target, marker)
+}
+
+/// tells a client with draft/read-marker where it has read a channel up to, sent on JOIN
+pub async fn send_read_marker(
+    state: &ServerState,
+    id: u64,
+    channel: &str,
+    stream: &mut Connection,
+) -> Result<(), std::io::Error> {
+    let account = match state.users.read().await.get(&id) {
+        Some(user) if user.enabled_caps().has(READ_MARKER) => user.account().clone(),
+        _ => return Ok(()),
+    };
+    // markers are kept per account, a user who isn't logged in never has one
+    let marker = match account {
+        Some(account) => state.read_markers.read().await.get(&account, channel),
+        None => None,
+    };
+    stream.write_all(marker_line(&state.name, channel, marker).as_bytes()).await
+}
+
+/// gets or moves forward the account's read marker for a channel, every connection
+/// logged in to the account with draft/read-marker is told where the marker ends up
+/// nothing is stored for a user who isn't logged in
+pub async fn markread(
+    state: &ServerState,
+    id: u64,
+    target: &str,
+    timestamp: &Option<String>,
+    stream: &mut Connection,
+) -> Result<(), std::io::Error> {
+    let account = match state.users.read().await.get(&id) {
+        Some(user) => user.account().clone(),
+        None => return Ok(()),
+    };
+    let account = match account {
+        Some(account) => account,
+        None => return stream.write_all(marker_line(&state.name, target, None).as_bytes()).await,
+    };
+    let marker = match timestamp {
+        None => state.read_markers.read().await.get(&account, target),
+        Some(timestamp) => {
+            let time = match timestamp.strip_prefix("timestamp=").and_then(parse_server_time) {
+                Some(time) => time,
+                None => {
+                    let line = format!(":{} FAIL MARKREAD INVALID_PARAMS {} :Invalid timestamp\r\n", state.name, timestamp);
+                    return stream.write_all(line.as_bytes()).await;
+                },
+            };
+            Some(state.read_markers.write().await.set(&account, target, time))
+        },
+    };
+    let line = marker_line(&state.name, target, marker);
+
+    if timestamp.is_some() {
+        let sessions = state.users.read().await.iter()
+            .filter(|(uid, user)| **uid != id && user.account().as_deref() == Some(account.as_str()) && user.enabled_caps().has(READ_MARKER))
+            .map(|(uid, _)| *uid)
+            .collect::<Vec<u64>>();
+        deliver(&state.connections, &sessions, &line).await;
+    }
+    stream.write_all(line.as_bytes()).await
+}
@@ -0,0 +1,230 @@
+//! the sockets clients connect through and the stream of each client,
+//! counting the traffic that passes through it
+
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+use tokio_rustls::server::TlsStream;
+
+use networkingirc::config::ListenAddress;
+use networkingirc::stats::LinkStats;
+use networkingirc::tls::fingerprint;
+
+use crate::websocket::WebSocket;
+
+pub enum Stream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+    /// TLS over either of the others
+    Tls(Box<TlsStream<Stream>>),
+    /// IRC over WebSocket over any of the others
+    WebSocket(Box<WebSocket>),
+}
+
+/// where a client connected from
+#[derive(Debug, Clone)]
+pub enum Peer {
+    Tcp(SocketAddr),
+    /// a local process on a unix socket
+    Unix,
+}
+
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+pub struct Connection {
+    stream: Stream,
+    stats: Arc<LinkStats>,
+    /// wrong passwords sent for accounts and oper blocks
+    login_failures: u32,
+}
+
+impl Stream {
+    pub fn is_secure(&self) -> bool {
+        match self {
+            Stream::Tls(_) => true,
+            Stream::WebSocket(stream) => stream.get_ref().is_secure(),
+            _ => false,
+        }
+    }
+
+    /// the fingerprint of the certificate the client presented during the TLS handshake
+    pub fn client_fingerprint(&self) -> Option<String> {
+        match self {
+            Stream::Tls(stream) => stream.get_ref().1.peer_certificates()
+                .and_then(|certificates| certificates.first())
+                .map(|certificate| fingerprint(certificate)),
+            Stream::WebSocket(stream) => stream.get_ref().client_fingerprint(),
+            _ => None,
+        }
+    }
+}
+
+impl Peer {
+    /// clients on unix sockets are on the same machine, so they count as localhost
+    pub fn ip(&self) -> String {
+        match self {
+            Peer::Tcp(address) => address.ip().to_string(),
+            Peer::Unix => String::from("127.0.0.1"),
+        }
+    }
+}
+
+impl std::fmt::Display for Peer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Peer::Tcp(address) => write!(f, "{}", address),
+            Peer::Unix => write!(f, "unix socket"),
+        }
+    }
+}
+
+impl Listener {
+    pub async fn bind(endpoint: &ListenAddress) -> io::Result<Self> {
+        match endpoint {
+            ListenAddress::Tcp(address) => Ok(Listener::Tcp(TcpListener::bind(address).await?)),
+            #[cfg(unix)]
+            ListenAddress::Unix(path) => {
+                use std::os::unix::fs::FileTypeExt;
+                // a socket left behind by a server that didn't shut down cleanly
+                if let Ok(metadata) = std::fs::symlink_metadata(path) {
+                    if metadata.file_type().is_socket() {
+                        std::fs::remove_file(path)?;
+                    }
+                }
+                Ok(Listener::Unix(UnixListener::bind(path)?))
+            },
+            #[cfg(not(unix))]
+            ListenAddress::Unix(_) => Err(io::Error::new(io::ErrorKind::Unsupported, "unix sockets aren't supported on this platform")),
+        }
+    }
+
+    pub async fn accept(&self) -> io::Result<(Stream, Peer)> {
+        match self {
+            Listener::Tcp(listener) => {
+                let (stream, address) = listener.accept().await?;
+                Ok((Stream::Tcp(stream), Peer::Tcp(address)))
+            },
+            #[cfg(unix)]
+            Listener::Unix(listener) => {
+                let (stream, _) = listener.accept().await?;
+                Ok((Stream::Unix(stream), Peer::Unix))
+            },
+        }
+    }
+}
+
+impl Connection {
+    pub fn new(stream: Stream) -> Self {
+        Self {
+            stream,
+            stats: Arc::new(LinkStats::new()),
+            login_failures: 0,
+        }
+    }
+
+    /// counts a wrong password sent on this connection, returning how many there have been
+    pub fn failed_login(&mut self) -> u32 {
+        self.login_failures += 1;
+        self.login_failures
+    }
+
+    pub fn is_secure(&self) -> bool {
+        self.stream.is_secure()
+    }
+
+    pub fn client_fingerprint(&self) -> Option<String> {
+        self.stream.client_fingerprint()
+    }
+
+    /// the counters for this connection, shared with STATS
+    pub fn stats(&self) -> Arc<LinkStats> {
+        self.stats.clone()
+    }
+}
+
+impl AsyncRead for Stream {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Tcp(stream) => Pin::new(stream).poll_read(cx, buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => Pin::new(stream).poll_read(cx, buf),
+            Stream::Tls(stream) => Pin::new(stream).poll_read(cx, buf),
+            Stream::WebSocket(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Stream {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Stream::Tcp(stream) => Pin::new(stream).poll_write(cx, buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => Pin::new(stream).poll_write(cx, buf),
+            Stream::Tls(stream) => Pin::new(stream).poll_write(cx, buf),
+            Stream::WebSocket(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Tcp(stream) => Pin::new(stream).poll_flush(cx),
+            #[cfg(unix)]
+            Stream::Unix(stream) => Pin::new(stream).poll_flush(cx),
+            Stream::Tls(stream) => Pin::new(stream).poll_flush(cx),
+            Stream::WebSocket(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Tcp(stream) => Pin::new(stream).poll_shutdown(cx),
+            #[cfg(unix)]
+            Stream::Unix(stream) => Pin::new(stream).poll_shutdown(cx),
+            Stream::Tls(stream) => Pin::new(stream).poll_shutdown(cx),
+            Stream::WebSocket(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
+
+impl AsyncRead for Connection {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let before = buf.filled().len();
+        let poll = Pin::new(&mut this.stream).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = poll {
+            this.stats.record_received(&buf.filled()[before..]);
+        }
+        poll
+    }
+}
+
+impl AsyncWrite for Connection {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let poll = Pin::new(&mut this.stream).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = poll {
+            this.stats.record_sent(&buf[..written]);
+        }
+        poll
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_shutdown(cx)
+    }
+}
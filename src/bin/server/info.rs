@@ -0,0 +1,271 @@
+//! queries about the server itself: the welcome, MOTD, LUSERS, VERSION, STATS, TIME, ADMIN and INFO
+
+use tokio::io::AsyncWriteExt;
+
+use networkingirc::command::UserMode;
+use networkingirc::response::*;
+use networkingirc::stats::format_uptime;
+use networkingirc::{format_time, unix_time, wildcard_match};
+
+use crate::connection::Connection;
+use crate::{reply_target, ServerState};
+
+/// replies with ERR_NOSUCHSERVER and returns false when the query names a server other than this one
+async fn is_local_target(
+    state: &ServerState,
+    nick: &str,
+    target: &Option<String>,
+    stream: &mut Connection,
+) -> Result<bool, std::io::Error> {
+    match target {
+        Some(target) if !wildcard_match(target, &state.name) => {
+            stream.write_all(numeric(&state.name, ERR_NOSUCHSERVER, nick, &format!("{} :No such server", target)).as_bytes()).await?;
+            Ok(false)
+        },
+        _ => Ok(true),
+    }
+}
+
+/// RPL_WELCOME and RPL_ISUPPORT, sent once a client has registered
+pub async fn send_welcome(
+    state: &ServerState,
+    id: u64,
+    stream: &mut Connection,
+) -> Result<(), std::io::Error> {
+    let (nick, sig) = match state.users.read().await.get(&id) {
+        Some(user) => (user.display_nick().to_string(), user.sig()),
+        None => return Ok(()),
+    };
+    let config = state.config.read().await;
+    let mut outstring = numeric(&state.name, RPL_WELCOME, &nick, &format!(":Welcome to the {} IRC Network {}", config.server.network, sig));
+    let tokens = format!(
+        "NETWORK={} NICKLEN={} CHANLIMIT=#:{} PREFIX=(ov)@+",
+        config.server.network, config.limits.nick_length, config.limits.max_channels_per_user
+    );
+    std::mem::drop(config);
+    outstring.push_str(&numeric(&state.name, RPL_ISUPPORT, &nick, &format!("{} :are supported by this server", tokens)));
+    stream.write_all(outstring.as_bytes()).await
+}
+
+/// sends the message of the day, the file is read on every request so it can be edited while running
+pub async fn motd(
+    state: &ServerState,
+    id: u64,
+    target: &Option<String>,
+    stream: &mut Connection,
+) -> Result<(), std::io::Error> {
+    let nick = reply_target(&state.users, id).await;
+    if !is_local_target(state, &nick, target, stream).await? {
+        return Ok(());
+    }
+    let path = state.config.read().await.server.motd.clone();
+    let contents = match path {
+        Some(path) => tokio::fs::read_to_string(&path).await.ok(),
+        None => None,
+    };
+    let contents = match contents {
+        Some(contents) => contents,
+        None => return stream.write_all(numeric(&state.name, ERR_NOMOTD, &nick, ":MOTD File is missing").as_bytes()).await,
+    };
+
+    let mut outstring = numeric(&state.name, RPL_MOTDSTART, &nick, &format!(":- {} Message of the day - ", state.name));
+    for line in contents.lines() {
+        outstring.push_str(&numeric(&state.name, RPL_MOTD, &nick, &format!(":- {}", line.trim_end())));
+    }
+    outstring.push_str(&numeric(&state.name, RPL_ENDOFMOTD, &nick, ":End of MOTD command"));
+    stream.write_all(outstring.as_bytes()).await
+}
+
+/// counts of users, operators and channels across the network, the mask is ignored
+pub async fn lusers(
+    state: &ServerState,
+    id: u64,
+    target: &Option<String>,
+    stream: &mut Connection,
+) -> Result<(), std::io::Error> {
+    let nick = reply_target(&state.users, id).await;
+    if !is_local_target(state, &nick, target, stream).await? {
+        return Ok(());
+    }
+    let (servers, links, remote) = {
+        let network = state.network.read().await;
+        (network.len() + 1, network.links().len(), network.remote_users())
+    };
+    let read = state.users.read().await;
+    let total = read.len();
+    let invisible = read.values().filter(|user| user.has_mode(&UserMode::Invisible)).count();
+    let operators = read.values().filter(|user| user.has_mode(&UserMode::Operator)).count();
+    std::mem::drop(read);
+    let channels = state.channels.read().await.len();
+
+    let mut outstring = numeric(&state.name, RPL_LUSERCLIENT, &nick,
+        &format!(":There are {} users and {} invisible on {} servers", total - invisible, invisible, servers));
+    if operators > 0 {
+        outstring.push_str(&numeric(&state.name, RPL_LUSEROP, &nick, &format!("{} :operator(s) online", operators)));
+    }
+    if channels > 0 {
+        outstring.push_str(&numeric(&state.name, RPL_LUSERCHANNELS, &nick, &format!("{} :channels formed", channels)));
+    }
+    outstring.push_str(&numeric(&state.name, RPL_LUSERME, &nick, &format!(":I have {} clients and {} servers", total - remote, links)));
+    stream.write_all(outstring.as_bytes()).await
+}
+
+pub async fn version(
+    state: &ServerState,
+    id: u64,
+    target: &Option<String>,
+    stream: &mut Connection,
+) -> Result<(), std::io::Error> {
+    let nick = reply_target(&state.users, id).await;
+    if !is_local_target(state, &nick, target, stream).await? {
+        return Ok(());
+    }
+    let debug = if cfg!(debug_assertions) { "debug" } else { "" };
+    let params = format!(
+        "{}-{}.{} {} :{} {}",
+        env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"), debug,
+        state.name, std::env::consts::OS, std::env::consts::ARCH
+    );
+    stream.write_all(numeric(&state.name, RPL_VERSION, &nick, &params).as_bytes()).await
+}
+
+/// answers a single STATS letter, anything that shows other users' addresses
+/// or the server's config is kept to operators
+pub async fn stats(
+    state: &ServerState,
+    id: u64,
+    query: &Option<String>,
+    target: &Option<String>,
+    stream: &mut Connection,
+) -> Result<(), std::io::Error> {
+    let nick = reply_target(&state.users, id).await;
+    if !is_local_target(state, &nick, target, stream).await? {
+        return Ok(());
+    }
+    let query = match query.as_deref().and_then(|query| query.chars().next()) {
+        Some(query) => query,
+        None => return stream.write_all(numeric(&state.name, ERR_NEEDMOREPARAMS, &nick, "STATS :Not enough parameters").as_bytes()).await,
+    };
+    let is_oper = state.users.read().await.get(&id).map(|user| user.has_mode(&UserMode::Operator)).unwrap_or(false);
+
+    let mut outstring = String::new();
+    match query {
+        'l' | 'o' | 'k' if !is_oper => {
+            return stream.write_all(numeric(&state.name, ERR_NOPRIVILEGES, &nick, ":Permission Denied- You're not an IRC operator").as_bytes()).await;
+        },
+        'l' => {
+            let links = state.links.read().await.iter()
+                .map(|(uid, link)| (*uid, link.clone()))
+                .collect::<Vec<_>>();
+            let users = state.users.read().await;
+            let connections = state.connections.read().await;
+            for (uid, link) in links {
+                let name = match users.get(&uid) {
+                    Some(user) => format!("{}[{}]", user.display_nick(), user.ip_address()),
+                    None => continue,
+                };
+                // lines other tasks have queued that the session hasn't written yet
+                let sendq = connections.get(&uid).map(|sender| sender.max_capacity() - sender.capacity()).unwrap_or(0);
+                outstring.push_str(&numeric(&state.name, RPL_STATSLINKINFO, &nick, &format!(
+                    "{} {} {} {} {} {} {}",
+                    name, sendq,
+                    link.sent_messages(), link.sent_bytes() / 1024,
+                    link.received_messages(), link.received_bytes() / 1024,
+                    link.open_for(),
+                )));
+            }
+        },
+        'm' => {
+            let read = state.command_stats.read().await;
+            let mut commands = read.iter().collect::<Vec<_>>();
+            commands.sort_by(|a, b| a.0.cmp(b.0));
+            for (command, usage) in commands {
+                outstring.push_str(&numeric(&state.name, RPL_STATSCOMMANDS, &nick, &format!("{} {} {} {}", command, usage.count, usage.bytes, usage.remote)));
+            }
+        },
+        'u' => {
+            let uptime = unix_time().saturating_sub(state.started);
+            outstring.push_str(&numeric(&state.name, RPL_STATSUPTIME, &nick, &format!(":Server Up {}", format_uptime(uptime))));
+        },
+        'o' => {
+            let config = state.config.read().await;
+            for oper in config.opers.iter() {
+                let any_host = [String::from("*@*")];
+                let hosts = if oper.hosts.is_empty() { &any_host[..] } else { &oper.hosts[..] };
+                for host in hosts.iter() {
+                    outstring.push_str(&numeric(&state.name, RPL_STATSOLINE, &nick, &format!("O {} * {}", host, oper.name)));
+                }
+            }
+        },
+        'k' => {
+            let config = state.config.read().await;
+            for ban in config.bans.iter() {
+                let (username, host) = ban.mask.split_once('@').unwrap_or(("*", ban.mask.as_str()));
+                outstring.push_str(&numeric(&state.name, RPL_STATSKLINE, &nick, &format!("K {} * {} :{}", host, username, ban.reason)));
+            }
+        },
+        _ => {},
+    }
+    outstring.push_str(&numeric(&state.name, RPL_ENDOFSTATS, &nick, &format!("{} :End of STATS report", query)));
+    stream.write_all(outstring.as_bytes()).await
+}
+
+pub async fn time(
+    state: &ServerState,
+    id: u64,
+    target: &Option<String>,
+    stream: &mut Connection,
+) -> Result<(), std::io::Error> {
+    let nick = reply_target(&state.users, id).await;
+    if !is_local_target(state, &nick, target, stream).await? {
+        return Ok(());
+    }
+    let params = format!("{} :{}", state.name, format_time(unix_time()));
+    stream.write_all(numeric(&state.name, RPL_TIME, &nick, &params).as_bytes()).await
+}
+
+pub async fn admin(
+    state: &ServerState,
+    id: u64,
+    target: &Option<String>,
+    stream: &mut Connection,
+) -> Result<(), std::io::Error> {
+    let nick = reply_target(&state.users, id).await;
+    if !is_local_target(state, &nick, target, stream).await? {
+        return Ok(());
+    }
+    let admin = state.config.read().await.admin.clone();
+    if admin.location.is_empty() && admin.organization.is_empty() && admin.email.is_empty() {
+        return stream.write_all(numeric(&state.name, ERR_NOADMININFO, &nick, &format!("{} :No administrative info available", state.name)).as_bytes()).await;
+    }
+    let mut outstring = numeric(&state.name, RPL_ADMINME, &nick, &format!("{} :Administrative info", state.name));
+    outstring.push_str(&numeric(&state.name, RPL_ADMINLOC1, &nick, &format!(":{}", admin.location)));
+    outstring.push_str(&numeric(&state.name, RPL_ADMINLOC2, &nick, &format!(":{}", admin.organization)));
+    outstring.push_str(&numeric(&state.name, RPL_ADMINEMAIL, &nick, &format!(":{}", admin.email)));
+    stream.write_all(outstring.as_bytes()).await
+}
+
+pub async fn info(
+    state: &ServerState,
+    id: u64,
+    target: &Option<String>,
+    stream: &mut Connection,
+) -> Result<(), std::io::Error> {
+    let nick = reply_target(&state.users, id).await;
+    if !is_local_target(state, &nick, target, stream).await? {
+        return Ok(());
+    }
+    let description = state.config.read().await.server.description.clone();
+    let lines = [
+        format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
+        String::from(env!("CARGO_PKG_DESCRIPTION")),
+        format!("This server: {}", description),
+        format!("On-line since {}", format_time(state.started)),
+    ];
+    let mut outstring = String::new();
+    for line in lines.iter() {
+        outstring.push_str(&numeric(&state.name, RPL_INFO, &nick, &format!(":{}", line)));
+    }
+    outstring.push_str(&numeric(&state.name, RPL_ENDOFINFO, &nick, ":End of INFO list"));
+    stream.write_all(outstring.as_bytes()).await
+}
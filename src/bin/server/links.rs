@@ -0,0 +1,1039 @@
+//! links to other servers, forming a network where every server is reached through one
+//! direct link, a new link starts with a burst of every server, user and channel each side
+//! knows about and afterwards carries the changes made on either side
+//! users are named by their uid on links, see networkingirc::network
+
+use std::collections::HashMap;
+
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::task::{self, JoinHandle};
+use tokio::time::{sleep, sleep_until, timeout, timeout_at, Duration, Instant};
+
+use networkingirc::channel::ChannelMeta;
+use networkingirc::command::{ChannelMode, Command, Mode, ModeTrait, UserMode};
+use networkingirc::config::LinkBlock;
+use networkingirc::message::Message;
+use networkingirc::network::{format_uid, parse_uid, LinkedServer, RemoteUser};
+use networkingirc::response::*;
+use networkingirc::user::User;
+use networkingirc::{wildcard_match, IrcError, LineBuffer};
+
+use crate::channels::{create_message_board, leave_channels, put_message};
+use crate::connection::{Connection, Peer, Stream};
+use crate::messaging::{close_connection, deliver, quit_user, server_notice};
+use crate::nickserv::guest_nick;
+use crate::operators::require_oper;
+use crate::users::force_nick;
+use crate::{find_nick, new_id, reply_target, Outgoing, ServerState};
+
+/// how long to wait before trying a link marked autoconnect again
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// sends a line to every directly linked server except the one it came from
+pub async fn propagate(state: &ServerState, except: Option<u64>, line: &str) {
+    let links = state.network.read().await.links().iter()
+        .map(|server| server.link)
+        .filter(|link| Some(*link) != except)
+        .collect::<Vec<u64>>();
+    deliver(&state.connections, &links, line).await;
+}
+
+/// the UID parameters of a user, hops is how far their server is from this one
+fn remote_user(user: &User, uid: u64, hops: u32) -> RemoteUser {
+    RemoteUser {
+        nick: user.display_nick().to_string(),
+        hops,
+        signon: user.signon(),
+        modes: user.mode_string(),
+        username: user.username().to_string(),
+        hostname: user.hostname().to_string(),
+        ip: user.ip_address().to_string(),
+        uid,
+        account: user.account().clone(),
+        realname: user.realname().to_string(),
+    }
+}
+
+/// the flags of a channel that take no parameter, such as "+nt"
+fn channel_flags(channel: &ChannelMeta) -> String {
+    let mut flags = String::from("+");
+    flags.extend(channel.modes().iter().map(|mode| mode.to_char()));
+    flags
+}
+
+/// a member's uid as it appears in SJOIN, prefixed with their status
+fn sjoin_member(channel: &ChannelMeta, uid: u64) -> String {
+    let status = if channel.is_operator(uid) {
+        "@"
+    }else if channel.is_voiced(uid) {
+        "+"
+    }else{
+        ""
+    };
+    format!("{}{}", status, format_uid(uid))
+}
+
+/// introduces a local user to the rest of the network once they have their nickname
+pub async fn announce_user(state: &ServerState, id: u64) {
+    let line = match state.users.read().await.get(&id) {
+        Some(user) => remote_user(user, id, 0).to_line(&state.name),
+        None => return,
+    };
+    state.network.write().await.announce(id);
+    propagate(state, None, &line).await;
+}
+
+/// the lines below are only sent for local users the network already knows about
+async fn announced(state: &ServerState, id: u64) -> bool {
+    state.network.read().await.is_announced(id)
+}
+
+pub async fn announce_nick(state: &ServerState, id: u64, nick: &str) {
+    if announced(state, id).await {
+        propagate(state, None, &format!(":{} NICK {}\r\n", format_uid(id), nick)).await;
+    }
+}
+
+/// tells the network a local user joined a channel, along with their status in it
+pub async fn announce_join(state: &ServerState, id: u64, name: &str) {
+    if !announced(state, id).await {
+        return;
+    }
+    let line = match state.channels.read().await.get(name) {
+        Some(channel) => format!(":{} SJOIN {} {} {} :{}\r\n", state.name, channel.created(), name, channel_flags(channel), sjoin_member(channel, id)),
+        None => return,
+    };
+    propagate(state, None, &line).await;
+}
+
+/// a local user's own mode change, the away mode is left to announce_away
+pub async fn announce_user_mode(state: &ServerState, id: u64, modes: &str) {
+    if announced(state, id).await {
+        propagate(state, None, &format!(":{} MODE {} :{}\r\n", format_uid(id), format_uid(id), modes)).await;
+    }
+}
+
+pub async fn announce_away(state: &ServerState, id: u64, away: &Option<String>) {
+    if !announced(state, id).await {
+        return;
+    }
+    let line = match away {
+        Some(message) => format!(":{} AWAY :{}\r\n", format_uid(id), message),
+        None => format!(":{} AWAY\r\n", format_uid(id)),
+    };
+    propagate(state, None, &line).await;
+}
+
+pub async fn announce_part(state: &ServerState, id: u64, channels: &[String]) {
+    if announced(state, id).await && !channels.is_empty() {
+        propagate(state, None, &format!(":{} PART {}\r\n", format_uid(id), channels.join(","))).await;
+    }
+}
+
+/// the QUIT is only sent once however many times the user is quit
+pub async fn announce_quit(state: &ServerState, id: u64, reason: &str) {
+    if state.network.write().await.withdraw(id) {
+        propagate(state, None, &format!(":{} QUIT :{}\r\n", format_uid(id), reason)).await;
+    }
+}
+
+/// relays a PRIVMSG or NOTICE from a local user, a channel message reaches every server
+/// while a message to a user only goes towards the server they are on
+pub async fn relay_message(state: &ServerState, id: u64, kind: &str, target: &str, text: &str) {
+    if !announced(state, id).await {
+        return;
+    }
+    let line = format!(":{} {} {} :{}\r\n", format_uid(id), kind, target, text);
+    if state.channels.read().await.contains_key(target) {
+        return propagate(state, None, &line).await;
+    }
+    let recipient = find_nick(&*state.users.read().await, target).map(|(uid, _)| *uid);
+    if let Some(uid) = recipient {
+        let link = state.network.read().await.server_of(uid).map(|server| server.link);
+        if let Some(link) = link {
+            deliver(&state.connections, &[link], &line).await;
+        }
+    }
+}
+
+pub async fn announce_topic(state: &ServerState, id: u64, name: &str, text: &str) {
+    if announced(state, id).await {
+        propagate(state, None, &format!(":{} TOPIC {} :{}\r\n", format_uid(id), name, text)).await;
+    }
+}
+
+/// the parameters of o and v are uids rather than nicknames
+pub async fn announce_mode(state: &ServerState, id: u64, name: &str, modes: &str, params: &[String]) {
+    if !announced(state, id).await {
+        return;
+    }
+    let mut line = format!(":{} MODE {} {}", format_uid(id), name, modes);
+    for param in params.iter() {
+        line.push(' ');
+        line.push_str(param);
+    }
+    line.push_str("\r\n");
+    propagate(state, None, &line).await;
+}
+
+/// a user on another server killed by a local operator, their server closes the connection
+pub async fn kill_remote(state: &ServerState, id: u64, victim: u64, comment: &str, reason: &str) {
+    remove_remote_user(state, victim, reason).await;
+    let source = if announced(state, id).await { format_uid(id) } else { state.name.clone() };
+    propagate(state, None, &format!(":{} KILL {} :{}\r\n", source, format_uid(victim), comment)).await;
+}
+
+/// forgets a user on another server once they have quit, been killed or lost in a netsplit
+async fn remove_remote_user(state: &ServerState, uid: u64, reason: &str) {
+    quit_user(state, uid, reason).await;
+    state.network.write().await.remove_user(uid);
+    let mut write = state.users.write().await;
+    if let Some(user) = write.remove(&uid) {
+        state.whowas.write().await.record(&user, user.server());
+    }
+    std::mem::drop(write);
+}
+
+/// when two users end up with the same nickname the one who signed on later loses it
+/// every server only renames its own users, the others hear about it through NICK
+async fn resolve_collision(state: &ServerState, nick: &str) {
+    let mut holders = state.users.read().await.iter()
+        .filter(|(_, user)| user.display_nick().eq_ignore_ascii_case(nick))
+        .map(|(uid, user)| (user.signon(), *uid))
+        .collect::<Vec<(u64, u64)>>();
+    if holders.len() < 2 {
+        return;
+    }
+    holders.sort();
+    let network = state.network.read().await;
+    let losers = holders.into_iter()
+        .skip(1)
+        .map(|(_, uid)| uid)
+        .filter(|uid| !network.is_remote(*uid))
+        .collect::<Vec<u64>>();
+    std::mem::drop(network);
+    for uid in losers {
+        let guest = guest_nick(state, uid).await;
+        println!("nick collision on {}, renaming local user to {}", nick, guest);
+        force_nick(state, uid, &guest).await;
+    }
+}
+
+/// drops a server along with every server behind it, their users quit with the names
+/// of the two servers that lost each other as is usual for a netsplit
+async fn split_server(state: &ServerState, name: &str, except: Option<u64>, reason: &str) {
+    let uplink = match state.network.read().await.get(name) {
+        Some(server) => server.uplink.clone(),
+        None => return,
+    };
+    let (servers, users) = state.network.write().await.split(name);
+    let quit = format!("{} {}", uplink, name);
+    for uid in users.iter() {
+        remove_remote_user(state, *uid, &quit).await;
+    }
+    propagate(state, except, &format!(":{} SQUIT {} :{}\r\n", state.name, name, reason)).await;
+    println!("netsplit from {}: {} lost {} servers and {} users", name, reason, servers.len(), users.len());
+    server_notice(state, &format!("Netsplit {} <-> {} ({}), {} servers and {} users lost", uplink, name, reason, servers.len(), users.len())).await;
+}
+
+/// PASS and SERVER, both ends send them, the connecting one first
+async fn handshake(state: &ServerState, block: &LinkBlock) -> String {
+    let description = state.config.read().await.server.description.clone();
+    format!("PASS {}\r\nSERVER {} 1 :{}\r\n", block.send_password, state.name, description)
+}
+
+async fn refuse_link(stream: &mut Connection, name: &str, reason: &str) -> Result<(), IrcError> {
+    stream.write_all(format!("ERROR :Closing Link: {} ({})\r\n", name, reason).as_bytes()).await?;
+    stream.shutdown().await?;
+    Err(IrcError::LinkRefused(reason.to_string()))
+}
+
+/// a server that connected to one of the listeners and sent PASS and SERVER,
+/// `lines` holds whatever it sent after SERVER in the same read, such as the start of its burst
+pub async fn accept_link(
+    state: ServerState,
+    mut stream: Connection,
+    lines: LineBuffer,
+    addr: Peer,
+    name: String,
+    description: String,
+    password: Option<String>,
+) -> Result<(), IrcError> {
+    let block = state.config.read().await.find_link(&name).cloned();
+    let block = match (block, password) {
+        (Some(block), Some(password)) if block.verify(&password).await => Some(block),
+        _ => None,
+    };
+    let block = match block {
+        Some(block) => block,
+        None => {
+            println!("refused link from {} claiming to be {}: no link block or wrong password", addr, name);
+            return refuse_link(&mut stream, &name, "Bad Password").await;
+        },
+    };
+    stream.write_all(handshake(&state, &block).await.as_bytes()).await?;
+    run_link(state, stream, addr, block.name, description, lines).await
+}
+
+/// connects to the server of a link block and runs the link until it is lost
+pub async fn connect_link(state: ServerState, block: LinkBlock, address: String) -> Result<(), IrcError> {
+    let registration = Duration::from_secs(state.config.read().await.timeouts.registration);
+    let deadline = Instant::now() + registration;
+    let socket = match timeout(registration, TcpStream::connect(&address)).await {
+        Ok(socket) => socket?,
+        Err(_) => return Err(IrcError::RegistrationTimeout),
+    };
+    let addr = Peer::Tcp(socket.peer_addr()?);
+    let mut stream = Connection::new(Stream::Tcp(socket));
+    stream.write_all(handshake(&state, &block).await.as_bytes()).await?;
+
+    // the other server answers with its own PASS and SERVER before its burst
+    let mut buffer = LineBuffer::default();
+    let mut password = None;
+    let (name, description) = loop {
+        let line = match timeout_at(deadline, buffer.next_line(&mut stream)).await {
+            Ok(line) => line?,
+            Err(_) => return Err(IrcError::RegistrationTimeout),
+        };
+        let line = match line {
+            Some(line) => line,
+            None => return Err(IrcError::LinkRefused(String::from("connection closed"))),
+        };
+        let message = Message::parse(line).map_err(IrcError::CommandParse)?;
+        match message.command() {
+            Command::PASS(pass) => password = Some(pass.clone()),
+            Command::SERVER(name, _, description) => break (name.clone(), description.clone()),
+            Command::ERROR(reason) => return Err(IrcError::LinkRefused(reason.clone())),
+            _ => {},
+        }
+    };
+    let verified = match password {
+        Some(password) if name.eq_ignore_ascii_case(&block.name) => block.verify(&password).await,
+        _ => false,
+    };
+    if !verified {
+        println!("refused link to {} on {}: it answered as {} or with the wrong password", block.name, address, name);
+        return refuse_link(&mut stream, &name, "Bad Password").await;
+    }
+    run_link(state, stream, addr, block.name, description, buffer).await
+}
+
+/// adds a server that finished the handshake to the network, runs the link and splits
+/// the network when it ends
+async fn run_link(
+    state: ServerState,
+    mut stream: Connection,
+    addr: Peer,
+    name: String,
+    description: String,
+    mut buffer: LineBuffer,
+) -> Result<(), IrcError> {
+    let id = new_id(&state);
+    let (sender, mut receiver): (Sender<Outgoing>, Receiver<Outgoing>) = channel(1000);
+    state.connections.write().await.insert(id, sender);
+    let server = LinkedServer {
+        name: name.clone(),
+        hops: 1,
+        uplink: state.name.clone(),
+        description: description.clone(),
+        link: id,
+    };
+    if name.eq_ignore_ascii_case(&state.name) || !state.network.write().await.add(server) {
+        state.connections.write().await.remove(&id);
+        println!("refused link from {}: {} is already in the network", addr, name);
+        return refuse_link(&mut stream, &name, "Server exists").await;
+    }
+    state.links.write().await.insert(id, stream.stats());
+    println!("linked with server {} on {} with id: {}", name, addr, id);
+    propagate(&state, Some(id), &format!(":{} SERVER {} 1 :{}\r\n", state.name, name, description)).await;
+
+    let result = link_session(&state, &mut stream, &mut receiver, &mut buffer, id, &name).await;
+
+    let reason = match &result {
+        Ok(()) => String::from("Connection closed"),
+        Err(IrcError::PingTimeout) => String::from("Ping timeout"),
+        Err(IrcError::SendQExceeded) => String::from("SendQ exceeded"),
+        Err(IrcError::Closed(reason)) => reason.clone(),
+        Err(e) => e.to_string(),
+    };
+    split_server(&state, &name, None, &reason).await;
+    state.connections.write().await.remove(&id);
+    state.links.write().await.remove(&id);
+    result
+}
+
+/// everything the other end of a new link needs to know, servers nearest first so each
+/// one is introduced after its uplink, then the users and the channels they are in
+/// anything reached through the new link is already known on the other side
+async fn burst(state: &ServerState, link: u64) -> String {
+    let network = state.network.read().await;
+    let mut outstring = String::new();
+    for server in network.servers().into_iter().filter(|server| server.link != link) {
+        outstring.push_str(&format!(":{} SERVER {} {} :{}\r\n", server.uplink, server.name, server.hops, server.description));
+    }
+    let known = |uid: u64| match network.server_of(uid) {
+        Some(server) => server.link != link,
+        None => network.is_announced(uid),
+    };
+    let users = state.users.read().await;
+    for (uid, user) in users.iter().filter(|(uid, _)| known(**uid)) {
+        let line = match network.server_of(*uid) {
+            Some(server) => remote_user(user, *uid, server.hops).to_line(&server.name),
+            None => remote_user(user, *uid, 0).to_line(&state.name),
+        };
+        outstring.push_str(&line);
+        if let Some(away) = user.away_message() {
+            outstring.push_str(&format!(":{} AWAY :{}\r\n", format_uid(*uid), away));
+        }
+    }
+    std::mem::drop(users);
+    for (name, channel) in state.channels.read().await.iter() {
+        let members = channel.users().iter()
+            .filter(|uid| known(**uid))
+            .map(|uid| sjoin_member(channel, *uid))
+            .collect::<Vec<String>>();
+        if members.is_empty() {
+            continue;
+        }
+        outstring.push_str(&format!(":{} SJOIN {} {} {} :{}\r\n", state.name, channel.created(), name, channel_flags(channel), members.join(" ")));
+        if let Some(topic) = channel.topic() {
+            outstring.push_str(&format!(":{} TOPIC {} :{}\r\n", state.name, name, topic));
+        }
+        for ban in channel.bans().iter() {
+            outstring.push_str(&format!(":{} MODE {} +b {}\r\n", state.name, name, ban));
+        }
+    }
+    outstring.push_str(&format!(":{} EOB\r\n", state.name));
+    outstring
+}
+
+async fn link_session(
+    state: &ServerState,
+    stream: &mut Connection,
+    receiver: &mut Receiver<Outgoing>,
+    buffer: &mut LineBuffer,
+    id: u64,
+    peer: &str,
+) -> Result<(), IrcError> {
+    stream.write_all(burst(state, id).await.as_bytes()).await?;
+    let (ping_interval, ping_timeout) = {
+        let config = state.config.read().await;
+        (Duration::from_secs(config.timeouts.ping_interval), Duration::from_secs(config.timeouts.ping_timeout))
+    };
+    let mut last_seen = Instant::now();
+    let mut ping_sent: Option<Instant> = None;
+
+    loop {
+        let deadline = match ping_sent {
+            Some(sent) => sent + ping_timeout,
+            None => last_seen + ping_interval,
+        };
+        let line = tokio::select! {
+            outgoing = receiver.recv() => {
+                match outgoing {
+                    Some(Outgoing::Line(line)) => stream.write_all(line.as_bytes()).await?,
+                    Some(Outgoing::Close(line)) => {
+                        stream.write_all(line.as_bytes()).await?;
+                        stream.shutdown().await?;
+                        return Err(IrcError::Closed(line.lines().last().unwrap_or_default().to_string()));
+                    },
+                    // the queue is only closed under a session when it fell too far behind
+                    None => {
+                        stream.write_all(format!("ERROR :Closing Link: {} (SendQ exceeded)\r\n", state.name).as_bytes()).await?;
+                        stream.shutdown().await?;
+                        return Err(IrcError::SendQExceeded);
+                    },
+                }
+                continue;
+            },
+            _ = sleep_until(deadline) => {
+                if ping_sent.is_some() {
+                    stream.write_all(b"ERROR :Closing Link: Ping timeout\r\n").await?;
+                    stream.shutdown().await?;
+                    return Err(IrcError::PingTimeout);
+                }
+                stream.write_all(format!("PING :{}\r\n", state.name).as_bytes()).await?;
+                ping_sent = Some(Instant::now());
+                continue;
+            },
+            incoming = buffer.next_line(stream) => incoming?,
+        };
+        let line = match line {
+            Some(line) => line,
+            None => {
+                println!("server {} has disconnected", peer);
+                return Ok(());
+            },
+        };
+        last_seen = Instant::now();
+        ping_sent = None;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let message = match Message::parse(line.clone()) {
+            Ok(message) => message,
+            Err(e) => {
+                println!("couldn't parse message from server {}: {}", peer, e);
+                continue;
+            },
+        };
+        state.command_stats.write().await
+            .entry(message.command_name())
+            .or_default()
+            .record_remote();
+        match message.command() {
+            Command::PING(token, _) => {
+                stream.write_all(format!(":{} PONG {} :{}\r\n", state.name, state.name, token).as_bytes()).await?;
+            },
+            Command::ERROR(reason) => return Err(IrcError::Closed(reason.clone())),
+            _ => {
+                if let Some(reason) = server_message(state, id, peer, &line, &message).await {
+                    stream.write_all(format!("ERROR :Closing Link: {} ({})\r\n", peer, reason).as_bytes()).await?;
+                    stream.shutdown().await?;
+                    return Err(IrcError::Closed(reason));
+                }
+            },
+        }
+    }
+}
+
+/// applies a message from a linked server and passes the line on to the other links as it
+/// was received, returns a reason when the link should be closed
+async fn server_message(state: &ServerState, link: u64, peer: &str, line: &str, message: &Message) -> Option<String> {
+    let source = message.prefix().unwrap_or(peer).to_string();
+    // the local users sharing a channel see a user on another server by their nick!user@host
+    let sender = match parse_uid(&source) {
+        Some(uid) => match state.users.read().await.get(&uid) {
+            Some(user) => Some((uid, user.display_nick().to_string(), user.sig())),
+            None => return None,
+        },
+        None => None,
+    };
+    if let Some((uid, _, _)) = &sender {
+        if !state.network.read().await.reached_through(*uid, link) {
+            return Some(format!("{} isn't reached through {}", source, peer));
+        }
+    }
+    let sig = sender.as_ref().map(|(_, _, sig)| sig.clone()).unwrap_or_else(|| source.clone());
+    let forward = format!("{}\r\n", line);
+
+    match message.command() {
+        Command::SERVER(name, hops, description) => {
+            let hops = hops.parse::<u32>().unwrap_or(0) + 1;
+            let server = LinkedServer { name: name.clone(), hops, uplink: source.clone(), description: description.clone(), link };
+            if name.eq_ignore_ascii_case(&state.name) || !state.network.write().await.add(server) {
+                return Some(format!("{} is already in the network", name));
+            }
+            server_notice(state, &format!("Server {} joined the network through {}", name, source)).await;
+            propagate(state, Some(link), &format!(":{} SERVER {} {} :{}\r\n", source, name, hops, description)).await;
+        },
+        Command::UID(params) => {
+            let server = match state.network.read().await.get(&source) {
+                Some(server) => server.name.clone(),
+                None => return None,
+            };
+            let remote = RemoteUser::parse(params)?;
+            if state.users.read().await.contains_key(&remote.uid) {
+                return None;
+            }
+            let mut user = User::new(remote.username.clone(), remote.hostname.clone(), remote.realname.clone(), server.clone());
+            user.nick(remote.nick.clone()).ip(remote.ip.clone()).login(remote.account.clone()).signed_on(remote.signon)
+                .cloak(&state.config.read().await.server.cloak_key);
+            // the away mode is set by the AWAY that carries the message
+            for mode in Mode::<UserMode>::parse_list(&remote.modes) {
+                if let Mode::Add(flag) = mode {
+                    if flag != UserMode::Away {
+                        user.add_mode(flag);
+                    }
+                }
+            }
+            state.users.write().await.insert(remote.uid, user);
+            state.network.write().await.add_user(remote.uid, &server);
+            let line = RemoteUser { hops: remote.hops + 1, ..remote.clone() }.to_line(&server);
+            propagate(state, Some(link), &line).await;
+            resolve_collision(state, &remote.nick).await;
+        },
+        Command::NICK(nick) => {
+            let (uid, _, old_sig) = sender?;
+            let mut write = state.users.write().await;
+            if let Some(user) = write.get_mut(&uid) {
+                state.whowas.write().await.record(user, user.server());
+                user.nick(nick.clone());
+            }
+            std::mem::drop(write);
+            let channels = state.channels.read().await;
+            let mut recipients = channels.values()
+                .filter(|channel| channel.has_user(uid))
+                .flat_map(|channel| channel.users().iter().cloned())
+                .filter(|member| *member != uid)
+                .collect::<Vec<u64>>();
+            std::mem::drop(channels);
+            recipients.sort();
+            recipients.dedup();
+            deliver(&state.connections, &recipients, &format!(":{} NICK :{}\r\n", old_sig, nick)).await;
+            propagate(state, Some(link), &forward).await;
+            resolve_collision(state, nick).await;
+        },
+        Command::UserMode(target, modes) => {
+            let (uid, _, _) = sender?;
+            if parse_uid(target) != Some(uid) {
+                return None;
+            }
+            if let Some(user) = state.users.write().await.get_mut(&uid) {
+                for mode in modes.iter() {
+                    match mode {
+                        Mode::Add(UserMode::Away) | Mode::Sub(UserMode::Away) => {},
+                        Mode::Add(UserMode::Unknown(_)) | Mode::Sub(UserMode::Unknown(_)) => {},
+                        Mode::Add(flag) => {
+                            user.add_mode(flag.clone());
+                        },
+                        Mode::Sub(flag) => {
+                            user.remove_mode(flag);
+                        },
+                    }
+                }
+            }
+            propagate(state, Some(link), &forward).await;
+        },
+        Command::AWAY(away) => {
+            let (uid, _, _) = sender?;
+            if let Some(user) = state.users.write().await.get_mut(&uid) {
+                user.away(away.clone());
+            }
+            propagate(state, Some(link), &forward).await;
+        },
+        Command::SJOIN(ts, name, flags, members) => {
+            let ts = ts.parse::<u64>().ok()?;
+            let known = state.users.read().await;
+            let members = members.split(' ')
+                .filter_map(|member| match member.strip_prefix('@') {
+                    Some(uid) => Some((parse_uid(uid)?, true, false)),
+                    None => match member.strip_prefix('+') {
+                        Some(uid) => Some((parse_uid(uid)?, false, true)),
+                        None => Some((parse_uid(member)?, false, false)),
+                    },
+                })
+                .filter_map(|(uid, operator, voiced)| known.get(&uid).map(|user| (uid, user.display_nick().to_string(), user.sig(), operator, voiced)))
+                .collect::<Vec<(u64, String, String, bool, bool)>>();
+            std::mem::drop(known);
+            let first = members.first()?.0;
+            let mut write = state.channels.write().await;
+            let created = !write.contains_key(name);
+            let channel = write.entry(name.clone()).or_insert_with(|| {
+                // the members are joined below along with everyone else so their JOIN is shown
+                let mut channel = ChannelMeta::new(name.clone(), first);
+                channel.leave(first);
+                channel.set_created(ts);
+                channel
+            });
+            // the side with the newer channel loses its status and modes
+            let (accepted, dropped) = match channel.settle_ts(ts) {
+                Some(dropped) => (true, dropped),
+                None => (false, Vec::new()),
+            };
+            if accepted {
+                for mode in Mode::<ChannelMode>::parse_list(flags) {
+                    match mode {
+                        Mode::Add(ChannelMode::OperatorPrivileges) | Mode::Add(ChannelMode::VoiceToggle) | Mode::Add(ChannelMode::BanSet) => {},
+                        Mode::Add(flag) => {
+                            channel.add_mode(flag);
+                        },
+                        Mode::Sub(_) => {},
+                    }
+                }
+            }
+            let mut lines = Vec::new();
+            for (uid, nick, sig, operator, voiced) in members.iter() {
+                if !channel.has_user(*uid) {
+                    channel.join(*uid);
+                    lines.push((*uid, format!(":{} JOIN {}\r\n", sig, name)));
+                }
+                if accepted && *operator && channel.set_operator(*uid, true) {
+                    lines.push((*uid, format!(":{} MODE {} +o {}\r\n", state.name, name, nick)));
+                }
+                if accepted && *voiced && channel.set_voiced(*uid, true) {
+                    lines.push((*uid, format!(":{} MODE {} +v {}\r\n", state.name, name, nick)));
+                }
+            }
+            let users = state.users.read().await;
+            let taken = dropped.iter()
+                .filter_map(|(uid, mode)| users.get(uid).map(|user| format!(":{} MODE {} -{} {}\r\n", state.name, name, mode, user.display_nick())))
+                .collect::<String>();
+            std::mem::drop(users);
+            let recipients = channel.users().iter().cloned().collect::<Vec<u64>>();
+            std::mem::drop(write);
+            if created {
+                create_message_board(name, &state.messages).await;
+            }
+            if !taken.is_empty() {
+                deliver(&state.connections, &recipients, &taken).await;
+            }
+            for (uid, line) in lines {
+                let others = recipients.iter().filter(|member| **member != uid).cloned().collect::<Vec<u64>>();
+                deliver(&state.connections, &others, &line).await;
+            }
+            propagate(state, Some(link), &forward).await;
+        },
+        Command::PART(channellist, _) => {
+            let (uid, _, sig) = sender?;
+            let members = state.channels.read().await.iter()
+                .filter(|(name, channel)| channellist.contains(name) && channel.has_user(uid))
+                .map(|(name, channel)| (name.clone(), channel.users().iter().filter(|member| **member != uid).cloned().collect::<Vec<u64>>()))
+                .collect::<Vec<(String, Vec<u64>)>>();
+            for (name, recipients) in members {
+                deliver(&state.connections, &recipients, &format!(":{} PART {}\r\n", sig, name)).await;
+            }
+            leave_channels(&state.channels, channellist, uid).await;
+            propagate(state, Some(link), &forward).await;
+        },
+        Command::QUIT(reason) => {
+            let (uid, _, _) = sender?;
+            remove_remote_user(state, uid, reason.as_deref().unwrap_or("")).await;
+            propagate(state, Some(link), &forward).await;
+        },
+        Command::PRIVMSG(target, text) | Command::NOTICE(target, text) => {
+            let (uid, nick, sig) = sender?;
+            let line = format!(":{} {} {} :{}\r\n", sig, message.command_name(), target, text);
+            let members = state.channels.read().await.get(target)
+                .map(|channel| channel.users().iter().filter(|member| **member != uid).cloned().collect::<Vec<u64>>());
+            match members {
+                Some(members) => {
+                    deliver(&state.connections, &members, &line).await;
+                    put_message(target, &nick, text, &state.messages).await;
+                    propagate(state, Some(link), &forward).await;
+                },
+                None => {
+                    let recipient = find_nick(&*state.users.read().await, target).map(|(uid, _)| *uid);
+                    let recipient = recipient?;
+                    let route = state.network.read().await.server_of(recipient).map(|server| server.link);
+                    match route {
+                        Some(route) if route != link => deliver(&state.connections, &[route], &forward).await,
+                        Some(_) => {},
+                        None => deliver(&state.connections, &[recipient], &line).await,
+                    }
+                },
+            }
+        },
+        Command::TOPIC(name, Some(text)) => {
+            let mut write = state.channels.write().await;
+            let channel = write.get_mut(name)?;
+            // a topic in a burst doesn't replace one already set on this side
+            if sender.is_none() && channel.topic().is_some() {
+                return None;
+            }
+            channel.set_topic(Some(text.clone()).filter(|text| !text.is_empty()));
+            let members = channel.users().iter().cloned().collect::<Vec<u64>>();
+            std::mem::drop(write);
+            deliver(&state.connections, &members, &format!(":{} TOPIC {} :{}\r\n", sig, name, text)).await;
+            propagate(state, Some(link), &forward).await;
+        },
+        Command::ChannelMode(name, modes, params) => {
+            remote_channel_mode(state, &sig, name, modes, params).await;
+            propagate(state, Some(link), &forward).await;
+        },
+        Command::KILL(target, comment) => {
+            let victim = parse_uid(target)?;
+            let killer = sender.as_ref().map(|(_, nick, _)| nick.clone()).unwrap_or_else(|| source.clone());
+            let reason = format!("Killed ({} ({}))", killer, comment);
+            if state.network.read().await.is_remote(victim) {
+                remove_remote_user(state, victim, &reason).await;
+            }else{
+                let nick = match state.users.read().await.get(&victim) {
+                    Some(user) => user.display_nick().to_string(),
+                    None => return None,
+                };
+                // the rest of the network has already forgotten the user
+                state.network.write().await.withdraw(victim);
+                quit_user(state, victim, &reason).await;
+                let lines = format!(":{} KILL {} :{}\r\nERROR :Closing Link: {} ({})\r\n", sig, nick, comment, state.name, reason);
+                close_connection(&state.connections, victim, &lines).await;
+                server_notice(state, &format!("Received KILL message for {} from {} ({})", nick, killer, comment)).await;
+            }
+            propagate(state, Some(link), &forward).await;
+        },
+        Command::SQUIT(name, reason) => {
+            if name.eq_ignore_ascii_case(&state.name) || name.eq_ignore_ascii_case(peer) {
+                return Some(reason.clone());
+            }
+            let route = state.network.read().await.get(name).map(|server| (server.link, server.hops));
+            match route {
+                // coming from the side of the server, it was lost over there
+                Some((route, _)) if route == link => split_server(state, name, Some(link), reason).await,
+                // otherwise an operator elsewhere asked for it to be dropped
+                Some((route, 1)) => close_connection(&state.connections, route, &format!("ERROR :Closing Link: {} ({})\r\n", state.name, reason)).await,
+                Some((route, _)) => deliver(&state.connections, &[route], &forward).await,
+                None => {},
+            }
+        },
+        Command::EOB => {
+            println!("end of burst from server {}", source);
+            if source.eq_ignore_ascii_case(peer) {
+                server_notice(state, &format!("Link with {} established", peer)).await;
+            }
+        },
+        _ => {},
+    }
+    None
+}
+
+/// a mode change made on another server, o and v name members by uid
+async fn remote_channel_mode(state: &ServerState, sig: &str, name: &str, modes: &[Mode<ChannelMode>], params: &[String]) {
+    let users = state.users.read().await;
+    let mut params = params.iter();
+    let mut write = state.channels.write().await;
+    let channel = match write.get_mut(name) {
+        Some(channel) => channel,
+        None => return,
+    };
+    let mut applied = Vec::new();
+    let mut applied_params = Vec::new();
+    for mode in modes.iter() {
+        let changed = match mode {
+            Mode::Add(ChannelMode::OperatorPrivileges) | Mode::Sub(ChannelMode::OperatorPrivileges)
+            | Mode::Add(ChannelMode::VoiceToggle) | Mode::Sub(ChannelMode::VoiceToggle) => {
+                let uid = match params.next().and_then(|param| parse_uid(param)) {
+                    Some(uid) => uid,
+                    None => continue,
+                };
+                let nick = match users.get(&uid) {
+                    Some(user) => user.display_nick().to_string(),
+                    None => continue,
+                };
+                let changed = match mode {
+                    Mode::Add(ChannelMode::OperatorPrivileges) => channel.set_operator(uid, true),
+                    Mode::Sub(ChannelMode::OperatorPrivileges) => channel.set_operator(uid, false),
+                    Mode::Add(_) => channel.set_voiced(uid, true),
+                    Mode::Sub(_) => channel.set_voiced(uid, false),
+                };
+                if changed {
+                    applied_params.push(nick);
+                }
+                changed
+            },
+            Mode::Add(ChannelMode::BanSet) | Mode::Sub(ChannelMode::BanSet) => {
+                let mask = match params.next() {
+                    Some(mask) => mask,
+                    None => continue,
+                };
+                let changed = match mode {
+                    Mode::Add(_) => channel.add_ban(mask),
+                    Mode::Sub(_) => channel.remove_ban(mask),
+                };
+                if changed {
+                    applied_params.push(mask.clone());
+                }
+                changed
+            },
+            Mode::Add(ChannelMode::UserLimit) | Mode::Add(ChannelMode::ChannelPassword) | Mode::Add(ChannelMode::Unknown(_)) => false,
+            Mode::Add(flag) => channel.add_mode(flag.clone()),
+            Mode::Sub(flag) => channel.remove_mode(flag),
+        };
+        if changed {
+            applied.push(mode.clone());
+        }
+    }
+    let members = channel.users().iter().cloned().collect::<Vec<u64>>();
+    std::mem::drop(write);
+    std::mem::drop(users);
+    if applied.is_empty() {
+        return;
+    }
+    let mut line = format!(":{} MODE {} {}", sig, name, Mode::format_list(&applied));
+    for param in applied_params.iter() {
+        line.push(' ');
+        line.push_str(param);
+    }
+    line.push_str("\r\n");
+    deliver(&state.connections, &members, &line).await;
+}
+
+/// tries a link and tells operators how it ended
+async fn start_link(state: ServerState, block: LinkBlock, address: String) {
+    let name = block.name.clone();
+    println!("connecting to server {} on {}", name, address);
+    if let Err(e) = connect_link(state.clone(), block, address).await {
+        println!("link with {} ended: {}", name, e);
+        server_notice(&state, &format!("Link with {} ended: {}", name, e)).await;
+    }
+}
+
+/// keeps the links marked autoconnect up, the config is read every time so a REHASH
+/// can add or remove them
+pub async fn autoconnect(state: ServerState) {
+    let mut attempts: HashMap<String, JoinHandle<()>> = HashMap::new();
+    loop {
+        let blocks = state.config.read().await.links.iter()
+            .filter(|block| block.autoconnect)
+            .cloned()
+            .collect::<Vec<LinkBlock>>();
+        for block in blocks {
+            let key = block.name.to_ascii_lowercase();
+            let running = attempts.get(&key).map(|attempt| !attempt.is_finished()).unwrap_or(false);
+            if running || state.network.read().await.get(&block.name).is_some() {
+                continue;
+            }
+            if let Some(address) = block.address.clone() {
+                attempts.insert(key, task::spawn(start_link(state.clone(), block, address)));
+            }
+        }
+        sleep(RECONNECT_INTERVAL).await;
+    }
+}
+
+/// links to a server from the config, the port replaces the one in the link block
+pub async fn connect(
+    state: &ServerState,
+    id: u64,
+    target: &str,
+    port: &str,
+    remote: &Option<String>,
+    stream: &mut Connection,
+) -> Result<(), std::io::Error> {
+    let (nick, sig) = match require_oper(state, id, stream).await? {
+        Some(oper) => oper,
+        None => return Ok(()),
+    };
+    if let Some(remote) = remote.as_ref().filter(|remote| !wildcard_match(remote, &state.name)) {
+        return stream.write_all(numeric(&state.name, ERR_NOSUCHSERVER, &nick, &format!("{} :No such server", remote)).as_bytes()).await;
+    }
+    let block = match state.config.read().await.find_link(target).cloned() {
+        Some(block) => block,
+        None => return stream.write_all(numeric(&state.name, ERR_NOSUCHSERVER, &nick, &format!("{} :No such server", target)).as_bytes()).await,
+    };
+    if state.network.read().await.get(target).is_some() {
+        let line = format!(":{} NOTICE {} :*** {} is already linked\r\n", state.name, nick, block.name);
+        return stream.write_all(line.as_bytes()).await;
+    }
+    let host = block.address.as_deref()
+        .and_then(|address| address.rsplit_once(':'))
+        .map(|(host, _)| host.to_string())
+        .unwrap_or_else(|| block.name.clone());
+    let address = format!("{}:{}", host, port);
+    println!("CONNECT to {} on {} by {}", block.name, address, sig);
+    server_notice(state, &format!("{} is connecting to {} on {}", nick, block.name, address)).await;
+    task::spawn(start_link(state.clone(), block, address));
+    Ok(())
+}
+
+/// drops a server from the network, a direct link is closed and any other server
+/// is asked to be dropped by the server it is linked to
+pub async fn squit(
+    state: &ServerState,
+    id: u64,
+    server: &str,
+    comment: &str,
+    stream: &mut Connection,
+) -> Result<(), std::io::Error> {
+    let (nick, sig) = match require_oper(state, id, stream).await? {
+        Some(oper) => oper,
+        None => return Ok(()),
+    };
+    let route = state.network.read().await.get(server).map(|linked| (linked.name.clone(), linked.link, linked.hops));
+    let (name, route, hops) = match route {
+        Some(route) => route,
+        None => return stream.write_all(numeric(&state.name, ERR_NOSUCHSERVER, &nick, &format!("{} :No such server", server)).as_bytes()).await,
+    };
+    let reason = if comment.is_empty() { nick.as_str() } else { comment };
+    println!("SQUIT of {} by {}: {}", name, sig, reason);
+    server_notice(state, &format!("Received SQUIT {} from {} ({})", name, nick, reason)).await;
+    if hops == 1 {
+        close_connection(&state.connections, route, &format!("ERROR :Closing Link: {} ({})\r\n", state.name, reason)).await;
+    }else{
+        deliver(&state.connections, &[route], &format!(":{} SQUIT {} :{}\r\n", state.name, name, reason)).await;
+    }
+    Ok(())
+}
+
+/// every server in the network matching the mask, this one first
+pub async fn links(
+    state: &ServerState,
+    id: u64,
+    mask: &Option<String>,
+    stream: &mut Connection,
+) -> Result<(), std::io::Error> {
+    let nick = reply_target(&state.users, id).await;
+    let description = state.config.read().await.server.description.clone();
+    let mask = mask.as_deref().unwrap_or("*");
+    let mut outstring = String::new();
+    if wildcard_match(mask, &state.name) {
+        outstring.push_str(&numeric(&state.name, RPL_LINKS, &nick, &format!("{} {} :0 {}", state.name, state.name, description)));
+    }
+    for server in state.network.read().await.servers().into_iter().filter(|server| wildcard_match(mask, &server.name)) {
+        outstring.push_str(&numeric(&state.name, RPL_LINKS, &nick, &format!("{} {} :{} {}", server.name, server.uplink, server.hops, server.description)));
+    }
+    outstring.push_str(&numeric(&state.name, RPL_ENDOFLINKS, &nick, &format!("{} :End of LINKS list", mask)));
+    stream.write_all(outstring.as_bytes()).await
+}
+
+/// the connections of this server, or the next hop towards a server or user elsewhere
+/// in the network, operators see every user while others only see operators and servers
+pub async fn trace(
+    state: &ServerState,
+    id: u64,
+    target: &Option<String>,
+    stream: &mut Connection,
+) -> Result<(), std::io::Error> {
+    let (nick, is_oper) = match state.users.read().await.get(&id) {
+        Some(user) => (user.display_nick().to_string(), user.has_mode(&UserMode::Operator)),
+        None => return Ok(()),
+    };
+    let version = format!("{}-{}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+    let network = state.network.read().await.clone();
+    let end = |name: &str| numeric(&state.name, RPL_TRACEEND, &nick, &format!("{} {} :End of TRACE", name, version));
+
+    // a target elsewhere gets the link it is reached through
+    let mut only = None;
+    if let Some(target) = target.as_ref().filter(|target| !wildcard_match(target, &state.name)) {
+        let remote = match network.get(target) {
+            Some(server) => Some(server.clone()),
+            None => match find_nick(&*state.users.read().await, target) {
+                Some((uid, _)) if network.is_remote(*uid) => network.server_of(*uid).cloned(),
+                Some((uid, _)) => {
+                    only = Some(*uid);
+                    None
+                },
+                None => {
+                    return stream.write_all(numeric(&state.name, ERR_NOSUCHSERVER, &nick, &format!("{} :No such server", target)).as_bytes()).await;
+                },
+            },
+        };
+        if let Some(server) = remote {
+            let next = network.links().into_iter().find(|linked| linked.link == server.link).map(|linked| linked.name.clone()).unwrap_or_default();
+            let mut outstring = numeric(&state.name, RPL_TRACELINK, &nick, &format!("Link {} {} {}", version, target, next));
+            outstring.push_str(&end(&state.name));
+            return stream.write_all(outstring.as_bytes()).await;
+        }
+    }
+
+    let mut outstring = String::new();
+    for (uid, user) in state.users.read().await.iter() {
+        if network.is_remote(*uid) || only.map(|only| only != *uid).unwrap_or(false) {
+            continue;
+        }
+        let class = user.connection_class().unwrap_or("default");
+        if user.has_mode(&UserMode::Operator) {
+            outstring.push_str(&numeric(&state.name, RPL_TRACEOPERATOR, &nick, &format!("Oper {} {}", class, user.display_nick())));
+        }else if is_oper || only.is_some() {
+            outstring.push_str(&numeric(&state.name, RPL_TRACEUSER, &nick, &format!("User {} {}", class, user.display_nick())));
+        }
+    }
+    if only.is_none() {
+        if is_oper {
+            for service in state.services.read().await.values() {
+                outstring.push_str(&numeric(&state.name, RPL_TRACESERVICE, &nick, &format!("Service default {} {} 0", service.name(), service.kind())));
+            }
+        }
+        for linked in network.links() {
+            let servers = network.servers().into_iter().filter(|server| server.link == linked.link).count();
+            let users = state.users.read().await.keys()
+                .filter(|uid| network.server_of(**uid).map(|server| server.link == linked.link).unwrap_or(false))
+                .count();
+            outstring.push_str(&numeric(&state.name, RPL_TRACESERVER, &nick,
+                &format!("Serv default {}S {}C {} *!*@{} V2", servers, users, linked.name, linked.name)));
+        }
+    }
+    outstring.push_str(&end(&state.name));
+    stream.write_all(outstring.as_bytes()).await
+}
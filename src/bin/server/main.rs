@@ -0,0 +1,880 @@
+//#[macro_use]extern crate tokio;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::RwLock;
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::task::{self, JoinHandle};
+use tokio::time::{sleep, sleep_until, timeout, timeout_at, Duration, Instant};
+use tokio_rustls::TlsAcceptor;
+
+use networkingirc::Args;
+use networkingirc::message::Message;
+use networkingirc::account::Accounts;
+use networkingirc::auth::{LoginFailures, FAILURE_DELAY, MAX_CONNECTION_FAILURES};
+use networkingirc::capability::{CapabilityRegistry, EnabledCaps, SASL, SERVER_TIME};
+use networkingirc::channel::ChannelMeta;
+use networkingirc::command::{Command, UserMode};
+use networkingirc::config::{ConnectionClass, ListenAddress, ListenerBlock, ServerConfig};
+use networkingirc::history::{HistoryStore, ReadMarkers};
+use networkingirc::memo::Memos;
+use networkingirc::network::{first_id, Network};
+use networkingirc::response::{numeric, ERR_PASSWDMISMATCH, ERR_YOUREBANNEDCREEP};
+use networkingirc::service::Service;
+use networkingirc::stats::{CommandStats, LinkStats};
+use networkingirc::tls::{fingerprint, load_certificates, server_config, TlsError};
+use networkingirc::user::{User, NickHistory};
+use networkingirc::{unix_time, LineBuffer};
+use structopt::StructOpt;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use networkingirc::IrcError;
+
+use std::collections::HashMap;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+mod capabilities;
+mod channels;
+mod chanserv;
+mod chathistory;
+mod connection;
+mod info;
+mod links;
+mod memoserv;
+mod messaging;
+mod nickserv;
+mod operators;
+mod queries;
+mod sasl;
+mod services;
+mod snapshots;
+mod users;
+mod websocket;
+
+use capabilities::*;
+use channels::*;
+use chanserv::*;
+use chathistory::*;
+use links::*;
+use connection::{Connection, Listener, Peer, Stream};
+use info::*;
+use memoserv::*;
+use messaging::*;
+use nickserv::*;
+use operators::*;
+use queries::*;
+use sasl::*;
+use services::*;
+use snapshots::*;
+use users::*;
+
+/// items other tasks queue for a client's connection
+pub enum Outgoing {
+    /// a line written to the client as is
+    Line(String),
+    /// a final line written before the connection is closed
+    Close(String),
+}
+
+/// handles to the state shared between every client task
+#[derive(Clone)]
+pub struct ServerState {
+    /// the name used as the prefix of every reply, fixed for the life of the process
+    pub name: String,
+    /// unix timestamp of when the server started
+    pub started: u64,
+    pub config: Arc<RwLock<ServerConfig>>,
+    /// where the config was loaded from, read again on REHASH
+    pub config_path: Option<PathBuf>,
+    /// the --address argument, which replaces the configured listen addresses
+    pub address_override: Option<String>,
+    // maintains meta data about all channels in the server
+    pub channels: Arc<RwLock<HashMap<String, ChannelMeta>>>,
+    // the history of every channel, replayed on JOIN
+    pub messages: Arc<RwLock<HistoryStore>>,
+    // how far each user has read each channel, set with MARKREAD
+    pub read_markers: Arc<RwLock<ReadMarkers>>,
+    // registered accounts users log in to with SASL, saved in the snapshot
+    pub accounts: Arc<RwLock<Accounts>>,
+    // memos left for accounts with MemoServ, saved in the snapshot
+    pub memos: Arc<RwLock<Memos>>,
+    // external services registered with SERVICE, by the id of their connection
+    pub services: Arc<RwLock<HashMap<u64, Service>>>,
+    // the other servers linked into the network and which of them each remote user is on
+    pub network: Arc<RwLock<Network>>,
+    // list of all users by the id of their connection, users on other servers
+    // are kept under the id their own server gave them
+    pub users: Arc<RwLock<HashMap<u64, User>>>,
+    // nicknames that have been changed or have left the server, used by WHOWAS
+    pub whowas: Arc<RwLock<NickHistory>>,
+    // outgoing message queue of every connected user, used to reach users
+    // other than the one a task is serving
+    pub connections: Arc<RwLock<HashMap<u64, Sender<Outgoing>>>>,
+    // accepts the handshake on TLS listeners, replaced on REHASH so a renewed certificate is picked up
+    pub tls: Arc<RwLock<Option<TlsAcceptor>>>,
+    // IRCv3 capabilities offered in CAP LS
+    pub capabilities: Arc<CapabilityRegistry>,
+    // traffic counters of every connected user, reported by STATS l
+    pub links: Arc<RwLock<HashMap<u64, Arc<LinkStats>>>>,
+    // how often each command has been used since startup, reported by STATS m
+    pub command_stats: Arc<RwLock<HashMap<String, CommandStats>>>,
+    // the id the next user, service or server link is given, see new_id
+    pub next_id: Arc<AtomicU64>,
+    // recent wrong passwords for accounts and oper blocks, see login_failed
+    pub login_failures: Arc<RwLock<LoginFailures>>,
+    // the NickServ grace period running for each user, a nick change replaces it
+    pub nick_timers: Arc<RwLock<HashMap<u64, JoinHandle<()>>>>,
+}
+
+#[tokio::main]
+async fn main() {
+    let args = Args::from_args();
+    if args.hash_password {
+        let mut password = String::new();
+        if std::io::stdin().read_line(&mut password).is_err() {
+            std::process::exit(1);
+        }
+        match networkingirc::auth::hash_password(password.trim_end_matches(['\r', '\n'])) {
+            Ok(hash) => println!("{}", hash),
+            Err(e) => {
+                eprintln!("couldn't hash password: {}", e);
+                std::process::exit(1);
+            },
+        }
+        return;
+    }
+    let mut config = match &args.config {
+        Some(path) => match ServerConfig::read(path) {
+            Ok(config) => config,
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            },
+        },
+        None => ServerConfig::default(),
+    };
+    if let Some(address) = &args.address {
+        config.server.listen = vec![address.clone()];
+    }
+    if config.server.cloak_key.is_empty() {
+        println!("server.cloak_key isn't set, masked hosts will change when the server restarts");
+        config.server.cloak_key = random_key();
+    }
+    if let Err(e) = config.validate() {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+
+    let history = match &config.history.directory {
+        Some(directory) => match HistoryStore::open(directory, config.history.retention(), &config.channel_retention()) {
+            Ok(history) => history,
+            Err(e) => {
+                eprintln!("couldn't open history directory {:?}: {}", directory, e);
+                std::process::exit(1);
+            },
+        },
+        None => HistoryStore::in_memory(config.history.retention()),
+    };
+    let tls = match load_tls(&config) {
+        Ok(tls) => tls,
+        Err(e) => {
+            eprintln!("couldn't load the TLS certificate: {}", e);
+            std::process::exit(1);
+        },
+    };
+    let messages = Arc::new(RwLock::new(history));
+    load_default_channels(&config, &messages).await;
+    let (channels, read_markers, accounts, memos) = restore_snapshot(&config);
+
+    let state = ServerState {
+        name: config.server.name.clone(),
+        started: unix_time(),
+        channels: Arc::new(RwLock::new(channels)),
+        messages,
+        read_markers: Arc::new(RwLock::new(read_markers)),
+        accounts: Arc::new(RwLock::new(accounts)),
+        memos: Arc::new(RwLock::new(memos)),
+        services: Arc::new(RwLock::new(HashMap::new())),
+        network: Arc::new(RwLock::new(Network::new())),
+        users: Arc::new(RwLock::new(HashMap::new())),
+        whowas: Arc::new(RwLock::new(NickHistory::new(config.limits.whowas_size))),
+        connections: Arc::new(RwLock::new(HashMap::new())),
+        tls: Arc::new(RwLock::new(tls)),
+        capabilities: Arc::new(capability_registry()),
+        links: Arc::new(RwLock::new(HashMap::new())),
+        command_stats: Arc::new(RwLock::new(HashMap::new())),
+        next_id: Arc::new(AtomicU64::new(first_id(&config.server.name))),
+        login_failures: Arc::new(RwLock::new(LoginFailures::new())),
+        nick_timers: Arc::new(RwLock::new(HashMap::new())),
+        config: Arc::new(RwLock::new(config.clone())),
+        config_path: args.config.clone(),
+        address_override: args.address.clone(),
+    };
+
+    task::spawn(snapshot_loop(state.clone()));
+    task::spawn(save_on_shutdown(state.clone()));
+    task::spawn(autoconnect(state.clone()));
+
+    let mut listeners = Vec::new();
+    for block in config.all_listeners() {
+        // validate has already checked every listener has one
+        let endpoint = match block.endpoint() {
+            Some(endpoint) => endpoint,
+            None => continue,
+        };
+        let listener = match Listener::bind(&endpoint).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                eprintln!("couldn't listen on {}: {}", endpoint, e);
+                std::process::exit(1);
+            },
+        };
+        let kind = match (block.tls, block.websocket) {
+            (false, false) => "plaintext",
+            (true, false) => "TLS",
+            (false, true) => "WebSocket",
+            (true, true) => "WebSocket over TLS",
+        };
+        println!("successfully open {} listener on: {}", kind, endpoint);
+        listeners.push(task::spawn(accept_clients(listener, endpoint, state.clone())));
+    }
+    for listener in listeners {
+        let _ = listener.await;
+    }
+}
+
+/// the acceptor for TLS listeners, None when the config has no certificate
+pub fn load_tls(config: &ServerConfig) -> Result<Option<TlsAcceptor>, TlsError> {
+    let (certificate, key) = match (&config.tls.certificate, &config.tls.key) {
+        (Some(certificate), Some(key)) => (certificate, key),
+        _ => return Ok(None),
+    };
+    if let Some(leaf) = load_certificates(certificate)?.first() {
+        println!("TLS certificate fingerprint: sha256 {}", fingerprint(leaf));
+    }
+    Ok(Some(TlsAcceptor::from(server_config(certificate, key)?)))
+}
+
+/// main event loop to listen for incoming connections, the listener's options
+/// are looked up for every client so a REHASH changes them for new clients
+async fn accept_clients(listener: Listener, endpoint: ListenAddress, state: ServerState) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, addr)) => {
+                println!("new client: {} on {}", addr, endpoint);
+                let state = state.clone();
+                let block = match state.config.read().await.find_listener(&endpoint) {
+                    Some(block) => block,
+                    None => {
+                        println!("refused client {}, {} was removed from the config", addr, endpoint);
+                        continue;
+                    },
+                };
+
+                // an additional task is spawned here to handle the initial handshake
+                task::spawn(async move {
+                    let stream = if block.tls {
+                        match accept_tls(&state, stream).await {
+                            Ok(stream) => stream,
+                            Err(e) => {
+                                println!("client {} failed the TLS handshake: {}", addr, e);
+                                return;
+                            },
+                        }
+                    }else{
+                        stream
+                    };
+                    let stream = if block.websocket {
+                        match accept_websocket(&state, stream, &block.origins).await {
+                            Ok(stream) => stream,
+                            Err(e) => {
+                                println!("client {} failed the WebSocket handshake: {}", addr, e);
+                                return;
+                            },
+                        }
+                    }else{
+                        stream
+                    };
+                    if let Err(e) = launch_client_listener(state, Connection::new(stream), addr.clone(), block).await {
+                        println!("client {} disconnected with error: {}", addr, e);
+                    }
+                });
+            },
+            Err(e) => println!("couldn't get client: {:?}", e),
+        }
+    }
+}
+
+/// the handshake has the same deadline as registration so a silent client can't hold the task
+async fn accept_tls(state: &ServerState, stream: Stream) -> Result<Stream, IrcError> {
+    let acceptor = match state.tls.read().await.clone() {
+        Some(acceptor) => acceptor,
+        None => return Err(IrcError::ServerDisconnect(std::io::Error::other("no TLS certificate is loaded"))),
+    };
+    let registration = Duration::from_secs(state.config.read().await.timeouts.registration);
+    match timeout(registration, acceptor.accept(stream)).await {
+        Ok(stream) => Ok(Stream::Tls(Box::new(stream?))),
+        Err(_) => Err(IrcError::RegistrationTimeout),
+    }
+}
+
+async fn accept_websocket(state: &ServerState, stream: Stream, origins: &[String]) -> Result<Stream, IrcError> {
+    let registration = Duration::from_secs(state.config.read().await.timeouts.registration);
+    match timeout(registration, websocket::accept(stream, origins)).await {
+        Ok(stream) => Ok(Stream::WebSocket(Box::new(stream?))),
+        Err(_) => Err(IrcError::RegistrationTimeout),
+    }
+}
+
+/// a connection registers as a user, a service or another server
+enum Registered {
+    Client(Box<Registration>),
+    /// a SERVICE along with the password from a PASS sent before it
+    Service(Service, Option<String>),
+    /// the name and description from a SERVER along with the password from a PASS sent before it
+    Server(String, String, Option<String>),
+}
+
+/// what a client sent while registering
+struct Registration {
+    user: User,
+    password: Option<String>,
+    /// a NICK sent before registration finished, applied once the session starts
+    nick: Option<String>,
+    caps: EnabledCaps,
+    /// the account logged in to with SASL
+    account: Option<String>,
+}
+
+/// a message as it is written to the log, passwords from PASS and OPER, SASL payloads and
+/// commands for the built in services, which may carry NickServ passwords, are left out
+fn redacted(message: &Message) -> String {
+    match message.command() {
+        Command::PASS(_) => String::from("PASS <password>"),
+        Command::OPER(name, _) => format!("OPER {} <password>", name),
+        Command::AUTHENTICATE(_) => String::from("AUTHENTICATE <payload>"),
+        Command::PRIVMSG(target, _) | Command::SQUERY(target, _) if builtin_service(target).is_some() => {
+            format!("{} {} <command>", message.command_name(), target)
+        },
+        _ => format!("{:?}", message),
+    }
+}
+
+/// reads messages until USER, SERVICE or SERVER, keeping the password from a PASS and the nick from a NICK
+/// sent before it, once a client starts CAP negotiation registration waits for CAP END
+/// so it has the chance to authenticate with SASL
+async fn read_registration(
+    state: &ServerState,
+    stream: &mut Connection,
+    lines: &mut LineBuffer,
+    deadline: Instant,
+) -> Result<Registered, IrcError> {
+    let (mut password, mut nick) = (None, None);
+    let mut user: Option<User> = None;
+    let mut caps = EnabledCaps::default();
+    let mut negotiating = false;
+    let (mut exchange, mut account) = (SaslExchange::default(), None);
+    loop {
+        let line = match timeout_at(deadline, lines.next_line(stream)).await {
+            Ok(line) => line?,
+            Err(_) => return Err(IrcError::RegistrationTimeout),
+        };
+        let line = match line {
+            Some(line) => line,
+            None => return Err(IrcError::MissingUser),
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let message = Message::parse(line).map_err(IrcError::CommandParse)?;
+        println!("read in data: {}", redacted(&message));
+        match message.command() {
+            Command::PASS(pass) => password = Some(pass.clone()),
+            Command::NICK(name) => nick = Some(name.clone()),
+            Command::CAP(subcommand, param) => {
+                match subcommand.as_str() {
+                    "LS" | "REQ" => negotiating = true,
+                    "END" => negotiating = false,
+                    _ => {},
+                }
+                let target = nick.as_deref().unwrap_or("*");
+                negotiate(state, target, subcommand, param, &mut caps, stream).await?;
+            },
+            Command::AUTHENTICATE(param) => {
+                let nick = nick.clone().unwrap_or_else(|| String::from("*"));
+                let mask = match &user {
+                    Some(user) => format!("{}!{}@{}", nick, user.username(), user.hostname()),
+                    None => nick.clone(),
+                };
+                let client = SaslClient { nick, mask, enabled: caps.has(SASL), account: account.clone() };
+                if let Some(logged_in) = authenticate(state, &client, &mut exchange, param, stream).await? {
+                    account = Some(logged_in);
+                }
+            },
+            Command::USER(username, hostname, server, realname) => {
+                println!("received message from new client handshake now");
+                user = Some(User::new(username.to_string(),hostname.to_string(),realname.to_string(),server.to_string()));
+            },
+            Command::SERVICE(name, _, distribution, kind, _, info) => {
+                let service = Service::new(name.clone(), distribution.clone(), kind.clone(), info.clone(), unix_time());
+                return Ok(Registered::Service(service, password));
+            },
+            Command::SERVER(name, _, description) => {
+                return Ok(Registered::Server(name.clone(), description.clone(), password));
+            },
+            _ => {
+                stream.write_all(b"392 the first command should be USER \r\n").await?;
+                stream.shutdown().await?;
+                return Err(IrcError::MissingUser)
+            },
+        }
+        if !negotiating {
+            if let Some(user) = user.take() {
+                return Ok(Registered::Client(Box::new(Registration { user, password, nick, caps, account })));
+            }
+        }
+    }
+}
+
+async fn launch_client_listener(
+    state: ServerState,
+    mut stream: Connection,
+    addr: Peer,
+    listener: ListenerBlock,
+) -> Result<(), IrcError> {
+    println!("entered launch client listener function");
+    let (limits, class, registration) = {
+        let config = state.config.read().await;
+        (
+            config.limits.clone(),
+            config.connection_class(listener.class.as_deref()),
+            Duration::from_secs(config.timeouts.registration),
+        )
+    };
+
+    // users on other servers don't count towards any limit
+    let admitted = {
+        let network = state.network.read().await;
+        let read = state.users.read().await;
+        let local = read.iter().filter(|(uid, _)| !network.is_remote(**uid)).map(|(_, user)| user);
+        class.admits(limits.max_clients, local, &addr.ip())
+    };
+    if !admitted {
+        stream.write_all(b"ERROR :Closing Link: too many connections\r\n").await?;
+        stream.shutdown().await?;
+        return Err(IrcError::ConnectionLimit);
+    }
+
+    // services and servers authenticate with their own password from the config instead of the listener's
+    // a client may send more than its registration in one go, the rest is kept for the session
+    let mut lines = LineBuffer::default();
+    let Registration { mut user, password, nick, caps, account } = match read_registration(&state, &mut stream, &mut lines, Instant::now() + registration).await? {
+        Registered::Client(registration) => *registration,
+        Registered::Service(service, password) => return launch_service(state, stream, lines, addr, class, service, password).await,
+        Registered::Server(name, description, password) => return accept_link(state, stream, lines, addr, name, description, password).await,
+    };
+    user.ip(addr.ip()).cloak(&state.config.read().await.server.cloak_key).secure(stream.is_secure()).class(class.name.clone()).caps(caps).login(account);
+
+    if !listener.verify(password.as_deref()).await {
+        println!("refused client {} from {}: wrong or missing PASS", user.sig(), addr);
+        let mut outstring = numeric(&state.name, ERR_PASSWDMISMATCH, user.display_nick(), ":Password incorrect");
+        outstring.push_str(&format!("ERROR :Closing Link: {} (Bad Password)\r\n", user.hostname()));
+        stream.write_all(outstring.as_bytes()).await?;
+        stream.shutdown().await?;
+        return Err(IrcError::PasswordMismatch);
+    }
+
+    let ban = state.config.read().await
+        .find_ban(user.username(), user.hostname(), user.ip_address())
+        .map(|ban| ban.reason.clone());
+    if let Some(reason) = ban {
+        println!("refused banned client {} from {}", user.sig(), addr);
+        let mut outstring = numeric(&state.name, ERR_YOUREBANNEDCREEP, user.display_nick(), &format!(":You are banned from this server: {}", reason));
+        outstring.push_str(&format!("ERROR :Closing Link: {} (Banned)\r\n", user.hostname()));
+        stream.write_all(outstring.as_bytes()).await?;
+        stream.shutdown().await?;
+        return Err(IrcError::Banned);
+    }
+
+    let id = new_id(&state);
+    {
+        let mut write = state.users.write().await;
+        write.insert(id, user.clone());
+        std::mem::drop(write);
+    }
+    let (sender, mut receiver): (Sender<Outgoing>, Receiver<Outgoing>) = channel(100);
+    state.connections.write().await.insert(id, sender);
+    state.links.write().await.insert(id, stream.stats());
+
+    let result = client_session(&state, &mut stream, &mut lines, &mut receiver, &addr, &class, nick, id).await;
+
+    // whatever way the session ended the user is no longer on the server
+    let reason = match &result {
+        Err(IrcError::PingTimeout) => "Ping timeout",
+        Err(IrcError::SendQExceeded) => "SendQ exceeded",
+        _ => "Connection closed",
+    };
+    quit_user(&state, id, reason).await;
+    state.connections.write().await.remove(&id);
+    state.links.write().await.remove(&id);
+    if let Some(timer) = state.nick_timers.write().await.remove(&id) {
+        timer.abort();
+    }
+    let mut write = state.users.write().await;
+    if let Some(user) = write.remove(&id) {
+        state.whowas.write().await.record(&user, &state.name);
+    }
+    std::mem::drop(write);
+    result
+}
+
+// the connection's parts are passed separately so each can be borrowed on its own inside select!
+#[allow(clippy::too_many_arguments)]
+async fn client_session(
+    state: &ServerState,
+    stream: &mut Connection,
+    lines: &mut LineBuffer,
+    receiver: &mut Receiver<Outgoing>,
+    addr: &Peer,
+    class: &ConnectionClass,
+    nick: Option<String>,
+    id: u64,
+) -> Result<(), IrcError> {
+    let (registration, welcome) = {
+        let config = state.config.read().await;
+        (Duration::from_secs(config.timeouts.registration), config.welcome_channel().to_string())
+    };
+    let ping_interval = Duration::from_secs(class.ping_interval);
+    let ping_timeout = Duration::from_secs(class.ping_timeout);
+
+    println!("client incoming request received from: {} responding with ping", addr);
+    stream.write_all(format!("PING {} :12345\r\n", state.name).as_bytes()).await?;
+
+    let deadline = Instant::now() + registration;
+    let pong_read = loop {
+        let line = match timeout_at(deadline, lines.next_line(stream)).await {
+            Ok(line) => line?,
+            Err(_) => return Err(IrcError::RegistrationTimeout),
+        };
+        match line {
+            Some(line) if line.trim().is_empty() => continue,
+            Some(line) => break line,
+            None => return Err(IrcError::DoSWarning),
+        }
+    };
+    match Message::parse(pong_read) {
+        Ok(message) => {
+            match message.command() {
+                Command::PONG(server, _) => {
+                    println!("received pong from server: {}", server);
+                },
+                _ => {
+                    stream.write_all(b"392 the second command should be PONG \r\n").await?;
+                    stream.shutdown().await?;
+                    return Err(IrcError::DoSWarning)
+                },
+            }
+        },
+        Err(e) => return Err(IrcError::CommandParse(e)),
+    };
+    println!("client connected from address: {} with id: {}", addr, id);
+    // I'm debating having message meta data sent in the trailing as JSON
+
+    // a user known by their username may be using a registered nickname as well
+    match nick {
+        Some(nick) => change_nick(state, id, &nick, stream).await?,
+        None => protect_nick(state, id, stream).await?,
+    }
+    announce_user(state, id).await;
+    send_welcome(state, id, stream).await?;
+    motd(state, id, &None, stream).await?;
+    // a client that logged in with SASL while registering hears about its memos once welcomed
+    let login = state.users.read().await.get(&id).and_then(|user| user.account().clone().map(|account| (user.display_nick().to_string(), account)));
+    if let Some((nick, account)) = login {
+        stream.write_all(unread_memos(state, &nick, &account).await.as_bytes()).await?;
+    }
+
+    let mut current_channel = welcome;
+    let restricted = state.users.read().await.get(&id).map(|user| user.has_mode(&UserMode::Restricted)).unwrap_or(false);
+    join_channel(&state.channels, &current_channel, id, restricted).await;
+    restore_access(state, id, &current_channel).await;
+    announce_join(state, id, &current_channel).await;
+    send_read_marker(state, id, &current_channel, stream).await?;
+    display_messages(state, id, &current_channel, stream).await?;
+    // relayed lines are tagged with the time when the client asked for server-time
+    let mut server_time = has_cap(state, id, SERVER_TIME).await;
+    let mut exchange = SaslExchange::default();
+
+    // a client that stays quiet for ping_interval is sent a PING, and dropped
+    // if nothing comes back within ping_timeout
+    let mut last_seen = Instant::now();
+    let mut ping_sent: Option<Instant> = None;
+
+    loop {
+        let deadline = match ping_sent {
+            Some(sent) => sent + ping_timeout,
+            None => last_seen + ping_interval,
+        };
+        // messages from other users are forwarded while waiting on the client
+        let line = tokio::select! {
+            outgoing = receiver.recv() => {
+                match outgoing {
+                    Some(Outgoing::Line(line)) if server_time => stream.write_all(add_server_time(&line).as_bytes()).await?,
+                    Some(Outgoing::Line(line)) => stream.write_all(line.as_bytes()).await?,
+                    Some(Outgoing::Close(line)) => {
+                        stream.write_all(line.as_bytes()).await?;
+                        stream.shutdown().await?;
+                        return Err(IrcError::Closed(line.lines().last().unwrap_or_default().to_string()));
+                    },
+                    // the queue is only closed under a session when it fell too far behind
+                    None => {
+                        stream.write_all(format!("ERROR :Closing Link: {} (SendQ exceeded)\r\n", state.name).as_bytes()).await?;
+                        stream.shutdown().await?;
+                        return Err(IrcError::SendQExceeded);
+                    },
+                }
+                continue;
+            },
+            _ = sleep_until(deadline) => {
+                if ping_sent.is_some() {
+                    stream.write_all(b"ERROR :Closing Link: Ping timeout\r\n").await?;
+                    stream.shutdown().await?;
+                    return Err(IrcError::PingTimeout);
+                }
+                stream.write_all(format!("PING :{}\r\n", state.name).as_bytes()).await?;
+                ping_sent = Some(Instant::now());
+                continue;
+            },
+            incoming = lines.next_line(stream) => incoming?,
+        };
+        let line = match line {
+            Some(line) => line,
+            None => {
+                println!("client has disconnected");
+                return Ok(());
+            },
+        };
+        last_seen = Instant::now();
+        ping_sent = None;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let message = match Message::parse(line.clone()) {
+            Ok(message) => message,
+            Err(e) => {
+                println!("couldn't parse message from {}: {}", addr, e);
+                continue;
+            },
+        };
+        state.command_stats.write().await
+            .entry(message.command_name())
+            .or_default()
+            .record(line.len());
+        println!("raw message: {}", redacted(&message));
+        match message.command() {
+            Command::RAW => {
+                let nick = match state.users.write().await.get_mut(&id) {
+                    Some(user) => {
+                        user.touch();
+                        user.display_nick().to_string()
+                    },
+                    None => continue,
+                };
+                put_message(&current_channel, &nick, message.raw_message(), &state.messages).await;
+            },
+            Command::PING(token, _) => {
+                stream.write_all(format!(":{} PONG {} :{}\r\n", state.name, state.name, token).as_bytes()).await?;
+            },
+            Command::PONG(_, _) => {},
+            Command::NICK(nick) => {
+                change_nick(state, id, nick, stream).await?;
+            },
+            Command::JOIN(channellist, _keys, _) => {
+                println!("join command received from client");
+                if let Some((joined, already_member)) = join_channels(state, id, channellist, stream).await? {
+                    current_channel = joined;
+                    // switching back to a channel doesn't replay what the client has already seen
+                    if !already_member {
+                        display_messages(state, id, &current_channel, stream).await?;
+                    }
+                }
+            },
+            Command::LIST(channelset, _server) => {
+                println!("LIST command invoked with: {:?} querylist", channelset);
+                if channelset.is_empty() {
+                    list_channels(&state.channels, stream).await?;
+                }else{
+                    list_topics(&state.channels, channelset, stream).await?;
+                }
+            },
+            Command::NAMES(channellist, _server) if channellist.is_empty() => {
+                list_all_users(&state.channels, &state.users, id, stream).await?;
+            },
+            Command::NAMES(channellist, _server) => {
+                list_channel_names(state, id, channellist, stream).await?;
+            },
+            Command::PART(channellist, _) => {
+                leave_channels(&state.channels, channellist, id).await;
+                announce_part(state, id, channellist).await;
+            },
+            Command::WHO(mask, operators, whox) => {
+                who(state, id, mask.as_deref(), operators.unwrap_or(false), whox.as_ref(), stream).await?;
+            },
+            Command::WHOIS(_server, masks) => {
+                whois(state, id, masks, stream).await?;
+            },
+            Command::WHOWAS(nicks, count, _server) => {
+                let count = count.as_ref()
+                    .and_then(|count| count.parse::<i64>().ok())
+                    .filter(|count| *count > 0)
+                    .map(|count| count as usize);
+                whowas_query(state, id, nicks, count, stream).await?;
+            },
+            Command::PRIVMSG(msgtarget, text) => {
+                send_message(state, id, "PRIVMSG", msgtarget, text, stream).await?;
+            },
+            Command::NOTICE(msgtarget, text) => {
+                send_message(state, id, "NOTICE", msgtarget, text, stream).await?;
+            },
+            Command::SQUERY(service, text) => {
+                squery(state, id, service, text, stream).await?;
+            },
+            Command::SERVLIST(mask, kind) => {
+                servlist(state, id, mask, kind, stream).await?;
+            },
+            Command::OPER(name, password) => {
+                oper(state, id, name, password, stream).await?;
+            },
+            Command::UserMode(nick, modes) => {
+                user_mode(state, id, nick, modes, stream).await?;
+            },
+            Command::ChannelMode(target, modes, params) => {
+                channel_mode(state, id, target, modes, params, stream).await?;
+            },
+            Command::TOPIC(target, text) => {
+                topic(state, id, target, text, stream).await?;
+            },
+            Command::USERHOST(nicks) => {
+                userhost(state, id, nicks, stream).await?;
+            },
+            Command::ISON(nicks) => {
+                ison(state, id, nicks, stream).await?;
+            },
+            Command::AWAY(away) => {
+                set_away(state, id, away, stream).await?;
+            },
+            Command::MOTD(target) => {
+                motd(state, id, target, stream).await?;
+            },
+            Command::LUSERS(_, target) => {
+                lusers(state, id, target, stream).await?;
+            },
+            Command::VERSION(target) => {
+                version(state, id, target, stream).await?;
+            },
+            Command::TIME(target) => {
+                time(state, id, target, stream).await?;
+            },
+            Command::ADMIN(target) => {
+                admin(state, id, target, stream).await?;
+            },
+            Command::INFO(target) => {
+                info(state, id, target, stream).await?;
+            },
+            Command::STATS(query, target) => {
+                stats(state, id, query, target, stream).await?;
+            },
+            Command::LINKS(_, mask) => {
+                links(state, id, mask, stream).await?;
+            },
+            Command::TRACE(target) => {
+                trace(state, id, target, stream).await?;
+            },
+            Command::CONNECT(target, port, remote) => {
+                connect(state, id, target, port, remote, stream).await?;
+            },
+            Command::SQUIT(server, comment) => {
+                squit(state, id, server, comment, stream).await?;
+            },
+            Command::CAP(subcommand, param) => {
+                cap(state, id, subcommand, param, stream).await?;
+                server_time = has_cap(state, id, SERVER_TIME).await;
+            },
+            Command::AUTHENTICATE(param) => {
+                sasl(state, id, &mut exchange, param, stream).await?;
+            },
+            Command::CHATHISTORY(args) => {
+                chathistory(state, id, args, stream).await?;
+            },
+            Command::MARKREAD(target, timestamp) => {
+                markread(state, id, target, timestamp, stream).await?;
+            },
+            Command::KILL(nick, comment) => {
+                kill(state, id, nick, comment, stream).await?;
+            },
+            Command::WALLOPS(text) => {
+                wallops(state, id, text, stream).await?;
+            },
+            Command::REHASH => {
+                rehash(state, id, stream).await?;
+            },
+            Command::DIE => {
+                die(state, id, stream).await?;
+            },
+            Command::RESTART => {
+                restart(state, id, stream).await?;
+            },
+            Command::QUIT(comment) => {
+                quit_user(state, id, &format!("Quit: {}", comment.as_deref().unwrap_or(""))).await;
+                stream.write_all(b"ERROR :Closing Link\r\n").await?;
+                stream.shutdown().await?;
+                return Ok(());
+            },
+            _ => {},
+        }
+    }
+}
+
+/// a connection id no other connection has had since the server started
+pub fn new_id(state: &ServerState) -> u64 {
+    state.next_id.fetch_add(1, Ordering::Relaxed)
+}
+
+/// counts a wrong password against the account or oper block and the connection it came from,
+/// then waits a moment before the failure is answered; a connection that has failed too often
+/// is closed and the error ends its session
+pub async fn login_failed(state: &ServerState, name: &str, stream: &mut Connection) -> Result<(), std::io::Error> {
+    state.login_failures.write().await.fail(name, unix_time());
+    sleep(FAILURE_DELAY).await;
+    if stream.failed_login() < MAX_CONNECTION_FAILURES {
+        return Ok(());
+    }
+    stream.write_all(format!("ERROR :Closing Link: {} (Too many failed login attempts)\r\n", state.name).as_bytes()).await?;
+    stream.shutdown().await?;
+    Err(std::io::Error::new(std::io::ErrorKind::PermissionDenied, "too many failed login attempts"))
+}
+
+/// a cloak key for servers without one, from the random seeds std gives each HashMap
+fn random_key() -> String {
+    (0..4).map(|_| format!("{:016x}", RandomState::new().build_hasher().finish())).collect()
+}
+
+/// the name used as the target of numeric replies sent to a user
+pub async fn reply_target(users: &Arc<RwLock<HashMap<u64, User>>>, id: u64) -> String {
+    match users.read().await.get(&id) {
+        Some(user) => user.display_nick().to_string(),
+        None => String::from("*"),
+    }
+}
+
+/// finds a connected user by nickname, ignoring case
+pub fn find_nick<'a>(users: &'a HashMap<u64, User>, nick: &str) -> Option<(&'a u64, &'a User)> {
+    users.iter().find(|(_, user)| user.display_nick().eq_ignore_ascii_case(nick))
+}
+
+/// true when the two users are in at least one channel together
+pub fn shares_channel(channels: &HashMap<String, ChannelMeta>, first: u64, second: u64) -> bool {
+    channels.values().any(|channel| channel.has_user(first) && channel.has_user(second))
+}
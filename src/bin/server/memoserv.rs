@@ -0,0 +1,155 @@
+//! the memo service, leaves memos for registered accounts that are read with NOTICEs
+//! the next time the account logs in
+
+use tokio::io::AsyncWriteExt;
+
+use networkingirc::memo::Memo;
+use networkingirc::{format_time, unix_time};
+
+use crate::connection::Connection;
+use crate::messaging::deliver;
+use crate::services::{service_notice, MEMOSERV};
+use crate::snapshots::save_snapshot;
+use crate::ServerState;
+
+/// each command's syntax is the part before the " - "
+const HELP: [&str; 5] = [
+    "SEND <nick|account> <text> - leaves a memo for a registered account",
+    "LIST - lists the memos left for you, new ones are marked with a *",
+    "READ <number> - shows a memo",
+    "DEL <number|ALL> - deletes a memo or all of them",
+    "HELP - shows this list",
+];
+
+/// the user a command came from
+struct Caller {
+    nick: String,
+    account: Option<String>,
+}
+
+pub async fn memoserv(
+    state: &ServerState,
+    id: u64,
+    text: &str,
+    stream: &mut Connection,
+) -> Result<(), std::io::Error> {
+    let caller = match state.users.read().await.get(&id) {
+        Some(user) => Caller {
+            nick: user.display_nick().to_string(),
+            account: user.account().clone(),
+        },
+        None => return Ok(()),
+    };
+    let mut words = text.split_whitespace();
+    let command = words.next().unwrap_or_default().to_ascii_uppercase();
+    let args = words.collect::<Vec<&str>>();
+    let notice = |text: &str| service_notice(state, MEMOSERV, &caller.nick, text);
+
+    let outstring = match (command.as_str(), args.as_slice()) {
+        ("HELP", _) | ("", _) => HELP.iter().map(|line| notice(line)).collect(),
+        (_, _) if caller.account.is_none() && HELP.iter().any(|line| line.split(' ').next() == Some(command.as_str())) => {
+            notice("You need to IDENTIFY with NickServ before you can use memos")
+        },
+        ("SEND", [recipient, text @ ..]) if !text.is_empty() => send(state, &caller, recipient, &text.join(" ")).await,
+        ("LIST", []) => list(state, &caller).await,
+        ("READ", [number]) => match number.parse::<usize>() {
+            Ok(number) => read(state, &caller, number).await,
+            Err(_) => notice("Syntax: READ <number>"),
+        },
+        ("DEL", [all]) if all.eq_ignore_ascii_case("ALL") => delete_all(state, &caller).await,
+        ("DEL", [number]) => match number.parse::<usize>() {
+            Ok(number) => delete(state, &caller, number).await,
+            Err(_) => notice("Syntax: DEL <number|ALL>"),
+        },
+        _ => match HELP.iter().find(|line| line.split(' ').next() == Some(command.as_str())) {
+            Some(line) => notice(&format!("Syntax: {}", line.split(" - ").next().unwrap_or_default())),
+            None => notice(&format!("Unknown command {}, see HELP", command)),
+        },
+    };
+    stream.write_all(outstring.as_bytes()).await
+}
+
+/// the account every command but HELP is run as, checked before the command is dispatched
+fn account(caller: &Caller) -> &str {
+    caller.account.as_deref().unwrap_or_default()
+}
+
+/// sessions logged in to the account are told about the memo straight away
+async fn send(state: &ServerState, caller: &Caller, recipient: &str, text: &str) -> String {
+    let notice = |text: &str| service_notice(state, MEMOSERV, &caller.nick, text);
+    let recipient = match state.accounts.read().await.find_by_nick(recipient) {
+        Some(found) => found.name.clone(),
+        None => return notice(&format!("{} isn't registered", recipient)),
+    };
+    let limit = state.config.read().await.services.max_memos;
+    let memo = Memo::new(recipient.clone(), account(caller).to_string(), unix_time(), text.to_string());
+    let mut write = state.memos.write().await;
+    if !write.send(memo, limit) {
+        return notice(&format!("{} has too many memos, they need to delete some first", recipient));
+    }
+    let number = write.list(&recipient).len();
+    std::mem::drop(write);
+    save_snapshot(state).await;
+
+    let online = state.users.read().await.iter()
+        .filter(|(_, user)| user.account().as_deref().map(|account| account.eq_ignore_ascii_case(&recipient)).unwrap_or(false))
+        .map(|(uid, user)| (*uid, user.display_nick().to_string()))
+        .collect::<Vec<(u64, String)>>();
+    for (uid, nick) in online {
+        let text = format!("You have a new memo from {}, READ {} to see it", account(caller), number);
+        deliver(&state.connections, &[uid], &service_notice(state, MEMOSERV, &nick, &text)).await;
+    }
+    notice(&format!("Your memo has been left for {}", recipient))
+}
+
+async fn list(state: &ServerState, caller: &Caller) -> String {
+    let notice = |text: &str| service_notice(state, MEMOSERV, &caller.nick, text);
+    let read = state.memos.read().await;
+    let memos = read.list(account(caller));
+    if memos.is_empty() {
+        return notice("You have no memos");
+    }
+    let mut outstring = notice(&format!("You have {} memos, {} new:", memos.len(), read.unread(account(caller))));
+    for (index, memo) in memos.iter().enumerate() {
+        let new = if memo.read { ' ' } else { '*' };
+        outstring.push_str(&notice(&format!("{}{} from {} on {}", new, index + 1, memo.from, format_time(memo.sent))));
+    }
+    outstring
+}
+
+async fn read(state: &ServerState, caller: &Caller, number: usize) -> String {
+    let notice = |text: &str| service_notice(state, MEMOSERV, &caller.nick, text);
+    let memo = match state.memos.write().await.read(account(caller), number) {
+        Some(memo) => memo.clone(),
+        None => return notice(&format!("You have no memo {}", number)),
+    };
+    save_snapshot(state).await;
+    let mut outstring = notice(&format!("Memo {} from {} on {}:", number, memo.from, format_time(memo.sent)));
+    outstring.push_str(&notice(&memo.text));
+    outstring
+}
+
+async fn delete(state: &ServerState, caller: &Caller, number: usize) -> String {
+    let notice = |text: &str| service_notice(state, MEMOSERV, &caller.nick, text);
+    if state.memos.write().await.remove(account(caller), number).is_none() {
+        return notice(&format!("You have no memo {}", number));
+    }
+    save_snapshot(state).await;
+    notice(&format!("Memo {} has been deleted", number))
+}
+
+async fn delete_all(state: &ServerState, caller: &Caller) -> String {
+    let notice = |text: &str| service_notice(state, MEMOSERV, &caller.nick, text);
+    let deleted = state.memos.write().await.clear(account(caller));
+    save_snapshot(state).await;
+    notice(&format!("{} memos have been deleted", deleted))
+}
+
+/// tells a user who just logged in how many of their memos they haven't read yet
+pub async fn unread_memos(state: &ServerState, nick: &str, account: &str) -> String {
+    let unread = state.memos.read().await.unread(account);
+    if unread == 0 {
+        return String::new();
+    }
+    service_notice(state, MEMOSERV, nick, &format!("You have {} new memos, LIST to see them", unread))
+}
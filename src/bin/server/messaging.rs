@@ -0,0 +1,159 @@
+//! delivery of messages to other connected users
+
+use tokio::io::AsyncWriteExt;
+use tokio::sync::RwLock;
+use tokio::sync::mpsc::Sender;
+use tokio::sync::mpsc::error::TrySendError;
+
+use networkingirc::command::{ChannelMode, UserMode};
+use networkingirc::response::*;
+use std::sync::Arc;
+
+use std::collections::{HashMap, HashSet};
+
+use crate::connection::Connection;
+use crate::channels::put_message;
+use crate::links::{announce_quit, relay_message};
+use crate::services::{builtin_service, query_service};
+use crate::{find_nick, Outgoing, ServerState};
+
+/// queues a line on the connections of the given users
+/// a connection whose queue is full is dropped rather than waited on, since
+/// waiting here could deadlock two sessions that are each delivering to the other
+pub async fn deliver(connections: &Arc<RwLock<HashMap<u64, Sender<Outgoing>>>>, recipients: &[u64], line: &str) {
+    let read = connections.read().await;
+    let full = recipients.iter()
+        .filter_map(|recipient| read.get(recipient).map(|sender| (*recipient, sender)))
+        .filter(|(_, sender)| matches!(sender.try_send(Outgoing::Line(line.to_string())), Err(TrySendError::Full(_))))
+        .map(|(recipient, _)| recipient)
+        .collect::<Vec<u64>>();
+    std::mem::drop(read);
+    // a closed queue means that user is already disconnecting
+    sendq_exceeded(connections, &full).await;
+}
+
+/// sends a final line to a user and has their session close the connection
+pub async fn close_connection(connections: &Arc<RwLock<HashMap<u64, Sender<Outgoing>>>>, id: u64, line: &str) {
+    let full = match connections.read().await.get(&id) {
+        Some(sender) => matches!(sender.try_send(Outgoing::Close(line.to_string())), Err(TrySendError::Full(_))),
+        None => false,
+    };
+    if full {
+        sendq_exceeded(connections, &[id]).await;
+    }
+}
+
+/// drops the queues of connections that fell too far behind, their sessions
+/// see the queue close and disconnect with "SendQ exceeded"
+async fn sendq_exceeded(connections: &Arc<RwLock<HashMap<u64, Sender<Outgoing>>>>, ids: &[u64]) {
+    if ids.is_empty() {
+        return;
+    }
+    let mut write = connections.write().await;
+    for id in ids {
+        write.remove(id);
+        println!("dropping connection {}: SendQ exceeded", id);
+    }
+    std::mem::drop(write);
+}
+
+/// tells everyone sharing a channel with the user that they quit, then removes
+/// them from every channel so calling this twice only announces the quit once
+pub async fn quit_user(state: &ServerState, id: u64, reason: &str) {
+    let sig = match state.users.read().await.get(&id) {
+        Some(user) => user.sig(),
+        None => return,
+    };
+    let mut recipients = HashSet::new();
+    let mut write = state.channels.write().await;
+    for channel in write.values_mut().filter(|channel| channel.has_user(id)) {
+        recipients.extend(channel.users().iter().filter(|member| **member != id).cloned());
+        channel.leave(id);
+    }
+    std::mem::drop(write);
+    let recipients = recipients.into_iter().collect::<Vec<u64>>();
+    deliver(&state.connections, &recipients, &format!(":{} QUIT :{}\r\n", sig, reason)).await;
+    announce_quit(state, id, reason).await;
+}
+
+/// sends a notice to every user with +s
+pub async fn server_notice(state: &ServerState, text: &str) {
+    let read = state.users.read().await;
+    let recipients = read.iter()
+        .filter(|(_, user)| user.has_mode(&UserMode::NoticeList))
+        .map(|(uid, user)| (*uid, user.display_nick().to_string()))
+        .collect::<Vec<(u64, String)>>();
+    std::mem::drop(read);
+    for (uid, nick) in recipients {
+        deliver(&state.connections, &[uid], &format!(":{} NOTICE {} :*** Notice -- {}\r\n", state.name, nick, text)).await;
+    }
+}
+
+/// delivers a PRIVMSG or NOTICE to every member of a channel or to a single user,
+/// or hands it to a service
+pub async fn send_message(
+    state: &ServerState,
+    id: u64,
+    kind: &str,
+    msgtarget: &str,
+    text: &str,
+    stream: &mut Connection,
+) -> Result<(), std::io::Error> {
+    let (nick, sig) = match state.users.write().await.get_mut(&id) {
+        Some(user) => {
+            user.touch();
+            (user.display_nick().to_string(), user.sig())
+        },
+        None => return Ok(()),
+    };
+    // services never answer a NOTICE, so two of them can't keep answering each other
+    if builtin_service(msgtarget).is_some() {
+        if kind == "PRIVMSG" {
+            query_service(state, id, msgtarget, text, stream).await?;
+        }
+        return Ok(());
+    }
+    let line = format!(":{} {} {} :{}\r\n", sig, kind, msgtarget, text);
+
+    let channel_read = state.channels.read().await;
+    if let Some(channel) = channel_read.get(msgtarget) {
+        let outside = !channel.has_user(id) && channel.modes().contains(&ChannelMode::NoOutSideClients);
+        // only operators and voiced members speak in a moderated channel
+        let silenced = channel.modes().contains(&ChannelMode::ModeratedChannel) && !channel.is_operator(id) && !channel.is_voiced(id);
+        if outside || silenced {
+            std::mem::drop(channel_read);
+            return stream.write_all(numeric(&state.name, ERR_CANNOTSENDTOCHAN, &nick, &format!("{} :Cannot send to channel", msgtarget)).as_bytes()).await;
+        }
+        let recipients = channel.users().iter()
+            .filter(|member| **member != id)
+            .cloned()
+            .collect::<Vec<u64>>();
+        std::mem::drop(channel_read);
+        // stored first so the time the members see is never earlier than the one in history
+        put_message(msgtarget, &nick, text, &state.messages).await;
+        deliver(&state.connections, &recipients, &line).await;
+        relay_message(state, id, kind, msgtarget, text).await;
+        return Ok(());
+    }
+    std::mem::drop(channel_read);
+
+    let user_read = state.users.read().await;
+    let recipient = find_nick(&user_read, msgtarget)
+        .map(|(uid, user)| (*uid, away_reply(&state.name, &nick, kind, user)));
+    std::mem::drop(user_read);
+
+    match recipient {
+        Some((uid, away)) => {
+            deliver(&state.connections, &[uid], &line).await;
+            relay_message(state, id, kind, msgtarget, text).await;
+            match away {
+                Some(reply) => stream.write_all(reply.as_bytes()).await,
+                None => Ok(()),
+            }
+        },
+        None if kind == "PRIVMSG" => {
+            stream.write_all(numeric(&state.name, ERR_NOSUCHNICK, &nick, &format!("{} :No such nick/channel", msgtarget)).as_bytes()).await
+        },
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,296 @@
+//! the account service, registers nicknames as accounts to log in to with IDENTIFY or SASL
+//! and renames users who take a registered nickname without identifying for it
+
+use tokio::io::AsyncWriteExt;
+use tokio::task;
+use tokio::time::{sleep, Duration};
+
+use networkingirc::account::Account;
+use networkingirc::auth::{hash_password_async, verify_password_async};
+use networkingirc::response::*;
+use networkingirc::unix_time;
+
+use crate::chanserv::forget_account;
+use crate::connection::Connection;
+use crate::memoserv::unread_memos;
+use crate::messaging::deliver;
+use crate::sasl::logged_in;
+use crate::services::{service_notice, NICKSERV};
+use crate::snapshots::save_snapshot;
+use crate::users::force_nick;
+use crate::{find_nick, login_failed, ServerState};
+
+/// each command's syntax is the part before the " - "
+const HELP: [&str; 6] = [
+    "REGISTER <password> - registers your current nickname as an account",
+    "IDENTIFY [account] <password> - logs you in to an account",
+    "GROUP - adds your current nickname to the account you are logged in to",
+    "DROP <password> - deletes the account you are logged in to",
+    "SET PASSWORD <password> - changes the password of the account you are logged in to",
+    "HELP - shows this list",
+];
+
+/// the user a command came from
+struct Caller {
+    id: u64,
+    nick: String,
+    /// nick!user@host
+    mask: String,
+    account: Option<String>,
+}
+
+pub async fn nickserv(
+    state: &ServerState,
+    id: u64,
+    text: &str,
+    stream: &mut Connection,
+) -> Result<(), std::io::Error> {
+    let caller = match state.users.read().await.get(&id) {
+        Some(user) => Caller {
+            id,
+            nick: user.display_nick().to_string(),
+            mask: user.sig(),
+            account: user.account().clone(),
+        },
+        None => return Ok(()),
+    };
+    let mut words = text.split_whitespace();
+    let command = words.next().unwrap_or_default().to_ascii_uppercase();
+    let args = words.collect::<Vec<&str>>();
+    let notice = |text: &str| service_notice(state, NICKSERV, &caller.nick, text);
+
+    let outstring = match (command.as_str(), args.as_slice()) {
+        ("REGISTER", [password]) => register(state, &caller, password).await,
+        ("IDENTIFY", [password]) => identify(state, &caller, &caller.nick, password, stream).await?,
+        ("IDENTIFY", [name, password]) => identify(state, &caller, name, password, stream).await?,
+        ("GROUP", []) => group(state, &caller).await,
+        ("DROP", [password]) => drop_account(state, &caller, password, stream).await?,
+        ("SET", [setting, password]) if setting.eq_ignore_ascii_case("PASSWORD") => set_password(state, &caller, password).await,
+        ("HELP", _) | ("", _) => HELP.iter().map(|line| notice(line)).collect(),
+        _ => match HELP.iter().find(|line| line.split(' ').next() == Some(command.as_str())) {
+            Some(line) => notice(&format!("Syntax: {}", line.split(" - ").next().unwrap_or_default())),
+            None => notice(&format!("Unknown command {}, see HELP", command)),
+        },
+    };
+    stream.write_all(outstring.as_bytes()).await
+}
+
+async fn login(state: &ServerState, id: u64, account: &str) {
+    if let Some(user) = state.users.write().await.get_mut(&id) {
+        user.login(Some(account.to_string()));
+    }
+}
+
+async fn register(state: &ServerState, caller: &Caller, password: &str) -> String {
+    let notice = |text: &str| service_notice(state, NICKSERV, &caller.nick, text);
+    if let Some(account) = &caller.account {
+        return notice(&format!("You are already logged in as {}", account));
+    }
+    if state.accounts.read().await.find_by_nick(&caller.nick).is_some() {
+        return notice(&format!("{} is already registered", caller.nick));
+    }
+    let hash = match hash_password_async(password).await {
+        Some(hash) => hash,
+        None => return notice("Your password couldn't be stored, please try again"),
+    };
+    // someone may have registered the nickname while the password was hashed
+    if !state.accounts.write().await.insert(Account::new(caller.nick.clone(), hash, unix_time())) {
+        return notice(&format!("{} is already registered", caller.nick));
+    }
+    login(state, caller.id, &caller.nick).await;
+    save_snapshot(state).await;
+    println!("NickServ: {} registered the account {}", caller.mask, caller.nick);
+
+    let mut outstring = logged_in(state, &caller.nick, &caller.mask, &caller.nick);
+    outstring.push_str(&notice(&format!("{} is now registered to you", caller.nick)));
+    outstring
+}
+
+/// the account can be named by any nickname grouped to it
+async fn identify(state: &ServerState, caller: &Caller, name: &str, password: &str, stream: &mut Connection) -> Result<String, std::io::Error> {
+    let notice = |text: &str| service_notice(state, NICKSERV, &caller.nick, text);
+    if let Some(account) = &caller.account {
+        return Ok(notice(&format!("You are already logged in as {}", account)));
+    }
+    let found = state.accounts.read().await
+        .find_by_nick(name)
+        .map(|account| (account.name.clone(), account.password.clone()));
+    let (account, hash) = match found {
+        Some(found) => found,
+        None => return Ok(notice(&format!("{} isn't registered", name))),
+    };
+    let locked = state.login_failures.read().await.is_locked(&account, unix_time());
+    let verified = !locked && verify_password_async(password, &hash).await;
+    println!("NickServ: IDENTIFY by {} for {}: {}", caller.mask, account, match (verified, locked) {
+        (true, _) => "success",
+        (false, true) => "too many failed attempts",
+        (false, false) => "wrong password",
+    });
+    if !verified {
+        login_failed(state, &account, stream).await?;
+        return Ok(notice(&format!("Invalid password for {}", account)));
+    }
+    state.login_failures.write().await.succeed(&account);
+    login(state, caller.id, &account).await;
+
+    let mut outstring = logged_in(state, &caller.nick, &caller.mask, &account);
+    outstring.push_str(&notice(&format!("You are now identified for {}", account)));
+    outstring.push_str(&unread_memos(state, &caller.nick, &account).await);
+    Ok(outstring)
+}
+
+async fn group(state: &ServerState, caller: &Caller) -> String {
+    let notice = |text: &str| service_notice(state, NICKSERV, &caller.nick, text);
+    let account = match &caller.account {
+        Some(account) => account,
+        None => return notice("You need to IDENTIFY before you can GROUP"),
+    };
+    let mut write = state.accounts.write().await;
+    if let Some(owner) = write.find_by_nick(&caller.nick) {
+        if owner.name.eq_ignore_ascii_case(account) {
+            return notice(&format!("{} is already part of your account", caller.nick));
+        }
+        return notice(&format!("{} is registered to another account", caller.nick));
+    }
+    match write.get_mut(account) {
+        Some(owner) => owner.nicks.push(caller.nick.clone()),
+        None => return notice("Your account no longer exists"),
+    }
+    std::mem::drop(write);
+    save_snapshot(state).await;
+    notice(&format!("{} is now grouped to {}", caller.nick, account))
+}
+
+/// every session logged in to the account is logged out, the channels it founded are dropped
+/// and the memos left for it are deleted
+/// wrong passwords count towards the same lockout as IDENTIFY
+async fn drop_account(state: &ServerState, caller: &Caller, password: &str, stream: &mut Connection) -> Result<String, std::io::Error> {
+    let notice = |text: &str| service_notice(state, NICKSERV, &caller.nick, text);
+    let account = match &caller.account {
+        Some(account) => account,
+        None => return Ok(notice("You need to IDENTIFY before you can DROP")),
+    };
+    let hash = match state.accounts.read().await.get(account) {
+        Some(found) => found.password.clone(),
+        None => return Ok(notice("Your account no longer exists")),
+    };
+    let locked = state.login_failures.read().await.is_locked(account, unix_time());
+    if locked || !verify_password_async(password, &hash).await {
+        println!("NickServ: DROP by {} for {}: {}", caller.mask, account, if locked { "too many failed attempts" }else{ "wrong password" });
+        login_failed(state, account, stream).await?;
+        return Ok(notice(&format!("Invalid password for {}", account)));
+    }
+    state.login_failures.write().await.succeed(account);
+    state.accounts.write().await.remove(account);
+    forget_account(state, account).await;
+    state.memos.write().await.clear(account);
+
+    let mut logged_out = Vec::new();
+    for (uid, user) in state.users.write().await.iter_mut() {
+        if user.account().as_deref() == Some(account.as_str()) {
+            user.login(None);
+            logged_out.push((*uid, numeric(&state.name, RPL_LOGGEDOUT, user.display_nick(), &format!("{} :You are now logged out", user.sig()))));
+        }
+    }
+    let mut outstring = String::new();
+    for (uid, line) in logged_out {
+        if uid == caller.id {
+            outstring.push_str(&line);
+        }else{
+            deliver(&state.connections, &[uid], &line).await;
+        }
+    }
+    save_snapshot(state).await;
+    println!("NickServ: {} dropped the account {}", caller.mask, account);
+    outstring.push_str(&notice(&format!("{} has been dropped", account)));
+    Ok(outstring)
+}
+
+async fn set_password(state: &ServerState, caller: &Caller, password: &str) -> String {
+    let notice = |text: &str| service_notice(state, NICKSERV, &caller.nick, text);
+    let account = match &caller.account {
+        Some(account) => account,
+        None => return notice("You need to IDENTIFY before you can change your password"),
+    };
+    let hash = match hash_password_async(password).await {
+        Some(hash) => hash,
+        None => return notice("Your password couldn't be stored, please try again"),
+    };
+    match state.accounts.write().await.get_mut(account) {
+        Some(found) => found.password = hash,
+        None => return notice("Your account no longer exists"),
+    }
+    save_snapshot(state).await;
+    notice(&format!("The password for {} has been changed", account))
+}
+
+/// warns a user who is using a registered nickname without being logged in to its
+/// account, and renames them if they still haven't identified once the grace period is over
+pub async fn protect_nick(
+    state: &ServerState,
+    id: u64,
+    stream: &mut Connection,
+) -> Result<(), std::io::Error> {
+    let (nick, account) = match state.users.read().await.get(&id) {
+        Some(user) => (user.display_nick().to_string(), user.account().clone()),
+        None => return Ok(()),
+    };
+    // the grace period for the nickname the user had before is over once they change it
+    if let Some(timer) = state.nick_timers.write().await.remove(&id) {
+        timer.abort();
+    }
+    if !needs_identify(state, &nick, &account).await {
+        return Ok(());
+    }
+    let grace = state.config.read().await.services.nick_grace;
+    let text = format!("{} is registered, IDENTIFY within {} seconds or you will be renamed", nick, grace);
+    stream.write_all(service_notice(state, NICKSERV, &nick, &text).as_bytes()).await?;
+
+    let timer_state = state.clone();
+    let timer = task::spawn(async move {
+        sleep(Duration::from_secs(grace)).await;
+        timer_state.nick_timers.write().await.remove(&id);
+        enforce_nick(&timer_state, id, &nick).await;
+    });
+    state.nick_timers.write().await.insert(id, timer);
+    Ok(())
+}
+
+/// true when the nickname is registered to an account other than the one given
+async fn needs_identify(state: &ServerState, nick: &str, account: &Option<String>) -> bool {
+    match state.accounts.read().await.find_by_nick(nick) {
+        Some(owner) => account.as_deref().map(|account| !owner.name.eq_ignore_ascii_case(account)).unwrap_or(true),
+        None => false,
+    }
+}
+
+/// renames the user when they are still using the nickname without having identified
+async fn enforce_nick(state: &ServerState, id: u64, nick: &str) {
+    let account = match state.users.read().await.get(&id) {
+        Some(user) if user.display_nick().eq_ignore_ascii_case(nick) => user.account().clone(),
+        _ => return,
+    };
+    if !needs_identify(state, nick, &account).await {
+        return;
+    }
+    let guest = guest_nick(state, id).await;
+    println!("NickServ: renaming {} to {}, they didn't identify", nick, guest);
+    force_nick(state, id, &guest).await;
+    let text = format!("You didn't identify for {} in time and have been renamed to {}", nick, guest);
+    deliver(&state.connections, &[id], &service_notice(state, NICKSERV, &guest, &text)).await;
+}
+
+/// a nickname made of the guest prefix and a number that nobody is using or has registered
+pub async fn guest_nick(state: &ServerState, id: u64) -> String {
+    let prefix = state.config.read().await.services.guest_prefix.clone();
+    let mut number = (unix_time() ^ id) % 100000;
+    loop {
+        let nick = format!("{}{}", prefix, number);
+        let taken = find_nick(&*state.users.read().await, &nick).is_some()
+            || state.accounts.read().await.find_by_nick(&nick).is_some();
+        if !taken {
+            return nick;
+        }
+        number = (number + 1) % 100000;
+    }
+}
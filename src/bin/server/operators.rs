@@ -0,0 +1,295 @@
+//! operator authentication and the commands only operators may use
+
+use tokio::io::AsyncWriteExt;
+use tokio::time::{sleep, Duration, Instant};
+
+use networkingirc::command::UserMode;
+use networkingirc::config::ServerConfig;
+use networkingirc::response::*;
+use networkingirc::unix_time;
+
+use crate::connection::Connection;
+use crate::links::{announce_user_mode, kill_remote};
+use crate::channels::load_default_channels;
+use crate::messaging::{close_connection, deliver, quit_user, server_notice};
+use crate::snapshots::{flush_history, save_snapshot};
+use crate::{find_nick, load_tls, login_failed, ServerState};
+
+/// how long DIE and RESTART wait for other clients to be sent their final lines
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(3);
+
+pub async fn oper(
+    state: &ServerState,
+    id: u64,
+    name: &str,
+    password: &str,
+    stream: &mut Connection,
+) -> Result<(), std::io::Error> {
+    let user = match state.users.read().await.get(&id) {
+        Some(user) => user.clone(),
+        None => return Ok(()),
+    };
+    let (nick, sig, ip) = (user.display_nick().to_string(), user.sig(), user.ip_address().to_string());
+    let block = state.config.read().await.opers.iter().find(|block| block.name == name).cloned();
+    // oper blocks are counted apart from accounts, which can't have spaces in their names
+    let failures = format!("oper {}", name);
+    let locked = state.login_failures.read().await.is_locked(&failures, unix_time());
+
+    let (code, reply, outcome) = match block {
+        _ if user.has_mode(&UserMode::Restricted) => (ERR_RESTRICTED, ":Your connection is restricted!", "restricted connection"),
+        None => (ERR_NOOPERHOST, ":No O-lines for your host", "no such oper block"),
+        Some(block) if !block.host_allowed(&user) => (ERR_NOOPERHOST, ":No O-lines for your host", "host not allowed"),
+        Some(_) if locked => (ERR_PASSWDMISMATCH, ":Password incorrect", "too many failed attempts"),
+        Some(block) => {
+            if block.verify(password).await {
+                (RPL_YOUREOPER, ":You are now an IRC operator", "success")
+            }else{
+                (ERR_PASSWDMISMATCH, ":Password incorrect", "wrong password")
+            }
+        },
+    };
+    println!("OPER attempt by {} ({}) for block {:?}: {}", sig, ip, name, outcome);
+    match code {
+        RPL_YOUREOPER => state.login_failures.write().await.succeed(&failures),
+        ERR_PASSWDMISMATCH => login_failed(state, &failures, stream).await?,
+        _ => {},
+    }
+
+    let mut outstring = String::new();
+    if code == RPL_YOUREOPER {
+        let added = state.users.write().await.get_mut(&id).map(|user| user.add_mode(UserMode::Operator)).unwrap_or(false);
+        if added {
+            outstring.push_str(&format!(":{} MODE {} :+o\r\n", nick, nick));
+            announce_user_mode(state, id, "+o").await;
+        }
+    }
+    outstring.push_str(&numeric(&state.name, code, &nick, reply));
+    stream.write_all(outstring.as_bytes()).await
+}
+
+/// returns the nick and signature of the user if they are an operator,
+/// otherwise tells them they aren't and returns None
+pub async fn require_oper(
+    state: &ServerState,
+    id: u64,
+    stream: &mut Connection,
+) -> Result<Option<(String, String)>, std::io::Error> {
+    let (nick, sig, is_oper) = match state.users.read().await.get(&id) {
+        Some(user) => (user.display_nick().to_string(), user.sig(), user.has_mode(&UserMode::Operator)),
+        None => return Ok(None),
+    };
+    if is_oper {
+        return Ok(Some((nick, sig)));
+    }
+    stream.write_all(numeric(&state.name, ERR_NOPRIVILEGES, &nick, ":Permission Denied- You're not an IRC operator").as_bytes()).await?;
+    Ok(None)
+}
+
+pub async fn kill(
+    state: &ServerState,
+    id: u64,
+    target: &str,
+    comment: &str,
+    stream: &mut Connection,
+) -> Result<(), std::io::Error> {
+    let (nick, sig) = match require_oper(state, id, stream).await? {
+        Some(oper) => oper,
+        None => return Ok(()),
+    };
+    let victim = find_nick(&*state.users.read().await, target)
+        .map(|(uid, user)| (*uid, user.display_nick().to_string()));
+    let (victim_id, victim_nick) = match victim {
+        Some(victim) => victim,
+        None => {
+            return stream.write_all(numeric(&state.name, ERR_NOSUCHNICK, &nick, &format!("{} :No such nick/channel", target)).as_bytes()).await;
+        },
+    };
+    let (comment, reason) = kill_reason(&nick, comment);
+    println!("KILL of {} by {}: {}", victim_nick, sig, comment);
+
+    // a user on another server is closed by their own server
+    if state.network.read().await.is_remote(victim_id) {
+        kill_remote(state, id, victim_id, comment, &reason).await;
+    }else{
+        quit_user(state, victim_id, &reason).await;
+        let lines = format!(
+            ":{} KILL {} :{}\r\nERROR :Closing Link: {} ({})\r\n",
+            sig, victim_nick, comment, state.name, reason
+        );
+        close_connection(&state.connections, victim_id, &lines).await;
+    }
+    server_notice(state, &format!("Received KILL message for {} from {} ({})", victim_nick, nick, comment)).await;
+    Ok(())
+}
+
+/// sends a message to every user with +w
+pub async fn wallops(
+    state: &ServerState,
+    id: u64,
+    text: &str,
+    stream: &mut Connection,
+) -> Result<(), std::io::Error> {
+    let (_, sig) = match require_oper(state, id, stream).await? {
+        Some(oper) => oper,
+        None => return Ok(()),
+    };
+    let recipients = state.users.read().await.iter()
+        .filter(|(_, user)| user.has_mode(&UserMode::Wallops))
+        .map(|(uid, _)| *uid)
+        .collect::<Vec<u64>>();
+    deliver(&state.connections, &recipients, &format!(":{} WALLOPS :{}\r\n", sig, text)).await;
+    Ok(())
+}
+
+/// rereads the config file the server was started with and swaps it in
+/// the server name and listen addresses only change on a restart
+pub async fn rehash(
+    state: &ServerState,
+    id: u64,
+    stream: &mut Connection,
+) -> Result<(), std::io::Error> {
+    let (nick, sig) = match require_oper(state, id, stream).await? {
+        Some(oper) => oper,
+        None => return Ok(()),
+    };
+    let path = match &state.config_path {
+        Some(path) => path,
+        None => {
+            let line = format!(":{} NOTICE {} :*** The server was started without a config file\r\n", state.name, nick);
+            return stream.write_all(line.as_bytes()).await;
+        },
+    };
+    stream.write_all(numeric(&state.name, RPL_REHASHING, &nick, &format!("{} :Rehashing", path.display())).as_bytes()).await?;
+
+    let loaded = ServerConfig::read_async(path).await.and_then(|mut config| {
+        if let Some(address) = &state.address_override {
+            config.server.listen = vec![address.clone()];
+        }
+        config.validate().map(|_| config)
+    });
+    let mut config = match loaded {
+        Ok(config) => config,
+        Err(e) => {
+            println!("REHASH by {} failed: {}", sig, e);
+            // toml errors span several lines with a snippet of the file
+            let error = e.to_string().split_whitespace().collect::<Vec<&str>>().join(" ");
+            let line = format!(":{} NOTICE {} :*** Rehash failed, keeping the old config: {}\r\n", state.name, nick, error);
+            return stream.write_all(line.as_bytes()).await;
+        },
+    };
+
+    // users keep the masked host they connected with, an unset key keeps the random one
+    if config.server.cloak_key.is_empty() {
+        config.server.cloak_key = state.config.read().await.server.cloak_key.clone();
+    }
+
+    let tls = match load_tls(&config) {
+        Ok(tls) => tls,
+        Err(e) => {
+            println!("REHASH by {} failed: {}", sig, e);
+            let line = format!(":{} NOTICE {} :*** Rehash failed, keeping the old config: {}\r\n", state.name, nick, e);
+            return stream.write_all(line.as_bytes()).await;
+        },
+    };
+
+    load_default_channels(&config, &state.messages).await;
+    *state.tls.write().await = tls;
+    let mut write = state.config.write().await;
+    let needs_restart = write.needs_restart(&config);
+    *write = config;
+    std::mem::drop(write);
+
+    if needs_restart {
+        let line = format!(":{} NOTICE {} :*** The server name and listen addresses change on RESTART\r\n", state.name, nick);
+        stream.write_all(line.as_bytes()).await?;
+    }
+    println!("REHASH of {} by {}", path.display(), sig);
+    server_notice(state, &format!("{} is rehashing the server config", nick)).await;
+    Ok(())
+}
+
+/// closes every connection including the links to other servers, waiting a moment
+/// for other clients to be sent their final lines before returning
+async fn close_all(state: &ServerState, id: u64, nick: &str, reason: &str, stream: &mut Connection) -> Result<(), std::io::Error> {
+    let links = state.network.read().await.links().iter().map(|server| server.link).collect::<Vec<u64>>();
+    for link in links {
+        close_connection(&state.connections, link, &format!("ERROR :Closing Link: {} ({})\r\n", state.name, reason)).await;
+    }
+    let network = state.network.read().await.clone();
+    let recipients = state.users.read().await.iter()
+        .filter(|(uid, _)| **uid != id && !network.is_remote(**uid))
+        .map(|(uid, user)| (*uid, user.display_nick().to_string()))
+        .collect::<Vec<(u64, String)>>();
+    for (uid, recipient) in recipients {
+        let lines = format!(
+            ":{} NOTICE {} :*** {}\r\nERROR :Closing Link: {} ({})\r\n",
+            state.name, recipient, reason, state.name, reason
+        );
+        close_connection(&state.connections, uid, &lines).await;
+    }
+    let lines = format!(
+        ":{} NOTICE {} :*** {}\r\nERROR :Closing Link: {} ({})\r\n",
+        state.name, nick, reason, state.name, reason
+    );
+    stream.write_all(lines.as_bytes()).await?;
+    stream.shutdown().await?;
+
+    let deadline = Instant::now() + SHUTDOWN_GRACE;
+    while Instant::now() < deadline {
+        let remaining = state.connections.read().await.keys().filter(|uid| **uid != id).count();
+        if remaining == 0 {
+            break;
+        }
+        sleep(Duration::from_millis(50)).await;
+    }
+    Ok(())
+}
+
+pub async fn die(
+    state: &ServerState,
+    id: u64,
+    stream: &mut Connection,
+) -> Result<(), std::io::Error> {
+    let (nick, sig) = match require_oper(state, id, stream).await? {
+        Some(oper) => oper,
+        None => return Ok(()),
+    };
+    println!("DIE by {}", sig);
+    close_all(state, id, &nick, &format!("Server terminating by request of {}", nick), stream).await?;
+    flush_history(state).await;
+    save_snapshot(state).await;
+    std::process::exit(0);
+}
+
+/// replaces the running server with a fresh copy started with the same arguments
+pub async fn restart(
+    state: &ServerState,
+    id: u64,
+    stream: &mut Connection,
+) -> Result<(), std::io::Error> {
+    let (nick, sig) = match require_oper(state, id, stream).await? {
+        Some(oper) => oper,
+        None => return Ok(()),
+    };
+    println!("RESTART by {}", sig);
+    close_all(state, id, &nick, &format!("Server restarting by request of {}", nick), stream).await?;
+    flush_history(state).await;
+    save_snapshot(state).await;
+
+    let exe = std::env::current_exe()?;
+    let mut command = std::process::Command::new(exe);
+    command.args(std::env::args_os().skip(1));
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        // exec only returns if it failed
+        let e = command.exec();
+        eprintln!("couldn't restart the server: {}", e);
+        std::process::exit(1);
+    }
+    #[cfg(not(unix))]
+    {
+        command.spawn()?;
+        std::process::exit(0);
+    }
+}
@@ -0,0 +1,217 @@
+//! queries about other users: WHO, WHOIS, WHOWAS, USERHOST and ISON
+
+use tokio::io::AsyncWriteExt;
+
+use networkingirc::command::{UserMode, WhoxQuery};
+use networkingirc::response::*;
+use networkingirc::{format_time, wildcard_match};
+
+use crate::connection::Connection;
+use crate::{find_nick, reply_target, shares_channel, ServerState};
+
+pub async fn userhost(
+    state: &ServerState,
+    id: u64,
+    nicks: &[String],
+    stream: &mut Connection,
+) -> Result<(), std::io::Error> {
+    let target = reply_target(&state.users, id).await;
+    if nicks.is_empty() {
+        return stream.write_all(numeric(&state.name, ERR_NEEDMOREPARAMS, &target, "USERHOST :Not enough parameters").as_bytes()).await;
+    }
+    let read = state.users.read().await;
+    // at most five nicknames are answered per query
+    let replies = nicks.iter()
+        .take(5)
+        .filter_map(|nick| find_nick(&read, nick))
+        .map(|(_, user)| format!("{}{}={}{}",
+            user.display_nick(),
+            if user.has_mode(&UserMode::Operator) { "*" } else { "" },
+            if user.away_message().is_some() { "-" } else { "+" },
+            user.visible_host()))
+        .collect::<Vec<String>>();
+    std::mem::drop(read);
+    stream.write_all(numeric(&state.name, RPL_USERHOST, &target, &format!(":{}", replies.join(" "))).as_bytes()).await
+}
+
+pub async fn ison(
+    state: &ServerState,
+    id: u64,
+    nicks: &[String],
+    stream: &mut Connection,
+) -> Result<(), std::io::Error> {
+    let target = reply_target(&state.users, id).await;
+    if nicks.is_empty() {
+        return stream.write_all(numeric(&state.name, ERR_NEEDMOREPARAMS, &target, "ISON :Not enough parameters").as_bytes()).await;
+    }
+    let read = state.users.read().await;
+    let online = nicks.iter()
+        .filter_map(|nick| find_nick(&read, nick))
+        .map(|(_, user)| user.display_nick().to_string())
+        .collect::<Vec<String>>();
+    std::mem::drop(read);
+    stream.write_all(numeric(&state.name, RPL_ISON, &target, &format!(":{}", online.join(" "))).as_bytes()).await
+}
+
+pub async fn who(
+    state: &ServerState,
+    id: u64,
+    mask: Option<&str>,
+    operators: bool,
+    whox: Option<&WhoxQuery>,
+    stream: &mut Connection,
+) -> Result<(), std::io::Error> {
+    let target = reply_target(&state.users, id).await;
+    let network = state.network.read().await.clone();
+    let server_of = |uid: u64| network.server_of(uid).map(|server| server.name.as_str()).unwrap_or(state.name.as_str());
+    let hops_to = |uid: u64| network.server_of(uid).map(|server| server.hops).unwrap_or(0);
+    let channel_read = state.channels.read().await;
+    let user_read = state.users.read().await;
+    let mut outstring = String::new();
+
+    // "0" and "*" are the same as giving no mask at all
+    let mask = mask.filter(|mask| *mask != "0" && *mask != "*");
+    let end_name = mask.unwrap_or("*").to_string();
+
+    match mask.and_then(|mask| channel_read.get(mask)) {
+        Some(channel) => {
+            let member = channel.has_user(id);
+            if member || !channel.is_hidden() {
+                for uid in channel.users().iter() {
+                    if let Some(user) = user_read.get(uid) {
+                        if operators && !user.has_mode(&UserMode::Operator) {
+                            continue;
+                        }
+                        // invisible users are only seen by people in the same channel
+                        if !member && user.has_mode(&UserMode::Invisible) {
+                            continue;
+                        }
+                        let status = if channel.is_operator(*uid) {
+                            "@"
+                        }else if channel.is_voiced(*uid) {
+                            "+"
+                        }else{
+                            ""
+                        };
+                        let context = WhoContext { channel: channel.name(), status, server: server_of(*uid), hops: hops_to(*uid) };
+                        outstring.push_str(&who_reply(&state.name, &target, user, &context, whox));
+                    }
+                }
+            }
+        },
+        None => {
+            for (uid, user) in user_read.iter() {
+                if operators && !user.has_mode(&UserMode::Operator) {
+                    continue;
+                }
+                if *uid != id && user.has_mode(&UserMode::Invisible) && !shares_channel(&channel_read, id, *uid) {
+                    continue;
+                }
+                let matched = match mask {
+                    Some(mask) => [user.display_nick(), &user.visible_host(), server_of(*uid), user.realname()]
+                        .iter()
+                        .any(|field| wildcard_match(mask, field)),
+                    None => true,
+                };
+                if matched {
+                    let context = WhoContext { channel: "*", status: "", server: server_of(*uid), hops: hops_to(*uid) };
+                    outstring.push_str(&who_reply(&state.name, &target, user, &context, whox));
+                }
+            }
+        },
+    }
+    std::mem::drop(user_read);
+    std::mem::drop(channel_read);
+    outstring.push_str(&numeric(&state.name, RPL_ENDOFWHO, &target, &format!("{} :End of WHO list", end_name)));
+    stream.write_all(outstring.as_bytes()).await
+}
+
+pub async fn whois(
+    state: &ServerState,
+    id: u64,
+    masks: &str,
+    stream: &mut Connection,
+) -> Result<(), std::io::Error> {
+    let target = reply_target(&state.users, id).await;
+    let description = state.config.read().await.server.description.clone();
+    let network = state.network.read().await.clone();
+    let channel_read = state.channels.read().await;
+    let user_read = state.users.read().await;
+    let mut outstring = String::new();
+
+    if masks.is_empty() {
+        outstring.push_str(&numeric(&state.name, ERR_NONICKNAMEGIVEN, &target, ":No nickname given"));
+    }
+    for mask in masks.split(',').filter(|mask| !mask.is_empty()) {
+        let mut found = false;
+        for (uid, user) in user_read.iter() {
+            if !wildcard_match(mask, user.display_nick()) {
+                continue;
+            }
+            found = true;
+            let nick = user.display_nick();
+            outstring.push_str(&numeric(&state.name, RPL_WHOISUSER, &target,
+                &format!("{} {} {} * :{}", nick, user.username(), user.visible_host(), user.realname())));
+
+            let chanlist = channel_read.values()
+                .filter(|channel| channel.has_user(*uid))
+                .filter(|channel| !channel.is_hidden() || channel.has_user(id))
+                .map(|channel| channel.name())
+                .collect::<Vec<&str>>();
+            if !chanlist.is_empty() {
+                outstring.push_str(&numeric(&state.name, RPL_WHOISCHANNELS, &target, &format!("{} :{}", nick, chanlist.join(" "))));
+            }
+
+            let (server, info) = match network.server_of(*uid) {
+                Some(server) => (server.name.as_str(), server.description.as_str()),
+                None => (state.name.as_str(), description.as_str()),
+            };
+            outstring.push_str(&numeric(&state.name, RPL_WHOISSERVER, &target, &format!("{} {} :{}", nick, server, info)));
+            if let Some(away) = user.away_message() {
+                outstring.push_str(&numeric(&state.name, RPL_AWAY, &target, &format!("{} :{}", nick, away)));
+            }
+            if user.has_mode(&UserMode::Operator) {
+                outstring.push_str(&numeric(&state.name, RPL_WHOISOPERATOR, &target, &format!("{} :is an IRC operator", nick)));
+            }
+            if user.is_secure() {
+                outstring.push_str(&numeric(&state.name, RPL_WHOISSECURE, &target, &format!("{} :is using a secure connection", nick)));
+            }
+            outstring.push_str(&numeric(&state.name, RPL_WHOISIDLE, &target,
+                &format!("{} {} {} :seconds idle, signon time", nick, user.idle(), user.signon())));
+        }
+        if !found {
+            outstring.push_str(&numeric(&state.name, ERR_NOSUCHNICK, &target, &format!("{} :No such nick/channel", mask)));
+        }
+    }
+    std::mem::drop(user_read);
+    std::mem::drop(channel_read);
+    outstring.push_str(&numeric(&state.name, RPL_ENDOFWHOIS, &target, &format!("{} :End of WHOIS list", masks)));
+    stream.write_all(outstring.as_bytes()).await
+}
+
+pub async fn whowas_query(
+    state: &ServerState,
+    id: u64,
+    nicks: &str,
+    count: Option<usize>,
+    stream: &mut Connection,
+) -> Result<(), std::io::Error> {
+    let target = reply_target(&state.users, id).await;
+    let history = state.whowas.read().await;
+    let mut outstring = String::new();
+    for nick in nicks.split(',').filter(|nick| !nick.is_empty()) {
+        let entries = history.lookup(nick, count);
+        if entries.is_empty() {
+            outstring.push_str(&numeric(&state.name, ERR_WASNOSUCHNICK, &target, &format!("{} :There was no such nickname", nick)));
+        }
+        for entry in entries {
+            outstring.push_str(&numeric(&state.name, RPL_WHOWASUSER, &target,
+                &format!("{} {} {} * :{}", entry.nick, entry.username, entry.hostname, entry.realname)));
+            outstring.push_str(&numeric(&state.name, RPL_WHOISSERVER, &target,
+                &format!("{} {} :{}", entry.nick, entry.server, format_time(entry.logoff))));
+        }
+    }
+    std::mem::drop(history);
+    outstring.push_str(&numeric(&state.name, RPL_ENDOFWHOWAS, &target, &format!("{} :End of WHOWAS", nicks)));
+    stream.write_all(outstring.as_bytes()).await
+}
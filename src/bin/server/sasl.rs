@@ -0,0 +1,174 @@
+//! SASL authentication with AUTHENTICATE, PLAIN checks a password against the account
+//! store and EXTERNAL looks up the fingerprint of the client's TLS certificate
+
+use tokio::io::AsyncWriteExt;
+
+use networkingirc::auth::verify_password_async;
+use networkingirc::capability::SASL;
+use networkingirc::response::*;
+use networkingirc::sasl::{Mechanism, PayloadBuffer, PlainCredentials, SaslError, MECHANISMS};
+use networkingirc::unix_time;
+
+use crate::connection::Connection;
+use crate::memoserv::unread_memos;
+use crate::{login_failed, ServerState};
+
+/// the client an AUTHENTICATE is from, before registration the nick may still be "*"
+pub struct SaslClient {
+    pub nick: String,
+    /// nick!user@host, sent back in RPL_LOGGEDIN
+    pub mask: String,
+    /// whether the client asked for the sasl capability
+    pub enabled: bool,
+    /// the account the client is already logged in to
+    pub account: Option<String>,
+}
+
+/// an exchange in progress, kept for the registration or the session it belongs to
+#[derive(Default)]
+pub struct SaslExchange {
+    mechanism: Option<Mechanism>,
+    payload: PayloadBuffer,
+}
+
+/// handles one AUTHENTICATE, returning the account once the client has logged in to it
+pub async fn authenticate(
+    state: &ServerState,
+    client: &SaslClient,
+    exchange: &mut SaslExchange,
+    param: &str,
+    stream: &mut Connection,
+) -> Result<Option<String>, std::io::Error> {
+    let reply = |code: u16, params: &str| numeric(&state.name, code, &client.nick, params);
+    if !client.enabled {
+        stream.write_all(reply(ERR_SASLFAIL, ":SASL authentication failed").as_bytes()).await?;
+        return Ok(None);
+    }
+    if client.account.is_some() {
+        stream.write_all(reply(ERR_SASLALREADY, ":You have already authenticated using SASL").as_bytes()).await?;
+        return Ok(None);
+    }
+    if param == "*" {
+        *exchange = SaslExchange::default();
+        stream.write_all(reply(ERR_SASLABORTED, ":SASL authentication aborted").as_bytes()).await?;
+        return Ok(None);
+    }
+
+    let mechanism = match exchange.mechanism {
+        Some(mechanism) => mechanism,
+        // the first AUTHENTICATE names the mechanism, the server answers with an empty challenge
+        None => {
+            let outstring = match param.parse::<Mechanism>() {
+                Ok(mechanism) => {
+                    exchange.mechanism = Some(mechanism);
+                    String::from("AUTHENTICATE +\r\n")
+                },
+                Err(_) => {
+                    let mut outstring = reply(RPL_SASLMECHS, &format!("{} :are available SASL mechanisms", MECHANISMS));
+                    outstring.push_str(&reply(ERR_SASLFAIL, ":SASL authentication failed"));
+                    outstring
+                },
+            };
+            stream.write_all(outstring.as_bytes()).await?;
+            return Ok(None);
+        },
+    };
+
+    let payload = match exchange.payload.push(param) {
+        Ok(Some(payload)) => payload,
+        Ok(None) => return Ok(None),
+        Err(e) => {
+            *exchange = SaslExchange::default();
+            let outstring = match e {
+                SaslError::Base64 => reply(ERR_SASLFAIL, ":SASL authentication failed"),
+                _ => reply(ERR_SASLTOOLONG, ":SASL message too long"),
+            };
+            stream.write_all(outstring.as_bytes()).await?;
+            return Ok(None);
+        },
+    };
+    *exchange = SaslExchange::default();
+
+    let account = match mechanism {
+        Mechanism::Plain => match PlainCredentials::parse(&payload) {
+            Some(credentials) => {
+                let account = login_plain(state, &credentials).await;
+                match &account {
+                    Some(account) => state.login_failures.write().await.succeed(account),
+                    None => login_failed(state, &credentials.authcid, stream).await?,
+                }
+                account
+            },
+            None => None,
+        },
+        Mechanism::External => login_external(state, &payload, stream.client_fingerprint()).await,
+    };
+    println!(
+        "SASL {} login by {}: {}",
+        mechanism, client.mask, account.as_deref().map(|account| format!("success as {}", account)).unwrap_or_else(|| String::from("failed"))
+    );
+    let outstring = match &account {
+        Some(account) => {
+            let mut outstring = logged_in(state, &client.nick, &client.mask, account);
+            outstring.push_str(&reply(RPL_SASLSUCCESS, ":SASL authentication successful"));
+            outstring
+        },
+        None => reply(ERR_SASLFAIL, ":SASL authentication failed"),
+    };
+    stream.write_all(outstring.as_bytes()).await?;
+    Ok(account)
+}
+
+/// RPL_LOGGEDIN, sent whichever way a user logs in
+pub fn logged_in(state: &ServerState, nick: &str, mask: &str, account: &str) -> String {
+    numeric(&state.name, RPL_LOGGEDIN, nick, &format!("{} {} :You are now logged in as {}", mask, account, account))
+}
+
+/// AUTHENTICATE from a registered client
+pub async fn sasl(
+    state: &ServerState,
+    id: u64,
+    exchange: &mut SaslExchange,
+    param: &str,
+    stream: &mut Connection,
+) -> Result<(), std::io::Error> {
+    let client = match state.users.read().await.get(&id) {
+        Some(user) => SaslClient {
+            nick: user.display_nick().to_string(),
+            mask: user.sig(),
+            enabled: user.enabled_caps().has(SASL),
+            account: user.account().clone(),
+        },
+        None => return Ok(()),
+    };
+    if let Some(account) = authenticate(state, &client, exchange, param, stream).await? {
+        if let Some(user) = state.users.write().await.get_mut(&id) {
+            user.login(Some(account.clone()));
+        }
+        stream.write_all(unread_memos(state, &client.nick, &account).await.as_bytes()).await?;
+    }
+    Ok(())
+}
+
+/// the account named in the credentials when the password matches, a client may
+/// only ask to act as the account it authenticates as
+async fn login_plain(state: &ServerState, credentials: &PlainCredentials) -> Option<String> {
+    if !credentials.authzid.is_empty() && !credentials.authzid.eq_ignore_ascii_case(&credentials.authcid) {
+        return None;
+    }
+    if state.login_failures.read().await.is_locked(&credentials.authcid, unix_time()) {
+        return None;
+    }
+    let (name, hash) = state.accounts.read().await
+        .get(&credentials.authcid)
+        .map(|account| (account.name.clone(), account.password.clone()))?;
+    verify_password_async(&credentials.password, &hash).await.then_some(name)
+}
+
+/// the account the client certificate belongs to, the payload may name it to be sure
+async fn login_external(state: &ServerState, payload: &[u8], fingerprint: Option<String>) -> Option<String> {
+    let fingerprint = fingerprint?;
+    let authzid = std::str::from_utf8(payload).ok()?;
+    let name = state.accounts.read().await.find_by_fingerprint(&fingerprint)?.name.clone();
+    (authzid.is_empty() || authzid.eq_ignore_ascii_case(&name)).then_some(name)
+}
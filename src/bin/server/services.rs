@@ -0,0 +1,293 @@
+//! the services built into the server, pseudo users that take commands over
+//! PRIVMSG or SQUERY and answer with NOTICEs, and the external services that
+//! register with SERVICE and are handed the SQUERY messages sent to them
+
+use std::collections::HashMap;
+
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::time::{sleep_until, Duration, Instant};
+
+use networkingirc::command::Command;
+use networkingirc::config::ConnectionClass;
+use networkingirc::message::Message;
+use networkingirc::response::*;
+use networkingirc::service::Service;
+use networkingirc::{IrcError, LineBuffer};
+
+use crate::chanserv::chanserv;
+use crate::connection::{Connection, Peer};
+use crate::memoserv::memoserv;
+use crate::messaging::deliver;
+use crate::nickserv::nickserv;
+use crate::{find_nick, new_id, reply_target, Outgoing, ServerState};
+
+pub const NICKSERV: &str = "NickServ";
+pub const CHANSERV: &str = "ChanServ";
+pub const MEMOSERV: &str = "MemoServ";
+/// every built in service, users can't take these nicknames
+pub const BUILTIN_SERVICES: [&str; 3] = [NICKSERV, CHANSERV, MEMOSERV];
+
+/// the proper name of a built in service, ignoring case
+pub fn builtin_service(name: &str) -> Option<&'static str> {
+    BUILTIN_SERVICES.iter().find(|service| service.eq_ignore_ascii_case(name)).copied()
+}
+
+/// the nick!user@host a service's messages come from
+pub fn service_prefix(state: &ServerState, service: &str) -> String {
+    format!("{}!{}@{}", service, service, state.name)
+}
+
+/// a NOTICE from a service to a user
+pub fn service_notice(state: &ServerState, service: &str, nick: &str, text: &str) -> String {
+    format!(":{} NOTICE {} :{}\r\n", service_prefix(state, service), nick, text)
+}
+
+/// hands a command to a built in service, returns false when there is no service by that name
+pub async fn query_service(
+    state: &ServerState,
+    id: u64,
+    service: &str,
+    text: &str,
+    stream: &mut Connection,
+) -> Result<bool, std::io::Error> {
+    match builtin_service(service) {
+        Some(NICKSERV) => nickserv(state, id, text, stream).await?,
+        Some(CHANSERV) => chanserv(state, id, text, stream).await?,
+        Some(MEMOSERV) => memoserv(state, id, text, stream).await?,
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+/// finds a registered external service by name, ignoring case
+pub fn find_service<'a>(services: &'a HashMap<u64, Service>, name: &str) -> Option<(&'a u64, &'a Service)> {
+    services.iter().find(|(_, service)| service.name().eq_ignore_ascii_case(name))
+}
+
+/// built in services answer straight away, external ones are sent the SQUERY as is
+pub async fn squery(
+    state: &ServerState,
+    id: u64,
+    service: &str,
+    text: &str,
+    stream: &mut Connection,
+) -> Result<(), std::io::Error> {
+    if query_service(state, id, service, text, stream).await? {
+        return Ok(());
+    }
+    let external = find_service(&*state.services.read().await, service)
+        .map(|(sid, found)| (*sid, found.name().to_string()));
+    let sig = match state.users.read().await.get(&id) {
+        Some(user) => user.sig(),
+        None => return Ok(()),
+    };
+    match external {
+        Some((sid, name)) => {
+            deliver(&state.connections, &[sid], &format!(":{} SQUERY {} :{}\r\n", sig, name, text)).await;
+            Ok(())
+        },
+        None => {
+            let nick = reply_target(&state.users, id).await;
+            stream.write_all(numeric(&state.name, ERR_NOSUCHSERVICE, &nick, &format!("{} :No such service", service)).as_bytes()).await
+        },
+    }
+}
+
+/// lists the built in services and the external ones matching the name mask and type
+pub async fn servlist(
+    state: &ServerState,
+    id: u64,
+    mask: &Option<String>,
+    kind: &Option<String>,
+    stream: &mut Connection,
+) -> Result<(), std::io::Error> {
+    let target = match state.services.read().await.get(&id) {
+        Some(service) => service.name().to_string(),
+        None => reply_target(&state.users, id).await,
+    };
+    let (mask, kind) = (mask.as_deref(), kind.as_deref());
+    let mut outstring = String::new();
+    for name in BUILTIN_SERVICES.iter() {
+        let builtin = Service::new(name.to_string(), String::from("*"), String::from("0"), String::from("built in service"), state.started);
+        if builtin.listed(mask, kind) {
+            outstring.push_str(&servlist_entry(state, &target, &builtin));
+        }
+    }
+    let read = state.services.read().await;
+    let mut external = read.values().filter(|service| service.listed(mask, kind)).collect::<Vec<&Service>>();
+    external.sort_by(|a, b| a.name().cmp(b.name()));
+    for service in external {
+        outstring.push_str(&servlist_entry(state, &target, service));
+    }
+    std::mem::drop(read);
+    outstring.push_str(&numeric(&state.name, RPL_SERVLISTEND, &target, &format!("{} {} :End of service listing", mask.unwrap_or("*"), kind.unwrap_or("*"))));
+    stream.write_all(outstring.as_bytes()).await
+}
+
+fn servlist_entry(state: &ServerState, target: &str, service: &Service) -> String {
+    numeric(
+        &state.name, RPL_SERVLIST, target,
+        &format!("{} {} {} {} 0 :{}", service.name(), state.name, service.distribution(), service.kind(), service.info()),
+    )
+}
+
+/// the name can't be used by a new service when a user, a built in service or another service has it
+async fn service_name_taken(state: &ServerState, name: &str) -> bool {
+    builtin_service(name).is_some()
+        || find_nick(&*state.users.read().await, name).is_some()
+        || find_service(&*state.services.read().await, name).is_some()
+}
+
+/// registers an external service that sent SERVICE, with the password from its PASS,
+/// and serves it until it disconnects
+pub async fn launch_service(
+    state: ServerState,
+    mut stream: Connection,
+    mut lines: LineBuffer,
+    addr: Peer,
+    class: ConnectionClass,
+    service: Service,
+    password: Option<String>,
+) -> Result<(), IrcError> {
+    let block = state.config.read().await.find_service(service.name()).cloned();
+    let ip = addr.ip();
+    let verified = match (block, password) {
+        (Some(block), Some(password)) if block.host_allowed(&ip, &ip) => block.verify(&password).await,
+        _ => false,
+    };
+    let refusal = if !verified {
+        Some((numeric(&state.name, ERR_PASSWDMISMATCH, service.name(), ":Password incorrect"), "Bad Password"))
+    }else if !service.visible_to(&state.name) {
+        Some((numeric(&state.name, ERR_NOSUCHSERVER, service.name(), &format!("{} :No such server", service.distribution())), "Not distributed here"))
+    }else if service_name_taken(&state, service.name()).await {
+        Some((numeric(&state.name, ERR_NICKNAMEINUSE, service.name(), &format!("{} :Nickname is already in use", service.name())), "Name in use"))
+    }else{
+        None
+    };
+    if let Some((reply, reason)) = refusal {
+        println!("refused service {} from {}: {}", service.name(), addr, reason);
+        stream.write_all(format!("{}ERROR :Closing Link: {} ({})\r\n", reply, service.name(), reason).as_bytes()).await?;
+        stream.shutdown().await?;
+        return Err(IrcError::ServiceRefused(reason.to_string()));
+    }
+
+    let id = new_id(&state);
+    let name = service.name().to_string();
+    state.services.write().await.insert(id, service);
+    let (sender, mut receiver): (Sender<Outgoing>, Receiver<Outgoing>) = channel(100);
+    state.connections.write().await.insert(id, sender);
+    state.links.write().await.insert(id, stream.stats());
+    println!("service {} registered from {} with id: {}", name, addr, id);
+
+    let result = service_session(&state, &mut stream, &mut lines, &mut receiver, &class, &name, id).await;
+
+    state.services.write().await.remove(&id);
+    state.connections.write().await.remove(&id);
+    state.links.write().await.remove(&id);
+    println!("service {} disconnected", name);
+    result
+}
+
+/// a service is forwarded the SQUERY messages sent to it and may answer users with PRIVMSG or NOTICE
+async fn service_session(
+    state: &ServerState,
+    stream: &mut Connection,
+    lines: &mut LineBuffer,
+    receiver: &mut Receiver<Outgoing>,
+    class: &ConnectionClass,
+    name: &str,
+    id: u64,
+) -> Result<(), IrcError> {
+    let welcome = numeric(&state.name, RPL_YOURESERVICE, name, &format!(":You are service {}", name));
+    stream.write_all(welcome.as_bytes()).await?;
+    let ping_interval = Duration::from_secs(class.ping_interval);
+    let ping_timeout = Duration::from_secs(class.ping_timeout);
+    let mut last_seen = Instant::now();
+    let mut ping_sent: Option<Instant> = None;
+    let sig = format!("{}!{}@{}", name, name, state.name);
+
+    loop {
+        let deadline = match ping_sent {
+            Some(sent) => sent + ping_timeout,
+            None => last_seen + ping_interval,
+        };
+        let line = tokio::select! {
+            outgoing = receiver.recv() => {
+                match outgoing {
+                    Some(Outgoing::Line(line)) => stream.write_all(line.as_bytes()).await?,
+                    Some(Outgoing::Close(line)) => {
+                        stream.write_all(line.as_bytes()).await?;
+                        stream.shutdown().await?;
+                        return Err(IrcError::Closed(line.lines().last().unwrap_or_default().to_string()));
+                    },
+                    // the queue is only closed under a session when it fell too far behind
+                    None => {
+                        stream.write_all(format!("ERROR :Closing Link: {} (SendQ exceeded)\r\n", state.name).as_bytes()).await?;
+                        stream.shutdown().await?;
+                        return Err(IrcError::SendQExceeded);
+                    },
+                }
+                continue;
+            },
+            _ = sleep_until(deadline) => {
+                if ping_sent.is_some() {
+                    stream.write_all(b"ERROR :Closing Link: Ping timeout\r\n").await?;
+                    stream.shutdown().await?;
+                    return Err(IrcError::PingTimeout);
+                }
+                stream.write_all(format!("PING :{}\r\n", state.name).as_bytes()).await?;
+                ping_sent = Some(Instant::now());
+                continue;
+            },
+            incoming = lines.next_line(stream) => incoming?,
+        };
+        let line = match line {
+            Some(line) => line,
+            None => return Ok(()),
+        };
+        last_seen = Instant::now();
+        ping_sent = None;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let message = match Message::parse(line) {
+            Ok(message) => message,
+            Err(e) => {
+                println!("couldn't parse message from service {}: {}", name, e);
+                continue;
+            },
+        };
+        match message.command() {
+            Command::PING(token, _) => {
+                stream.write_all(format!(":{} PONG {} :{}\r\n", state.name, state.name, token).as_bytes()).await?;
+            },
+            Command::PONG(_, _) => {},
+            // services talk to users directly, they can't join channels
+            Command::PRIVMSG(target, text) | Command::NOTICE(target, text) => {
+                let kind = if matches!(message.command(), Command::PRIVMSG(..)) { "PRIVMSG" } else { "NOTICE" };
+                let recipient = find_nick(&*state.users.read().await, target).map(|(uid, _)| *uid);
+                match recipient {
+                    Some(uid) => deliver(&state.connections, &[uid], &format!(":{} {} {} :{}\r\n", sig, kind, target, text)).await,
+                    None if kind == "PRIVMSG" => {
+                        stream.write_all(numeric(&state.name, ERR_NOSUCHNICK, name, &format!("{} :No such nick/channel", target)).as_bytes()).await?;
+                    },
+                    None => {},
+                }
+            },
+            Command::SERVLIST(mask, kind) => {
+                servlist(state, id, mask, kind, stream).await?;
+            },
+            Command::SERVICE(..) => {
+                stream.write_all(numeric(&state.name, ERR_ALREADYREGISTRED, name, ":You may not reregister").as_bytes()).await?;
+            },
+            Command::QUIT(_) => {
+                stream.write_all(b"ERROR :Closing Link\r\n").await?;
+                stream.shutdown().await?;
+                return Ok(());
+            },
+            _ => {},
+        }
+    }
+}
@@ -0,0 +1,122 @@
+//! saving channel settings, read markers, accounts and memos to the snapshot file and loading them back at startup
+
+use std::collections::HashMap;
+
+use tokio::task;
+use tokio::time::{sleep, Duration};
+
+use networkingirc::account::Accounts;
+use networkingirc::channel::ChannelMeta;
+use networkingirc::config::ServerConfig;
+use networkingirc::history::ReadMarkers;
+use networkingirc::memo::Memos;
+use networkingirc::snapshot::Snapshot;
+use networkingirc::unix_time;
+
+use crate::ServerState;
+
+/// the channels, read markers, accounts and memos from the last snapshot, the server refuses to
+/// start when the snapshot can't be read rather than overwrite it with an empty one
+pub fn restore_snapshot(config: &ServerConfig) -> (HashMap<String, ChannelMeta>, ReadMarkers, Accounts, Memos) {
+    let path = match &config.snapshot.path {
+        Some(path) => path,
+        None => return (HashMap::new(), ReadMarkers::new(), Accounts::new(), Memos::new()),
+    };
+    match Snapshot::load(path) {
+        Ok(Some(snapshot)) => {
+            println!(
+                "restored {} channels and {} accounts from snapshot {:?}",
+                snapshot.channels.len(), snapshot.accounts.len(), path
+            );
+            let channels = snapshot.channels.iter()
+                .map(|channel| (channel.name().to_string(), channel.clone()))
+                .collect();
+            (channels, snapshot.read_markers(), snapshot.accounts(), snapshot.memos())
+        },
+        Ok(None) => (HashMap::new(), ReadMarkers::new(), Accounts::new(), Memos::new()),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        },
+    }
+}
+
+pub async fn take_snapshot(state: &ServerState) -> Snapshot {
+    let mut snapshot = Snapshot {
+        taken: unix_time(),
+        channels: state.channels.read().await.values().cloned().collect(),
+        read_markers: Vec::new(),
+        accounts: Vec::new(),
+        memos: Vec::new(),
+    };
+    snapshot.channels.sort_by(|a, b| a.name().cmp(b.name()));
+    snapshot.set_read_markers(&*state.read_markers.read().await);
+    snapshot.set_accounts(&*state.accounts.read().await);
+    snapshot.set_memos(&*state.memos.read().await);
+    snapshot
+}
+
+/// waits for the channel logs to be written before the server exits
+pub async fn flush_history(state: &ServerState) {
+    let read = state.messages.read().await;
+    task::block_in_place(|| read.flush());
+}
+
+/// writes a snapshot when the config names a file for it
+pub async fn save_snapshot(state: &ServerState) {
+    let path = match state.config.read().await.snapshot.path.clone() {
+        Some(path) => path,
+        None => return,
+    };
+    let snapshot = take_snapshot(state).await;
+    // the file is small, writing it inline doesn't hold up other tasks for long
+    match task::block_in_place(|| snapshot.save(&path)) {
+        Ok(()) => println!("saved snapshot to {:?}", path),
+        Err(e) => eprintln!("{}", e),
+    }
+}
+
+/// saves a snapshot every configured interval, the interval is read again each
+/// time so a REHASH can change it
+pub async fn snapshot_loop(state: ServerState) {
+    loop {
+        let interval = state.config.read().await.snapshot.interval;
+        if interval == 0 {
+            sleep(Duration::from_secs(60)).await;
+            continue;
+        }
+        sleep(Duration::from_secs(interval)).await;
+        if state.config.read().await.snapshot.interval != 0 {
+            save_snapshot(&state).await;
+        }
+    }
+}
+
+/// saves a last snapshot and exits when the server is interrupted or terminated
+pub async fn save_on_shutdown(state: ServerState) {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut terminate = match signal(SignalKind::terminate()) {
+            Ok(terminate) => terminate,
+            Err(e) => {
+                eprintln!("couldn't listen for SIGTERM: {}", e);
+                return;
+            },
+        };
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {},
+            _ = terminate.recv() => {},
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        if tokio::signal::ctrl_c().await.is_err() {
+            return;
+        }
+    }
+    println!("shutting down");
+    flush_history(&state).await;
+    save_snapshot(&state).await;
+    std::process::exit(0);
+}
@@ -0,0 +1,160 @@
+//! changes a user makes to themselves: NICK, MODE and AWAY
+
+use tokio::io::AsyncWriteExt;
+
+use networkingirc::command::{Mode, UserMode};
+use networkingirc::response::*;
+
+use crate::connection::Connection;
+use crate::links::{announce_away, announce_nick, announce_user_mode};
+use crate::messaging::deliver;
+use crate::nickserv::protect_nick;
+use crate::services::{builtin_service, find_service};
+use crate::{reply_target, ServerState};
+
+/// nicknames must start with a letter or special character and stay short enough
+/// to fit comfortably in replies
+fn valid_nick(nick: &str, max_length: usize) -> bool {
+    let special = |c: char| "[]\\`_^{|}".contains(c);
+    let mut chars = nick.chars();
+    match chars.next() {
+        Some(first) if first.is_ascii_alphabetic() || special(first) => {},
+        _ => return false,
+    }
+    nick.len() <= max_length && chars.all(|c| c.is_ascii_alphanumeric() || special(c) || c == '-')
+}
+
+pub async fn change_nick(
+    state: &ServerState,
+    id: u64,
+    nick: &str,
+    stream: &mut Connection,
+) -> Result<(), std::io::Error> {
+    let ServerState { users, whowas, .. } = state;
+    let target = reply_target(users, id).await;
+    let max_length = state.config.read().await.limits.nick_length;
+    if nick.is_empty() {
+        return stream.write_all(numeric(&state.name, ERR_NONICKNAMEGIVEN, &target, ":No nickname given").as_bytes()).await;
+    }
+    if !valid_nick(nick, max_length) {
+        return stream.write_all(numeric(&state.name, ERR_ERRONEUSNICKNAME, &target, &format!("{} :Erroneous nickname", nick)).as_bytes()).await;
+    }
+    let mut write = users.write().await;
+    if write.get(&id).map(|user| user.has_mode(&UserMode::Restricted)).unwrap_or(false) {
+        std::mem::drop(write);
+        return stream.write_all(numeric(&state.name, ERR_RESTRICTED, &target, ":Your connection is restricted!").as_bytes()).await;
+    }
+    let taken = builtin_service(nick).is_some()
+        || find_service(&*state.services.read().await, nick).is_some()
+        || write.iter().any(|(other, user)| *other != id && user.display_nick().eq_ignore_ascii_case(nick));
+    if taken {
+        std::mem::drop(write);
+        return stream.write_all(numeric(&state.name, ERR_NICKNAMEINUSE, &target, &format!("{} :Nickname is already in use", nick)).as_bytes()).await;
+    }
+    let outstring = match write.get_mut(&id) {
+        Some(user) => {
+            whowas.write().await.record(user, &state.name);
+            let old_sig = user.sig();
+            user.nick(nick.to_string());
+            format!(":{} NICK :{}\r\n", old_sig, nick)
+        },
+        None => return Ok(()),
+    };
+    std::mem::drop(write);
+    stream.write_all(outstring.as_bytes()).await?;
+    announce_nick(state, id, nick).await;
+    protect_nick(state, id, stream).await
+}
+
+/// renames a user from outside their session, the NICK goes through their queue
+pub async fn force_nick(state: &ServerState, id: u64, nick: &str) {
+    let line = match state.users.write().await.get_mut(&id) {
+        Some(user) => {
+            state.whowas.write().await.record(user, &state.name);
+            let old_sig = user.sig();
+            user.nick(nick.to_string());
+            format!(":{} NICK :{}\r\n", old_sig, nick)
+        },
+        None => return,
+    };
+    deliver(&state.connections, &[id], &line).await;
+    announce_nick(state, id, nick).await;
+}
+
+/// queries or changes a user's own modes
+pub async fn user_mode(
+    state: &ServerState,
+    id: u64,
+    nick: &str,
+    modes: &[Mode<UserMode>],
+    stream: &mut Connection,
+) -> Result<(), std::io::Error> {
+    let mut write = state.users.write().await;
+    let user = match write.get_mut(&id) {
+        Some(user) => user,
+        None => return Ok(()),
+    };
+    let own = user.display_nick().to_string();
+    if !own.eq_ignore_ascii_case(nick) {
+        std::mem::drop(write);
+        return stream.write_all(numeric(&state.name, ERR_USERSDONTMATCH, &own, ":Cannot change mode for other users").as_bytes()).await;
+    }
+    if modes.is_empty() {
+        let reply = numeric(&state.name, RPL_UMODEIS, &own, &user.mode_string());
+        std::mem::drop(write);
+        return stream.write_all(reply.as_bytes()).await;
+    }
+
+    let mut applied = Vec::new();
+    let mut unknown = false;
+    for mode in modes.iter() {
+        match mode {
+            // operator status only comes from OPER and away status only from AWAY
+            Mode::Add(UserMode::Operator) | Mode::Add(UserMode::Away) | Mode::Sub(UserMode::Away) => {},
+            // a restricted user can't lift the restriction themselves
+            Mode::Sub(UserMode::Restricted) => {},
+            Mode::Add(UserMode::Unknown(_)) | Mode::Sub(UserMode::Unknown(_)) => unknown = true,
+            Mode::Add(flag) => {
+                if user.add_mode(flag.clone()) {
+                    applied.push(mode.clone());
+                }
+            },
+            Mode::Sub(flag) => {
+                if user.remove_mode(flag) {
+                    applied.push(mode.clone());
+                }
+            },
+        }
+    }
+    std::mem::drop(write);
+
+    let mut outstring = String::new();
+    if unknown {
+        outstring.push_str(&numeric(&state.name, ERR_UMODEUNKNOWNFLAG, &own, ":Unknown MODE flag"));
+    }
+    if !applied.is_empty() {
+        outstring.push_str(&format!(":{} MODE {} :{}\r\n", own, own, Mode::format_list(&applied)));
+        announce_user_mode(state, id, &Mode::format_list(&applied)).await;
+    }
+    stream.write_all(outstring.as_bytes()).await
+}
+
+pub async fn set_away(
+    state: &ServerState,
+    id: u64,
+    away: &Option<String>,
+    stream: &mut Connection,
+) -> Result<(), std::io::Error> {
+    let reply = match state.users.write().await.get_mut(&id) {
+        Some(user) => {
+            user.away(away.clone());
+            match away {
+                Some(_) => numeric(&state.name, RPL_NOWAWAY, user.display_nick(), ":You have been marked as being away"),
+                None => numeric(&state.name, RPL_UNAWAY, user.display_nick(), ":You are no longer marked as being away"),
+            }
+        },
+        None => return Ok(()),
+    };
+    announce_away(state, id, away).await;
+    stream.write_all(reply.as_bytes()).await
+}
@@ -0,0 +1,149 @@
+//! IRC over WebSocket as described by the IRCv3 websocket extension, every frame
+//! carries a single message without the trailing CRLF
+
+use std::collections::VecDeque;
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use futures_util::{Sink, Stream as _};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tokio_tungstenite::tungstenite::http::{HeaderValue, StatusCode};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+
+use networkingirc::wildcard_match;
+
+use crate::connection::Stream;
+
+const TEXT_PROTOCOL: &str = "text.ircv3.net";
+const BINARY_PROTOCOL: &str = "binary.ircv3.net";
+
+/// a WebSocket connection read and written as if it were a stream of IRC lines
+pub struct WebSocket {
+    inner: WebSocketStream<Stream>,
+    /// frames are sent as text for text.ircv3.net and as binary otherwise
+    text: bool,
+    /// the part of the last frame received that hasn't been read yet
+    received: Vec<u8>,
+    /// frames written but not yet handed to the socket
+    outgoing: VecDeque<Message>,
+}
+
+/// completes the WebSocket handshake, picking the first IRC subprotocol the client
+/// offers and refusing browsers from an origin that isn't allowed
+pub async fn accept(stream: Stream, origins: &[String]) -> io::Result<WebSocket> {
+    let mut text = false;
+    // the callback's signature is set by tungstenite
+    #[allow(clippy::result_large_err)]
+    let callback = |request: &Request, mut response: Response| -> Result<Response, ErrorResponse> {
+        if !origins.is_empty() {
+            let origin = request.headers().get("Origin").and_then(|origin| origin.to_str().ok());
+            // clients that aren't browsers don't send an origin
+            if let Some(origin) = origin {
+                if !origins.iter().any(|allowed| wildcard_match(allowed, origin)) {
+                    let mut refusal = ErrorResponse::new(Some(String::from("origin not allowed")));
+                    *refusal.status_mut() = StatusCode::FORBIDDEN;
+                    return Err(refusal);
+                }
+            }
+        }
+        let offered = request.headers().get_all("Sec-WebSocket-Protocol").iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(|protocol| protocol.trim())
+            .find(|protocol| *protocol == TEXT_PROTOCOL || *protocol == BINARY_PROTOCOL);
+        // without a subprotocol the client is treated like binary.ircv3.net
+        if let Some(protocol) = offered {
+            text = protocol == TEXT_PROTOCOL;
+            response.headers_mut().insert("Sec-WebSocket-Protocol", HeaderValue::from_static(if text { TEXT_PROTOCOL } else { BINARY_PROTOCOL }));
+        }
+        Ok(response)
+    };
+    let inner = tokio_tungstenite::accept_hdr_async(stream, callback).await.map_err(io::Error::other)?;
+    Ok(WebSocket {
+        inner,
+        text,
+        received: Vec::new(),
+        outgoing: VecDeque::new(),
+    })
+}
+
+impl WebSocket {
+    pub fn get_ref(&self) -> &Stream {
+        self.inner.get_ref()
+    }
+
+    /// hands queued frames to the socket and flushes them
+    fn poll_send(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while !self.outgoing.is_empty() {
+            ready!(Pin::new(&mut self.inner).poll_ready(cx)).map_err(io::Error::other)?;
+            if let Some(frame) = self.outgoing.pop_front() {
+                Pin::new(&mut self.inner).start_send(frame).map_err(io::Error::other)?;
+            }
+        }
+        Pin::new(&mut self.inner).poll_flush(cx).map_err(io::Error::other)
+    }
+}
+
+impl AsyncRead for WebSocket {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        // replies waiting on a full socket go out once it drains, waiting on a read wakes for that too
+        if let Poll::Ready(Err(e)) = this.poll_send(cx) {
+            return Poll::Ready(Err(e));
+        }
+        while this.received.is_empty() {
+            let frame = match ready!(Pin::new(&mut this.inner).poll_next(cx)) {
+                Some(frame) => frame.map_err(io::Error::other)?,
+                // a closed socket reads as the end of the stream
+                None => return Poll::Ready(Ok(())),
+            };
+            match frame {
+                Message::Text(text) => this.received.extend_from_slice(text.as_bytes()),
+                Message::Binary(bytes) => this.received.extend_from_slice(&bytes),
+                Message::Close(_) => return Poll::Ready(Ok(())),
+                // pings are answered by the WebSocket library itself
+                Message::Ping(_) | Message::Pong(_) | Message::Frame(_) => continue,
+            }
+            this.received.extend_from_slice(b"\r\n");
+        }
+        let length = this.received.len().min(buf.remaining());
+        buf.put_slice(&this.received[..length]);
+        this.received.drain(..length);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for WebSocket {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        // earlier frames have to be on their way before more are accepted
+        ready!(this.poll_send(cx))?;
+        let lines = String::from_utf8_lossy(buf);
+        for line in lines.split('\n').map(|line| line.trim_end_matches('\r')).filter(|line| !line.is_empty()) {
+            let frame = if this.text {
+                Message::text(line)
+            }else{
+                Message::binary(line.as_bytes().to_vec())
+            };
+            this.outgoing.push_back(frame);
+        }
+        // the frames are queued whether or not the socket takes them now
+        if let Poll::Ready(Err(e)) = this.poll_send(cx) {
+            return Poll::Ready(Err(e));
+        }
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_send(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_send(cx))?;
+        Pin::new(&mut this.inner).poll_close(cx).map_err(io::Error::other)
+    }
+}
@@ -0,0 +1,123 @@
+//! IRCv3 capability negotiation, the capabilities the server supports
+//! and the ones each connection has turned on
+
+use std::collections::BTreeSet;
+
+/// the BATCH command, used to group CHATHISTORY replies
+pub const BATCH: &str = "batch";
+/// a time tag on messages relayed from other users
+pub const SERVER_TIME: &str = "server-time";
+/// the msgid tag on messages sent from channel history
+pub const MESSAGE_TAGS: &str = "message-tags";
+pub const CHATHISTORY: &str = "draft/chathistory";
+/// MARKREAD, sent by the server on JOIN and when another session moves the marker
+pub const READ_MARKER: &str = "draft/read-marker";
+/// AUTHENTICATE, its value lists the mechanisms from crate::sasl
+pub const SASL: &str = "sasl";
+
+/// CAP LS replies are split so every line stays well under the 512 byte limit
+const MAX_LS_LINE: usize = 400;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Capability {
+    pub name: String,
+    /// sent after an '=' to clients asking with CAP LS 302
+    pub value: Option<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CapabilityRegistry {
+    capabilities: Vec<Capability>,
+}
+
+/// the capabilities a connection has turned on
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct EnabledCaps(BTreeSet<String>);
+
+impl CapabilityRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// adds a capability, replacing the value of one already registered
+    pub fn register(&mut self, name: &str, value: Option<String>) -> &mut Self {
+        match self.capabilities.iter_mut().find(|capability| capability.name == name) {
+            Some(capability) => capability.value = value,
+            None => self.capabilities.push(Capability { name: name.to_string(), value }),
+        }
+        self
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.capabilities.iter().any(|capability| capability.name == name)
+    }
+
+    /// the space separated lists to send for CAP LS, values are only included for version 302
+    pub fn ls_lines(&self, with_values: bool) -> Vec<String> {
+        let mut lines = vec![String::new()];
+        for capability in self.capabilities.iter() {
+            let entry = match (&capability.value, with_values) {
+                (Some(value), true) => format!("{}={}", capability.name, value),
+                _ => capability.name.clone(),
+            };
+            let line = lines.last_mut().unwrap();
+            if !line.is_empty() && line.len() + entry.len() + 1 > MAX_LS_LINE {
+                lines.push(entry);
+            }else{
+                if !line.is_empty() {
+                    line.push(' ');
+                }
+                line.push_str(&entry);
+            }
+        }
+        lines
+    }
+
+    /// applies a CAP REQ, names prefixed with '-' are turned off
+    /// the request is refused as a whole when any of it names an unknown capability
+    pub fn request(&self, enabled: &EnabledCaps, request: &str) -> Option<EnabledCaps> {
+        let mut updated = enabled.clone();
+        for name in request.split_whitespace() {
+            match name.strip_prefix('-') {
+                Some(name) if self.contains(name) => { updated.0.remove(name); },
+                None if self.contains(name) => { updated.0.insert(name.to_string()); },
+                _ => return None,
+            }
+        }
+        Some(updated)
+    }
+}
+
+impl EnabledCaps {
+    pub fn has(&self, name: &str) -> bool {
+        self.0.contains(name)
+    }
+
+    /// the enabled capabilities as sent in CAP LIST
+    pub fn list(&self) -> String {
+        self.0.iter().cloned().collect::<Vec<String>>().join(" ")
+    }
+}
+
+#[test]
+async fn capability_request_test() {
+    let mut registry = CapabilityRegistry::new();
+    registry.register(SERVER_TIME, None).register("sasl", Some(String::from("PLAIN")));
+    assert_eq!(registry.ls_lines(true), vec![String::from("server-time sasl=PLAIN")]);
+    assert_eq!(registry.ls_lines(false), vec![String::from("server-time sasl")]);
+
+    let enabled = registry.request(&EnabledCaps::default(), "server-time sasl").unwrap();
+    assert!(enabled.has(SERVER_TIME));
+    assert_eq!(enabled.list(), "sasl server-time");
+    // nothing changes when part of the request can't be granted
+    assert!(registry.request(&enabled, "-sasl echo-message").is_none());
+    let enabled = registry.request(&enabled, "-sasl").unwrap();
+    assert!(!enabled.has("sasl"));
+
+    for i in 0..100 {
+        registry.register(&format!("vendor.example/capability-{}", i), None);
+    }
+    let lines = registry.ls_lines(true);
+    assert!(lines.len() > 1);
+    assert!(lines.iter().all(|line| line.len() <= MAX_LS_LINE));
+}
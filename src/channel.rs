@@ -1,18 +1,184 @@
 use crate::command::ChannelMode;
 use std::collections::HashSet;
+/// everything but the users and their status is kept in snapshots, see crate::snapshot
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChannelMeta {
     name: String,
     modes: Vec<ChannelMode>,
     /// a vector of user ids
+    #[serde(skip)]
     users: HashSet<u64>,
+    /// members with +o
+    #[serde(skip)]
+    operators: HashSet<u64>,
+    /// members with +v
+    #[serde(skip)]
+    voiced: HashSet<u64>,
     topic: Option<String>,
     limit: Option<u32>,
+    /// nick!user@host masks that can't join
+    #[serde(default)]
+    bans: Vec<String>,
+    /// the account the channel is registered to with ChanServ
+    #[serde(default)]
+    founder: Option<String>,
+    /// accounts given a status whenever they join a registered channel
+    #[serde(default)]
+    access: Vec<ChannelAccess>,
+    /// when the channel was created, linked servers keep the status given on the older channel
+    #[serde(default)]
+    created: u64,
+}
+
+/// the status an account on a channel's access list is given when joining
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AccessLevel {
+    Voice,
+    Op,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChannelAccess {
+    pub account: String,
+    pub level: AccessLevel,
 }
 
 impl ChannelMeta {
+    pub fn name(&self) -> &str {
+        self.name.as_str()
+    }
+    pub fn modes(&self) -> &Vec<ChannelMode> {
+        &self.modes
+    }
+    pub fn users(&self) -> &HashSet<u64> {
+        &self.users
+    }
+    pub fn has_user(&self, user: u64) -> bool {
+        self.users.contains(&user)
+    }
+    /// secret and private channels are hidden from users outside of them
+    pub fn is_hidden(&self) -> bool {
+        self.modes.contains(&ChannelMode::SecretChannel) || self.modes.contains(&ChannelMode::PrivateChannel)
+    }
     pub fn topic(&self) -> &Option<String> {
         &self.topic
     }
+    pub fn set_topic(&mut self, topic: Option<String>) -> &mut Self {
+        self.topic = topic;
+        self
+    }
+    pub fn set_modes(&mut self, modes: Vec<ChannelMode>) -> &mut Self {
+        self.modes = modes;
+        self
+    }
+    /// returns false when the channel already had the mode
+    pub fn add_mode(&mut self, mode: ChannelMode) -> bool {
+        if self.modes.contains(&mode) {
+            return false;
+        }
+        self.modes.push(mode);
+        true
+    }
+    /// returns false when the channel didn't have the mode
+    pub fn remove_mode(&mut self, mode: &ChannelMode) -> bool {
+        let before = self.modes.len();
+        self.modes.retain(|set| set != mode);
+        self.modes.len() != before
+    }
+    pub fn is_operator(&self, user: u64) -> bool {
+        self.operators.contains(&user)
+    }
+    pub fn is_voiced(&self, user: u64) -> bool {
+        self.voiced.contains(&user)
+    }
+    /// gives or takes +o, returning false when nothing changed
+    pub fn set_operator(&mut self, user: u64, operator: bool) -> bool {
+        if operator { self.operators.insert(user) } else { self.operators.remove(&user) }
+    }
+    /// gives or takes +v, returning false when nothing changed
+    pub fn set_voiced(&mut self, user: u64, voiced: bool) -> bool {
+        if voiced { self.voiced.insert(user) } else { self.voiced.remove(&user) }
+    }
+    pub fn bans(&self) -> &Vec<String> {
+        &self.bans
+    }
+    /// returns false when the mask is already banned
+    pub fn add_ban(&mut self, mask: &str) -> bool {
+        if self.bans.iter().any(|ban| ban.eq_ignore_ascii_case(mask)) {
+            return false;
+        }
+        self.bans.push(mask.to_string());
+        true
+    }
+    /// returns false when the mask wasn't banned
+    pub fn remove_ban(&mut self, mask: &str) -> bool {
+        let before = self.bans.len();
+        self.bans.retain(|ban| !ban.eq_ignore_ascii_case(mask));
+        self.bans.len() != before
+    }
+    /// whether a nick!user@host matches any of the ban masks
+    pub fn is_banned(&self, sig: &str) -> bool {
+        self.bans.iter().any(|ban| crate::wildcard_match(ban, sig))
+    }
+    pub fn founder(&self) -> &Option<String> {
+        &self.founder
+    }
+    /// registers the channel to an account, or drops the registration and access list with None
+    pub fn register(&mut self, founder: Option<String>) -> &mut Self {
+        if founder.is_none() {
+            self.access.clear();
+        }
+        self.founder = founder;
+        self
+    }
+    pub fn access(&self) -> &Vec<ChannelAccess> {
+        &self.access
+    }
+    /// adds an account to the access list or changes its level
+    pub fn set_access(&mut self, account: &str, level: AccessLevel) {
+        match self.access.iter_mut().find(|entry| entry.account.eq_ignore_ascii_case(account)) {
+            Some(entry) => entry.level = level,
+            None => self.access.push(ChannelAccess { account: account.to_string(), level }),
+        }
+    }
+    /// returns false when the account wasn't on the access list
+    pub fn remove_access(&mut self, account: &str) -> bool {
+        let before = self.access.len();
+        self.access.retain(|entry| !entry.account.eq_ignore_ascii_case(account));
+        self.access.len() != before
+    }
+    /// the status an account gets on joining, the founder is always an op
+    pub fn access_level(&self, account: &str) -> Option<AccessLevel> {
+        if self.founder.as_deref().map(|founder| founder.eq_ignore_ascii_case(account)).unwrap_or(false) {
+            return Some(AccessLevel::Op);
+        }
+        self.access.iter()
+            .find(|entry| entry.account.eq_ignore_ascii_case(account))
+            .map(|entry| entry.level)
+    }
+    pub fn created(&self) -> u64 {
+        self.created
+    }
+    pub fn set_created(&mut self, created: u64) -> &mut Self {
+        self.created = created;
+        self
+    }
+    /// compares the creation time another server has for the channel with ours, the older
+    /// channel wins: None when theirs is newer and the status they send is to be ignored,
+    /// otherwise the status given here when theirs is older, which is taken away
+    pub fn settle_ts(&mut self, ts: u64) -> Option<Vec<(u64, char)>> {
+        if ts > self.created {
+            return None;
+        }
+        let mut dropped = Vec::new();
+        if ts < self.created {
+            self.created = ts;
+            dropped.extend(self.operators.drain().map(|uid| (uid, 'o')));
+            dropped.extend(self.voiced.drain().map(|uid| (uid, 'v')));
+        }
+        Some(dropped)
+    }
     pub fn limit(&mut self, limit: u32) -> &mut Self {
         self.limit = Some(limit);
         self
@@ -24,8 +190,14 @@ impl ChannelMeta {
             name,
             modes: Vec::new(),
             users,
+            operators: HashSet::new(),
+            voiced: HashSet::new(),
             topic: None,
-            limit: None
+            limit: None,
+            bans: Vec::new(),
+            founder: None,
+            access: Vec::new(),
+            created: crate::unix_time(),
         }
     }
 
@@ -36,5 +208,49 @@ impl ChannelMeta {
     }
     pub fn leave(&mut self, user: u64) {
         self.users.remove(&user);
+        self.operators.remove(&user);
+        self.voiced.remove(&user);
     }
-}
\ No newline at end of file
+}
+
+#[test]
+async fn channel_access_test() {
+    let mut channel = ChannelMeta::new(String::from("#rust"), 1);
+    assert!(channel.set_operator(1, true));
+    assert!(!channel.set_operator(1, true));
+    channel.leave(1);
+    assert!(!channel.is_operator(1));
+
+    assert!(channel.add_ban("*!*@10.0.0.*"));
+    assert!(!channel.add_ban("*!*@10.0.0.*"));
+    assert!(channel.is_banned("cardinal!cardinal@10.0.0.7"));
+    assert!(!channel.is_banned("cardinal!cardinal@127.0.0.1"));
+
+    channel.register(Some(String::from("cardinal")));
+    channel.set_access("hephaestus", AccessLevel::Voice);
+    channel.set_access("Hephaestus", AccessLevel::Op);
+    assert_eq!(channel.access().len(), 1);
+    assert_eq!(channel.access_level("CARDINAL"), Some(AccessLevel::Op));
+    assert_eq!(channel.access_level("hephaestus"), Some(AccessLevel::Op));
+    assert_eq!(channel.access_level("julian"), None);
+    // dropping the registration forgets the access list but keeps the bans
+    channel.register(None);
+    assert_eq!(channel.access_level("hephaestus"), None);
+    assert_eq!(channel.bans().len(), 1);
+}
+
+#[test]
+async fn channel_ts_test() {
+    let mut channel = ChannelMeta::new(String::from("#rust"), 1);
+    channel.set_operator(1, true);
+    let created = channel.created();
+    // a newer channel on the other side doesn't get its status through
+    assert_eq!(channel.settle_ts(created + 60), None);
+    assert!(channel.is_operator(1));
+    assert_eq!(channel.settle_ts(created), Some(Vec::new()));
+    assert!(channel.is_operator(1));
+    // an older one takes the status given here away
+    assert_eq!(channel.settle_ts(created - 60), Some(vec![(1, 'o')]));
+    assert!(!channel.is_operator(1));
+    assert_eq!(channel.created(), created - 60);
+}
@@ -1,6 +1,6 @@
-/// this modules provides utilities
-/// for the client binary program in order to keep
-/// the binary itself relatively clean
+//! this modules provides utilities
+//! for the client binary program in order to keep
+//! the binary itself relatively clean
 
 use crate::command::*;
 
@@ -8,18 +8,25 @@ pub struct CmdOperator {
     channel: String,
 }
 
-impl CmdOperator {
-    pub fn default() -> Self {
+impl Default for CmdOperator {
+    fn default() -> Self {
         Self {
             channel: String::from("Welcome"),
         }
     }
+}
+
+impl CmdOperator {
+    /// the channel the operator currently considers active
+    pub fn channel(&self) -> &str {
+        self.channel.as_str()
+    }
     pub fn read_input() -> Result<String, CommandParseError> {
         let mut input = String::new();
         match std::io::stdin()
         .read_line(&mut input) {
             Ok(_) => {},
-            Err(e) => return Err(CommandParseError::NoCommandFound(input)),
+            Err(_) => return Err(CommandParseError::NoCommandFound(input)),
         }
         Self::parse_input(input)
     }
@@ -29,10 +36,8 @@ impl CmdOperator {
         let cmdendopt = input.find(' ');
         if firstchar == Some('/') {
             if let Some(cmdend) = cmdendopt {
-                let mut invec = input.chars().collect::<Vec<char>>();
                 let mut newstring = String::new();
-                for i in 1..invec.len() {
-                    let mut val = invec[i];
+                for (i, mut val) in input.chars().enumerate().skip(1) {
                     if i < cmdend {
                         val = val.to_ascii_uppercase();
                     }
@@ -41,12 +46,9 @@ impl CmdOperator {
                 
                 Ok(newstring.trim().to_string())
             }else{
-                let mut invec = input.chars().collect::<Vec<char>>();
                 let mut newstring = String::new();
-                for i in 1..invec.len() {
-                    let mut val = invec[i];
-                    val = val.to_ascii_uppercase();
-                    newstring.push(val);
+                for val in input.chars().skip(1) {
+                    newstring.push(val.to_ascii_uppercase());
                 }
                 Ok(newstring.trim().to_string())
             }
@@ -65,4 +67,4 @@ async fn convert_input() {
     let list = "/list".to_string();
     let list_parsed = CmdOperator::parse_input(list).unwrap();
     assert_eq!(list_parsed.as_str(), "LIST".to_string());
-}
\ No newline at end of file
+}
@@ -1,5 +1,5 @@
 
-use serde::{ser::Serialize, de::Deserialize};
+use serde::ser::Serialize;
 use std::fmt::Debug;
 
 use err_derive::Error;
@@ -50,11 +50,27 @@ impl ModeTrait for ChannelMode {
             _ => Self::Unknown(c)
         }
     }
+    fn to_char(&self) -> char {
+        match self {
+            Self::OperatorPrivileges => 'o',
+            Self::PrivateChannel => 'p',
+            Self::SecretChannel => 's',
+            Self::InviteOnly => 'i',
+            Self::TopicSettability => 't',
+            Self::NoOutSideClients => 'n',
+            Self::ModeratedChannel => 'm',
+            Self::UserLimit => 'l',
+            Self::BanSet => 'b',
+            Self::VoiceToggle => 'v',
+            Self::ChannelPassword => 'k',
+            Self::Unknown(c) => *c,
+        }
+    }
 }
 
 /// +o should be ignored by server (client should be able to make themselves an operator)
 /// but -o is acceptable
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
 pub enum UserMode {
     /// i = invisible
     Invisible,
@@ -87,10 +103,58 @@ impl ModeTrait for UserMode {
             _ => Self::Unknown(c)
         }
     }
+    fn to_char(&self) -> char {
+        match self {
+            Self::Invisible => 'i',
+            Self::NoticeList => 's',
+            Self::Wallops => 'w',
+            Self::Operator => 'o',
+            Self::Away => 'a',
+            Self::Restricted => 'r',
+            Self::MaskedHost => 'x',
+            Self::Unknown(c) => *c,
+        }
+    }
 }
 
 pub trait ModeTrait: Debug + Clone + Serialize + PartialEq + Eq{
     fn from_char(c: char) -> Self;
+    fn to_char(&self) -> char;
+}
+
+impl<T: ModeTrait> Mode<T> {
+    /// parses a mode string such as "+iw-x", flags before any sign are added
+    pub fn parse_list(modes: &str) -> Vec<Self> {
+        let mut adding = true;
+        let mut list = Vec::new();
+        for c in modes.chars() {
+            match c {
+                '+' => adding = true,
+                '-' => adding = false,
+                _ if adding => list.push(Self::Add(T::from_char(c))),
+                _ => list.push(Self::Sub(T::from_char(c))),
+            }
+        }
+        list
+    }
+
+    /// formats a list of changes back into a mode string
+    pub fn format_list(modes: &[Self]) -> String {
+        let mut out = String::new();
+        let mut sign = None;
+        for mode in modes.iter() {
+            let (next, flag) = match mode {
+                Self::Add(flag) => ('+', flag),
+                Self::Sub(flag) => ('-', flag),
+            };
+            if sign != Some(next) {
+                out.push(next);
+                sign = Some(next);
+            }
+            out.push(flag.to_char());
+        }
+        out
+    }
 }
 
 #[derive(Debug, Clone, Serialize, PartialEq, Eq)]
@@ -111,6 +175,35 @@ pub enum CommandParseError {
     PrefixOnly(String),
 }
 
+/// the requested fields of a WHOX query, WHO mask %fields,token
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WhoxQuery {
+    /// field letters from "tcuihsnfdlaor"
+    pub fields: String,
+    /// echoed back in each reply when the 't' field is requested
+    pub token: Option<String>,
+}
+
+impl WhoxQuery {
+    /// parses the part of a WHOX query after the '%'
+    pub fn parse(spec: &str) -> Self {
+        match spec.find(',') {
+            Some(offset) => Self {
+                fields: spec[..offset].to_string(),
+                token: Some(spec[offset+1..].to_string()),
+            },
+            None => Self {
+                fields: spec.to_string(),
+                token: None,
+            },
+        }
+    }
+
+    pub fn has(&self, field: char) -> bool {
+        self.fields.contains(field)
+    }
+}
+
 // PLEASE NOTE: the irc crates irc-proto/src/command.rs
 // was heavily references in creating this type
 // see https://github.com/aatxe/irc/blob/develop/irc-proto/src/command.rs
@@ -143,8 +236,8 @@ pub enum Command {
     /// PART chanlist :[comment]
     PART(Vec<String>, Option<String>),
 
-    /// set channel modes
-    ChannelMode(String, Vec<Mode<ChannelMode>>),
+    /// MODE channel modes [mode parameters]
+    ChannelMode(String, Vec<Mode<ChannelMode>>, Vec<String>),
     
     /// set channel topic :[topic]
     TOPIC(String, Option<String>),
@@ -224,8 +317,9 @@ pub enum Command {
     SQUERY(String, String),
 
     // 3.6 User based queries
-    /// WHO [mask ["o"]]
-    WHO(Option<String>, Option<bool>),
+    /// WHO [mask ["o"][%fields[,token]]]
+    /// the third field is only present for WHOX queries
+    WHO(Option<String>, Option<bool>, Option<WhoxQuery>),
     /// WHOIS [target] masklist
     WHOIS(Option<String>, String),
     /// WHOWAS nicklist [count :[target]]
@@ -261,20 +355,67 @@ pub enum Command {
     /// ISON space-separated nicklist
     ISON(Vec<String>),
 
+    // IRCv3 extensions
+    /// CAP subcommand [:capabilities]
+    CAP(String, Option<String>),
+    /// AUTHENTICATE mechanism/base64 chunk/+/*
+    AUTHENTICATE(String),
+    /// CHATHISTORY subcommand target/timestamp reference... limit
+    /// the parameters are kept as sent and parsed by history::HistoryQuery
+    CHATHISTORY(Vec<String>),
+    /// MARKREAD target [timestamp=YYYY-MM-DDThh:mm:ss.sssZ]
+    MARKREAD(String, Option<String>),
+
+    // Server to server protocol, only accepted from linked servers
+    /// SERVER servername hopcount :info
+    SERVER(String, String, String),
+    /// UID nickname hopcount signon modes username hostname ip uid account :realname
+    /// the parameters are kept as sent and read by network::RemoteUser
+    UID(Vec<String>),
+    /// SJOIN ts channel modes :members, each member's uid prefixed with @ for ops or + for voice
+    /// ts is when the channel was created, the status of the side with the newer channel is ignored
+    SJOIN(String, String, String, String),
+    /// EOB, the end of a server's burst
+    EOB,
+
     // Default option.
     RAW,
 }
 
 impl Command {
     pub fn parse(command_str: &str) -> Result<Command, CommandParseError> {
-        let mut parts = command_str.split(" ").map(|v| v.to_string()).collect::<Vec<String>>();
+        let mut parts = Self::split_params(command_str);
         parts.reverse();
         let field = match parts.pop() {
             Some(part) => part,
             None => return Err(CommandParseError::EmptyString),
         };
-        assert!(field != "".to_string());
-        return Self::match_field(field, &mut parts);
+        Self::match_field(field, &mut parts)
+    }
+    /// splits a command into its parameters, everything following a
+    /// parameter starting with ':' is treated as a single trailing parameter
+    fn split_params(command_str: &str) -> Vec<String> {
+        let mut params = Vec::new();
+        let mut rest = command_str.trim_start_matches(' ');
+        while !rest.is_empty() {
+            if let Some(trailing) = rest.strip_prefix(':') {
+                if !params.is_empty() {
+                    params.push(trailing.to_string());
+                    break;
+                }
+            }
+            match rest.find(' ') {
+                Some(offset) => {
+                    params.push(rest[..offset].to_string());
+                    rest = rest[offset..].trim_start_matches(' ');
+                },
+                None => {
+                    params.push(rest.to_string());
+                    break;
+                },
+            }
+        }
+        params
     }
     fn grab_arg(args: &mut Vec<String>) -> Result<String, CommandParseError> {
         Ok(match args.pop() {
@@ -296,10 +437,19 @@ impl Command {
             None => Vec::new(),
         }
     }
+    /// collects every remaining argument as a space separated list
+    /// which may also have been sent as a single trailing parameter
+    fn grab_list(args: &mut Vec<String>) -> Vec<String> {
+        args.drain(..)
+            .rev()
+            .flat_map(|arg| arg.split_whitespace().map(|v| v.to_string()).collect::<Vec<String>>())
+            .collect::<Vec<String>>()
+    }
     fn match_field(field: String, args: &mut Vec<String>) -> Result<Self, CommandParseError> {
         Ok(match field.as_str() {
             "PASS" => Self::PASS(Self::grab_arg(args)?),
             "NICK" => Self::NICK(Self::grab_arg(args)?),
+            "OPER" => Self::OPER(Self::grab_arg(args)?, Self::grab_arg(args)?),
             "JOIN" => Self::JOIN(Self::grab_required_args(args)?, Self::grab_args(args), args.pop()),
             "LIST" => Self::LIST(Self::grab_args(args), args.pop()),
             "PART" => Self::PART(Self::grab_args(args), args.pop()),
@@ -308,6 +458,86 @@ impl Command {
             "PING" => Self::PING(Self::grab_arg(args)?, args.pop()),
             "PONG" => Self::PONG(Self::grab_arg(args)?, args.pop()),
             "NAMES" => Self::NAMES(Self::grab_args(args), args.pop()),
+            "TOPIC" => Self::TOPIC(Self::grab_arg(args)?, args.pop()),
+            "WHO" => {
+                let mask = args.pop();
+                match args.pop() {
+                    Some(options) => {
+                        // flags come before the '%' and the WHOX field list after it
+                        let (flags, whox) = match options.find('%') {
+                            Some(offset) => (&options[..offset], Some(WhoxQuery::parse(&options[offset+1..]))),
+                            None => (options.as_str(), None),
+                        };
+                        Self::WHO(mask, Some(flags.contains('o')), whox)
+                    },
+                    None => Self::WHO(mask, None, None),
+                }
+            },
+            "WHOIS" => {
+                // the target server is only present when two arguments are given
+                let first = Self::grab_arg(args)?;
+                match args.pop() {
+                    Some(masks) => Self::WHOIS(Some(first), masks),
+                    None => Self::WHOIS(None, first),
+                }
+            },
+            "WHOWAS" => Self::WHOWAS(Self::grab_arg(args)?, args.pop(), args.pop()),
+            "PRIVMSG" => Self::PRIVMSG(Self::grab_arg(args)?, Self::grab_arg(args)?),
+            "SQUERY" => Self::SQUERY(Self::grab_arg(args)?, Self::grab_arg(args)?),
+            "SERVICE" => Self::SERVICE(
+                Self::grab_arg(args)?,
+                Self::grab_arg(args)?,
+                Self::grab_arg(args)?,
+                Self::grab_arg(args)?,
+                Self::grab_arg(args)?,
+                Self::grab_arg(args)?,
+            ),
+            "SERVLIST" => Self::SERVLIST(args.pop(), args.pop()),
+            "NOTICE" => Self::NOTICE(Self::grab_arg(args)?, Self::grab_arg(args)?),
+            "MODE" => {
+                let target = Self::grab_arg(args)?;
+                let modes = args.pop().unwrap_or_default();
+                if target.starts_with(['#', '&', '+', '!']) {
+                    Self::ChannelMode(target, Mode::parse_list(&modes), Self::grab_list(args))
+                }else{
+                    Self::UserMode(target, Mode::parse_list(&modes))
+                }
+            },
+            "KILL" => Self::KILL(Self::grab_arg(args)?, args.pop().unwrap_or_default()),
+            "WALLOPS" => Self::WALLOPS(Self::grab_arg(args)?),
+            "DIE" => Self::DIE,
+            "RESTART" => Self::RESTART,
+            "REHASH" => Self::REHASH,
+            "MOTD" => Self::MOTD(args.pop()),
+            "LUSERS" => Self::LUSERS(args.pop(), args.pop()),
+            "VERSION" => Self::VERSION(args.pop()),
+            "TIME" => Self::TIME(args.pop()),
+            "ADMIN" => Self::ADMIN(args.pop()),
+            "INFO" => Self::INFO(args.pop()),
+            "STATS" => Self::STATS(args.pop(), args.pop()),
+            "LINKS" => {
+                let first = args.pop();
+                match args.pop() {
+                    Some(mask) => Self::LINKS(first, Some(mask)),
+                    None => Self::LINKS(None, first),
+                }
+            },
+            "CONNECT" => Self::CONNECT(Self::grab_arg(args)?, Self::grab_arg(args)?, args.pop()),
+            "SQUIT" => Self::SQUIT(Self::grab_arg(args)?, args.pop().unwrap_or_default()),
+            "TRACE" => Self::TRACE(args.pop()),
+            "ERROR" => Self::ERROR(args.pop().unwrap_or_default()),
+            "SERVER" => Self::SERVER(Self::grab_arg(args)?, Self::grab_arg(args)?, args.pop().unwrap_or_default()),
+            "UID" => Self::UID(args.drain(..).rev().collect()),
+            "SJOIN" => Self::SJOIN(Self::grab_arg(args)?, Self::grab_arg(args)?, Self::grab_arg(args)?, args.pop().unwrap_or_default()),
+            "EOB" => Self::EOB,
+            "CAP" => Self::CAP(Self::grab_arg(args)?.to_ascii_uppercase(), args.pop()),
+            "AUTHENTICATE" => Self::AUTHENTICATE(Self::grab_arg(args)?),
+            "CHATHISTORY" => Self::CHATHISTORY(args.drain(..).rev().collect()),
+            "MARKREAD" => Self::MARKREAD(Self::grab_arg(args)?, args.pop()),
+            "USERHOST" => Self::USERHOST(Self::grab_list(args)),
+            "ISON" => Self::ISON(Self::grab_list(args)),
+            // an empty message is the same as no message, both mark the user as back
+            "AWAY" => Self::AWAY(args.pop().filter(|message| !message.is_empty())),
             _ => Self::RAW,
         })
     }
@@ -334,4 +564,130 @@ async fn command_parse_test() {
         "hephaestus".to_string(),
         "127.0.0.1:2323".to_string(), 
         "Julian".to_string()));
-}
\ No newline at end of file
+}
+
+#[test]
+async fn who_parse_test() {
+    assert_eq!(Command::parse("WHO").unwrap(), Command::WHO(None, None, None));
+    assert_eq!(Command::parse("WHO #rust o").unwrap(), Command::WHO(Some("#rust".to_string()), Some(true), None));
+    assert_eq!(Command::parse("WHOIS cardinal").unwrap(), Command::WHOIS(None, "cardinal".to_string()));
+    assert_eq!(Command::parse("WHOIS irc.local cardinal,hephaestus").unwrap(),
+        Command::WHOIS(Some("irc.local".to_string()), "cardinal,hephaestus".to_string()));
+    assert_eq!(Command::parse("WHOWAS cardinal 2").unwrap(),
+        Command::WHOWAS("cardinal".to_string(), Some("2".to_string()), None));
+    assert_eq!(Command::parse("QUIT :gone for the night").unwrap(), Command::QUIT(Some("gone for the night".to_string())));
+}
+
+#[test]
+async fn whox_parse_test() {
+    assert_eq!(Command::parse("WHO #rust %tcnfa,42").unwrap(), Command::WHO(Some("#rust".to_string()), Some(false), Some(WhoxQuery {
+        fields: "tcnfa".to_string(),
+        token: Some("42".to_string()),
+    })));
+}
+
+#[test]
+async fn away_parse_test() {
+    assert_eq!(Command::parse("AWAY :out to lunch").unwrap(), Command::AWAY(Some("out to lunch".to_string())));
+    assert_eq!(Command::parse("AWAY :").unwrap(), Command::AWAY(None));
+    assert_eq!(Command::parse("PRIVMSG cardinal :hello there: friend").unwrap(),
+        Command::PRIVMSG("cardinal".to_string(), "hello there: friend".to_string()));
+}
+
+#[test]
+async fn userhost_ison_parse_test() {
+    assert_eq!(Command::parse("ISON cardinal :hephaestus julian").unwrap(),
+        Command::ISON(vec!["cardinal".to_string(), "hephaestus".to_string(), "julian".to_string()]));
+    assert_eq!(Command::parse("USERHOST").unwrap(), Command::USERHOST(Vec::new()));
+}
+
+#[test]
+async fn user_mode_parse_test() {
+    assert_eq!(Command::parse("MODE cardinal +iw-x").unwrap(), Command::UserMode("cardinal".to_string(), vec![
+        Mode::Add(UserMode::Invisible),
+        Mode::Add(UserMode::Wallops),
+        Mode::Sub(UserMode::MaskedHost),
+    ]));
+    assert_eq!(Command::parse("MODE cardinal").unwrap(), Command::UserMode("cardinal".to_string(), Vec::new()));
+    assert_eq!(Mode::format_list(&[Mode::Add(UserMode::Invisible), Mode::Add(UserMode::Wallops), Mode::Sub(UserMode::Operator)]), "+iw-o");
+}
+
+#[test]
+async fn operator_command_parse_test() {
+    assert_eq!(Command::parse("KILL cardinal :flooding").unwrap(), Command::KILL("cardinal".to_string(), "flooding".to_string()));
+    assert_eq!(Command::parse("REHASH").unwrap(), Command::REHASH);
+}
+
+#[test]
+async fn server_query_parse_test() {
+    assert_eq!(Command::parse("MOTD").unwrap(), Command::MOTD(None));
+    assert_eq!(Command::parse("LUSERS * irc.local").unwrap(), Command::LUSERS(Some("*".to_string()), Some("irc.local".to_string())));
+    assert_eq!(Command::parse("TIME irc.local").unwrap(), Command::TIME(Some("irc.local".to_string())));
+}
+
+#[test]
+async fn stats_parse_test() {
+    assert_eq!(Command::parse("STATS m").unwrap(), Command::STATS(Some("m".to_string()), None));
+}
+
+#[test]
+async fn chathistory_parse_test() {
+    assert_eq!(Command::parse("CHATHISTORY LATEST #rust * 50").unwrap(),
+        Command::CHATHISTORY(vec!["LATEST".to_string(), "#rust".to_string(), "*".to_string(), "50".to_string()]));
+}
+
+#[test]
+async fn markread_parse_test() {
+    assert_eq!(Command::parse("MARKREAD #rust").unwrap(), Command::MARKREAD("#rust".to_string(), None));
+}
+
+#[test]
+async fn cap_parse_test() {
+    assert_eq!(Command::parse("CAP ls 302").unwrap(), Command::CAP("LS".to_string(), Some("302".to_string())));
+    assert_eq!(Command::parse("CAP REQ :server-time -batch").unwrap(), Command::CAP("REQ".to_string(), Some("server-time -batch".to_string())));
+}
+
+#[test]
+async fn authenticate_parse_test() {
+    assert_eq!(Command::parse("AUTHENTICATE +").unwrap(), Command::AUTHENTICATE("+".to_string()));
+}
+
+#[test]
+async fn squery_parse_test() {
+    assert_eq!(Command::parse("SQUERY NickServ :IDENTIFY hunter42").unwrap(), Command::SQUERY("NickServ".to_string(), "IDENTIFY hunter42".to_string()));
+}
+
+#[test]
+async fn channel_mode_parse_test() {
+    assert_eq!(Command::parse("MODE #rust +t").unwrap(), Command::ChannelMode("#rust".to_string(), vec![Mode::Add(ChannelMode::TopicSettability)], vec![]));
+    assert_eq!(
+        Command::parse("MODE #rust +ov-b cardinal cardinal *!*@10.*").unwrap(),
+        Command::ChannelMode(
+            "#rust".to_string(),
+            vec![Mode::Add(ChannelMode::OperatorPrivileges), Mode::Add(ChannelMode::VoiceToggle), Mode::Sub(ChannelMode::BanSet)],
+            vec!["cardinal".to_string(), "cardinal".to_string(), "*!*@10.*".to_string()],
+        )
+    );
+    assert_eq!(Command::parse("TOPIC #rust :").unwrap(), Command::TOPIC("#rust".to_string(), Some(String::new())));
+    assert_eq!(Command::parse("TOPIC #rust").unwrap(), Command::TOPIC("#rust".to_string(), None));
+}
+
+#[test]
+async fn service_parse_test() {
+    assert_eq!(
+        Command::parse("SERVICE dict * *.local 0 0 :French dictionary").unwrap(),
+        Command::SERVICE("dict".to_string(), "*".to_string(), "*.local".to_string(), "0".to_string(), "0".to_string(), "French dictionary".to_string())
+    );
+    assert_eq!(Command::parse("SERVLIST d*").unwrap(), Command::SERVLIST(Some("d*".to_string()), None));
+}
+
+#[test]
+async fn server_link_parse_test() {
+    assert_eq!(Command::parse("LINKS *.local").unwrap(), Command::LINKS(None, Some("*.local".to_string())));
+    assert_eq!(Command::parse("SQUIT irc2.local :bye").unwrap(), Command::SQUIT("irc2.local".to_string(), "bye".to_string()));
+    assert_eq!(
+        Command::parse("SJOIN 1700000000 #rust +nt :@00000000000000FF +0000000000000A01").unwrap(),
+        Command::SJOIN("1700000000".to_string(), "#rust".to_string(), "+nt".to_string(), "@00000000000000FF +0000000000000A01".to_string())
+    );
+    assert_eq!(Command::parse("UID cardinal 1 1583157909").unwrap(), Command::UID(vec!["cardinal".to_string(), "1".to_string(), "1583157909".to_string()]));
+}
@@ -0,0 +1,1031 @@
+//! the server configuration file
+//! every section is optional, missing values fall back to the defaults below
+
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+use crate::history::Retention;
+use crate::user::User;
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error(display = "couldn't read config file {:?}: {}", _0, _1)]
+    Read(PathBuf, std::io::Error),
+    #[error(display = "couldn't parse config file {:?}: {}", _0, _1)]
+    Parse(PathBuf, toml::de::Error),
+    #[error(display = "invalid value for {}: {}", _0, _1)]
+    Invalid(String, String),
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub server: ServerInfo,
+    pub tls: TlsConfig,
+    /// listeners with their own options, on top of server.listen and tls.listen
+    #[serde(rename = "listener")]
+    pub listeners: Vec<ListenerBlock>,
+    #[serde(rename = "class")]
+    pub classes: Vec<ClassBlock>,
+    pub admin: AdminInfo,
+    /// channels that exist from startup, clients are placed in the first one
+    #[serde(rename = "channel")]
+    pub channels: Vec<DefaultChannel>,
+    #[serde(rename = "oper")]
+    pub opers: Vec<OperBlock>,
+    /// external services that may register with SERVICE
+    #[serde(rename = "service")]
+    pub service_logins: Vec<ServiceBlock>,
+    /// servers that may link with this one
+    #[serde(rename = "link")]
+    pub links: Vec<LinkBlock>,
+    #[serde(rename = "ban")]
+    pub bans: Vec<BanBlock>,
+    pub history: HistoryConfig,
+    pub snapshot: SnapshotConfig,
+    pub services: ServicesConfig,
+    pub limits: Limits,
+    pub timeouts: Timeouts,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerInfo {
+    /// the name used as the prefix of every server reply
+    pub name: String,
+    pub network: String,
+    pub description: String,
+    /// addresses to accept clients on
+    pub listen: Vec<String>,
+    /// file sent to clients as the message of the day
+    pub motd: Option<PathBuf>,
+    /// secret mixed into the hosts shown for +x users, servers in a network should share it
+    /// a random key is used when unset, so masked hosts change on every restart
+    pub cloak_key: String,
+}
+
+/// contact details sent in reply to ADMIN
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdminInfo {
+    /// where the server is, such as a city and country
+    pub location: String,
+    /// who runs the server
+    pub organization: String,
+    pub email: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DefaultChannel {
+    pub name: String,
+    /// the first message in the channel's history
+    pub greeting: Option<String>,
+    /// overrides history.max_messages for this channel
+    pub max_messages: Option<usize>,
+    /// overrides history.max_age for this channel
+    pub max_age: Option<u64>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OperBlock {
+    pub name: String,
+    /// argon2 hash of the password, generated with `server --hash-password`
+    pub password: String,
+    /// user@host masks the oper may authenticate from, any host when empty
+    #[serde(default)]
+    pub hosts: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ServiceBlock {
+    /// the name the service registers and is queried as
+    pub name: String,
+    /// argon2 hash of the password the service sends with PASS
+    pub password: String,
+    /// user@host masks the service may connect from, any host when empty
+    #[serde(default)]
+    pub hosts: Vec<String>,
+}
+
+/// a server this one links to, both ends need a block naming the other
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LinkBlock {
+    /// the name the other server introduces itself with
+    pub name: String,
+    /// host and port to connect to, the link is only accepted when unset
+    #[serde(default)]
+    pub address: Option<String>,
+    /// argon2 hash of the password the other server sends with PASS
+    pub password: String,
+    /// sent as is with PASS when linking to the other server
+    pub send_password: String,
+    /// connect at startup and again whenever the link is lost
+    #[serde(default)]
+    pub autoconnect: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HistoryConfig {
+    /// where channel logs are kept, history only lives in memory when unset
+    pub directory: Option<PathBuf>,
+    /// size of each channel's history buffer
+    pub max_messages: usize,
+    /// seconds a message is kept for, 0 keeps messages until the buffer is full
+    pub max_age: u64,
+    /// most messages replayed on JOIN, 0 replays the whole buffer
+    pub replay_lines: usize,
+    /// seconds back JOIN replays messages from, 0 replays messages of any age
+    pub replay_max_age: u64,
+    /// most messages sent in reply to a single CHATHISTORY request
+    pub query_limit: usize,
+}
+
+/// listeners that only accept TLS connections, the certificate and key are read again on REHASH
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsConfig {
+    /// PEM file with the server's certificate followed by any intermediates
+    pub certificate: Option<PathBuf>,
+    /// PEM file with the certificate's private key
+    pub key: Option<PathBuf>,
+    /// addresses to accept TLS clients on
+    pub listen: Vec<String>,
+}
+
+/// one socket clients connect to, either a TCP address or a unix socket path
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ListenerBlock {
+    /// ip address and port
+    pub address: Option<String>,
+    /// unix domain socket, replaced if a stale one is left over
+    pub path: Option<PathBuf>,
+    /// clients have to complete a TLS handshake with the [tls] certificate first
+    pub tls: bool,
+    /// clients speak IRC over WebSocket, after the TLS handshake when both are set
+    pub websocket: bool,
+    /// origins browsers may open a WebSocket from, any origin when empty
+    pub origins: Vec<String>,
+    /// argon2 hash clients have to send with PASS before USER
+    pub password: Option<String>,
+    /// the connection class clients of this listener are placed in
+    pub class: Option<String>,
+}
+
+/// where a listener accepts clients
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListenAddress {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+/// limits and timeouts for clients of the listeners naming this class,
+/// anything left out follows [limits] and [timeouts]
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClassBlock {
+    pub name: String,
+    /// most clients in this class at once
+    pub max_clients: Option<usize>,
+    pub max_clients_per_ip: Option<usize>,
+    pub ping_interval: Option<u64>,
+    pub ping_timeout: Option<u64>,
+}
+
+/// a class with the defaults filled in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionClass {
+    /// None for clients of listeners without a class
+    pub name: Option<String>,
+    pub max_clients: usize,
+    pub max_clients_per_ip: usize,
+    pub ping_interval: u64,
+    pub ping_timeout: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SnapshotConfig {
+    /// file channel settings and read markers are saved to, nothing is saved when unset
+    pub path: Option<PathBuf>,
+    /// seconds between snapshots, 0 only saves on shutdown
+    pub interval: u64,
+}
+
+/// the services built into the server
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServicesConfig {
+    /// seconds a user has to identify for a registered nickname before being renamed
+    pub nick_grace: u64,
+    /// users who don't identify in time are renamed to this followed by a number
+    pub guest_prefix: String,
+    /// how many memos MemoServ keeps for an account before refusing new ones
+    pub max_memos: usize,
+}
+
+/// refuses clients matching a user@host or user@ip mask
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BanBlock {
+    pub mask: String,
+    /// sent to the client when they are refused
+    #[serde(default)]
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    pub max_clients: usize,
+    pub max_clients_per_ip: usize,
+    pub max_channels_per_user: usize,
+    pub nick_length: usize,
+    /// how many old nicknames are remembered for WHOWAS
+    pub whowas_size: usize,
+}
+
+/// all timeouts are in seconds
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Timeouts {
+    /// how long a new connection has to finish the USER / PONG handshake
+    pub registration: u64,
+    /// how long a client may stay quiet before the server sends a PING
+    pub ping_interval: u64,
+    /// how long the server waits for the PONG before dropping the client
+    pub ping_timeout: u64,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            server: ServerInfo::default(),
+            tls: TlsConfig::default(),
+            listeners: Vec::new(),
+            classes: Vec::new(),
+            admin: AdminInfo::default(),
+            channels: vec![DefaultChannel {
+                name: String::from("Welcome"),
+                greeting: Some(String::from("welcome to the IRC server, you are now in the welcome channel.")),
+                max_messages: None,
+                max_age: None,
+            }],
+            opers: Vec::new(),
+            service_logins: Vec::new(),
+            links: Vec::new(),
+            bans: Vec::new(),
+            history: HistoryConfig::default(),
+            snapshot: SnapshotConfig::default(),
+            services: ServicesConfig::default(),
+            limits: Limits::default(),
+            timeouts: Timeouts::default(),
+        }
+    }
+}
+
+impl Default for ServerInfo {
+    fn default() -> Self {
+        Self {
+            name: String::from("networkingirc"),
+            network: String::from("networkingirc"),
+            description: String::from("simple partial IRC server"),
+            listen: Vec::new(),
+            motd: None,
+            cloak_key: String::new(),
+        }
+    }
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self {
+            directory: None,
+            max_messages: 1000,
+            max_age: 0,
+            replay_lines: 100,
+            replay_max_age: 0,
+            query_limit: 100,
+        }
+    }
+}
+
+impl Default for SnapshotConfig {
+    fn default() -> Self {
+        Self {
+            path: None,
+            interval: 300,
+        }
+    }
+}
+
+impl Default for ServicesConfig {
+    fn default() -> Self {
+        Self {
+            nick_grace: 60,
+            guest_prefix: String::from("Guest"),
+            max_memos: 20,
+        }
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_clients: 1024,
+            max_clients_per_ip: 16,
+            max_channels_per_user: 32,
+            nick_length: 30,
+            whowas_size: 256,
+        }
+    }
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            registration: 30,
+            ping_interval: 120,
+            ping_timeout: 60,
+        }
+    }
+}
+
+impl OperBlock {
+    /// true when the user@ip of a client is allowed to use this block, the hostname
+    /// a client sends in USER is its own choice so it is never matched
+    pub fn host_allowed(&self, user: &User) -> bool {
+        let mask_target = format!("{}@{}", user.username(), user.ip_address());
+        self.hosts.is_empty() || self.hosts.iter().any(|mask| crate::wildcard_match(mask, &mask_target))
+    }
+
+    pub async fn verify(&self, password: &str) -> bool {
+        crate::auth::verify_password_async(password, &self.password).await
+    }
+}
+
+impl ServiceBlock {
+    /// true when the host or ip a service connects from is allowed to use this block
+    pub fn host_allowed(&self, hostname: &str, ip: &str) -> bool {
+        self.hosts.is_empty() || self.hosts.iter().any(|mask| {
+            crate::wildcard_match(mask, &format!("{}@{}", self.name, hostname))
+                || crate::wildcard_match(mask, &format!("{}@{}", self.name, ip))
+        })
+    }
+
+    pub async fn verify(&self, password: &str) -> bool {
+        crate::auth::verify_password_async(password, &self.password).await
+    }
+}
+
+impl LinkBlock {
+    pub async fn verify(&self, password: &str) -> bool {
+        crate::auth::verify_password_async(password, &self.password).await
+    }
+}
+
+impl HistoryConfig {
+    /// the retention for channels without their own
+    pub fn retention(&self) -> Retention {
+        Retention {
+            max_messages: self.max_messages,
+            max_age: Some(self.max_age).filter(|max| *max > 0),
+        }
+    }
+
+    pub fn replay_lines(&self) -> Option<usize> {
+        Some(self.replay_lines).filter(|lines| *lines > 0)
+    }
+
+    pub fn replay_max_age(&self) -> Option<u64> {
+        Some(self.replay_max_age).filter(|max| *max > 0)
+    }
+}
+
+impl BanBlock {
+    pub fn matches(&self, username: &str, hostname: &str, ip: &str) -> bool {
+        crate::wildcard_match(&self.mask, &format!("{}@{}", username, hostname))
+            || crate::wildcard_match(&self.mask, &format!("{}@{}", username, ip))
+    }
+}
+
+impl ListenerBlock {
+    pub fn endpoint(&self) -> Option<ListenAddress> {
+        match (&self.address, &self.path) {
+            (Some(address), None) => address.parse().ok().map(ListenAddress::Tcp),
+            (None, Some(path)) => Some(ListenAddress::Unix(path.clone())),
+            _ => None,
+        }
+    }
+
+    /// checks the PASS a client sent, a missing PASS is refused when the listener has a password
+    pub async fn verify(&self, password: Option<&str>) -> bool {
+        match (&self.password, password) {
+            (Some(hash), Some(password)) => crate::auth::verify_password_async(password, hash).await,
+            (Some(_), None) => false,
+            (None, _) => true,
+        }
+    }
+}
+
+impl ConnectionClass {
+    /// whether one more client from `ip` fits alongside the clients already on this server,
+    /// the per ip limit and the class' own limit only count clients in the same class
+    pub fn admits<'a>(&self, max_clients: usize, clients: impl IntoIterator<Item = &'a User>, ip: &str) -> bool {
+        let (mut connected, mut in_class, mut from_address) = (0, 0, 0);
+        for user in clients {
+            connected += 1;
+            if user.connection_class() == self.name.as_deref() {
+                in_class += 1;
+                from_address += (user.ip_address() == ip) as usize;
+            }
+        }
+        connected < max_clients && in_class < self.max_clients && from_address < self.max_clients_per_ip
+    }
+}
+
+impl std::fmt::Display for ListenAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ListenAddress::Tcp(address) => write!(f, "{}", address),
+            ListenAddress::Unix(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+impl ServerConfig {
+    /// reads and parses a config file without validating it, so command line
+    /// overrides can be applied first
+    pub fn read(path: &Path) -> Result<Self, ConfigError> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| ConfigError::Read(path.to_path_buf(), e))?;
+        Self::parse(&contents)
+            .map_err(|e| ConfigError::Parse(path.to_path_buf(), e))
+    }
+
+    /// read for a running server, so the file is read without blocking the async workers
+    pub async fn read_async(path: &Path) -> Result<Self, ConfigError> {
+        let contents = tokio::fs::read_to_string(path).await
+            .map_err(|e| ConfigError::Read(path.to_path_buf(), e))?;
+        Self::parse(&contents)
+            .map_err(|e| ConfigError::Parse(path.to_path_buf(), e))
+    }
+
+    pub fn parse(contents: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str(contents)
+    }
+
+    /// the channel new clients are placed in
+    pub fn welcome_channel(&self) -> &str {
+        self.channels[0].name.as_str()
+    }
+
+    /// the retention of every configured channel, None for those using the default
+    pub fn channel_retention(&self) -> Vec<(&str, Option<Retention>)> {
+        let default = self.history.retention();
+        self.channels.iter()
+            .map(|channel| {
+                if channel.max_messages.is_none() && channel.max_age.is_none() {
+                    return (channel.name.as_str(), None);
+                }
+                (channel.name.as_str(), Some(Retention {
+                    max_messages: channel.max_messages.unwrap_or(default.max_messages),
+                    max_age: channel.max_age.map(Some).unwrap_or(default.max_age).filter(|max| *max > 0),
+                }))
+            })
+            .collect()
+    }
+
+    /// every listener, server.listen and tls.listen become listeners without a password or class
+    pub fn all_listeners(&self) -> Vec<ListenerBlock> {
+        let plain = self.server.listen.iter().map(|address| ListenerBlock {
+            address: Some(address.clone()),
+            ..ListenerBlock::default()
+        });
+        let tls = self.tls.listen.iter().map(|address| ListenerBlock {
+            address: Some(address.clone()),
+            tls: true,
+            ..ListenerBlock::default()
+        });
+        plain.chain(tls).chain(self.listeners.iter().cloned()).collect()
+    }
+
+    /// the listener accepting clients on an address
+    pub fn find_listener(&self, endpoint: &ListenAddress) -> Option<ListenerBlock> {
+        self.all_listeners().into_iter().find(|listener| listener.endpoint().as_ref() == Some(endpoint))
+    }
+
+    /// the limits for clients in a class, an unknown or missing class gets the defaults
+    pub fn connection_class(&self, name: Option<&str>) -> ConnectionClass {
+        let block = name.and_then(|name| self.classes.iter().find(|class| class.name == name));
+        ConnectionClass {
+            name: block.map(|class| class.name.clone()),
+            max_clients: block.and_then(|class| class.max_clients).unwrap_or(self.limits.max_clients),
+            max_clients_per_ip: block.and_then(|class| class.max_clients_per_ip).unwrap_or(self.limits.max_clients_per_ip),
+            ping_interval: block.and_then(|class| class.ping_interval).unwrap_or(self.timeouts.ping_interval),
+            ping_timeout: block.and_then(|class| class.ping_timeout).unwrap_or(self.timeouts.ping_timeout),
+        }
+    }
+
+    /// whether swapping in `new` on a REHASH leaves changes that only a restart applies,
+    /// listener options such as passwords apply to new clients straight away
+    /// but opening and closing sockets needs a restart
+    pub fn needs_restart(&self, new: &ServerConfig) -> bool {
+        let endpoints = |config: &ServerConfig| config.all_listeners().iter()
+            .filter_map(|listener| listener.endpoint())
+            .map(|endpoint| endpoint.to_string())
+            .collect::<HashSet<String>>();
+        self.server.name != new.server.name || endpoints(self) != endpoints(new)
+    }
+
+    /// the block for a service name, ignoring case
+    pub fn find_service(&self, name: &str) -> Option<&ServiceBlock> {
+        self.service_logins.iter().find(|service| service.name.eq_ignore_ascii_case(name))
+    }
+
+    /// the block for a server name, ignoring case
+    pub fn find_link(&self, name: &str) -> Option<&LinkBlock> {
+        self.links.iter().find(|link| link.name.eq_ignore_ascii_case(name))
+    }
+
+    /// the first ban matching a client, if any
+    pub fn find_ban(&self, username: &str, hostname: &str, ip: &str) -> Option<&BanBlock> {
+        self.bans.iter().find(|ban| ban.matches(username, hostname, ip))
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |field: &str, reason: String| Err(ConfigError::Invalid(field.to_string(), reason));
+
+        if self.server.name.is_empty() || self.server.name.contains(' ') {
+            return invalid("server.name", format!("{:?} must be non empty and contain no spaces", self.server.name));
+        }
+        if self.server.network.contains(' ') {
+            return invalid("server.network", format!("{:?} must not contain spaces", self.server.network));
+        }
+        if self.server.listen.is_empty() && self.tls.listen.is_empty() && self.listeners.is_empty() {
+            return invalid("server.listen", String::from("at least one address is required"));
+        }
+        for address in self.server.listen.iter() {
+            if address.parse::<SocketAddr>().is_err() {
+                return invalid("server.listen", format!("{:?} is not an ip address and port", address));
+            }
+        }
+        for address in self.tls.listen.iter() {
+            if address.parse::<SocketAddr>().is_err() {
+                return invalid("tls.listen", format!("{:?} is not an ip address and port", address));
+            }
+        }
+        if !self.tls.listen.is_empty() && (self.tls.certificate.is_none() || self.tls.key.is_none()) {
+            return invalid("tls.listen", String::from("a certificate and key are required to accept TLS clients"));
+        }
+
+        let mut names = HashSet::new();
+        for class in self.classes.iter() {
+            if class.name.is_empty() || class.name.contains(' ') {
+                return invalid("class.name", format!("{:?} must be non empty and contain no spaces", class.name));
+            }
+            if !names.insert(class.name.as_str()) {
+                return invalid("class.name", format!("{:?} is listed more than once", class.name));
+            }
+            if class.max_clients == Some(0) || class.max_clients_per_ip == Some(0) {
+                return invalid("class.max_clients", format!("class {:?} must allow at least one client", class.name));
+            }
+            if class.ping_interval == Some(0) || class.ping_timeout == Some(0) {
+                return invalid("class.ping_interval", format!("class {:?} timeouts must be greater than zero", class.name));
+            }
+        }
+        for listener in self.listeners.iter() {
+            let endpoint = match (&listener.address, &listener.path) {
+                (Some(address), None) => match address.parse::<SocketAddr>() {
+                    Ok(_) => address.clone(),
+                    Err(_) => return invalid("listener.address", format!("{:?} is not an ip address and port", address)),
+                },
+                (None, Some(path)) => path.display().to_string(),
+                _ => return invalid("listener", String::from("each listener needs either an address or a path")),
+            };
+            if listener.tls && (self.tls.certificate.is_none() || self.tls.key.is_none()) {
+                return invalid("listener.tls", format!("{} needs a certificate and key in [tls]", endpoint));
+            }
+            if !listener.origins.is_empty() && !listener.websocket {
+                return invalid("listener.origins", format!("{} only checks origins of WebSocket clients", endpoint));
+            }
+            if let Some(password) = &listener.password {
+                if !crate::auth::is_password_hash(password) {
+                    return invalid("listener.password", format!("{} needs an argon2 hash, generate one with --hash-password", endpoint));
+                }
+            }
+            if let Some(class) = &listener.class {
+                if !names.contains(class.as_str()) {
+                    return invalid("listener.class", format!("{} names the unknown class {:?}", endpoint, class));
+                }
+            }
+        }
+        // the same address may be given in server.listen, tls.listen and as a [[listener]]
+        let mut endpoints = HashSet::new();
+        for endpoint in self.all_listeners().iter().filter_map(|listener| listener.endpoint()) {
+            if !endpoints.insert(endpoint.to_string()) {
+                return invalid("listener", format!("{} is listed more than once", endpoint));
+            }
+        }
+
+        if self.channels.is_empty() {
+            return invalid("channel", String::from("at least one default channel is required"));
+        }
+        let mut names = HashSet::new();
+        for channel in self.channels.iter() {
+            if channel.name.is_empty() || channel.name.contains([' ', ',']) {
+                return invalid("channel.name", format!("{:?} must be non empty and contain no spaces or commas", channel.name));
+            }
+            if !names.insert(channel.name.as_str()) {
+                return invalid("channel.name", format!("{:?} is listed more than once", channel.name));
+            }
+            if channel.max_messages == Some(0) {
+                return invalid("channel.max_messages", format!("{:?} must keep at least one message", channel.name));
+            }
+        }
+
+        let mut names = HashSet::new();
+        for oper in self.opers.iter() {
+            if oper.name.is_empty() || oper.name.contains(' ') {
+                return invalid("oper.name", format!("{:?} must be non empty and contain no spaces", oper.name));
+            }
+            if !names.insert(oper.name.as_str()) {
+                return invalid("oper.name", format!("{:?} is listed more than once", oper.name));
+            }
+            if !crate::auth::is_password_hash(&oper.password) {
+                return invalid("oper.password", format!("oper {:?} needs an argon2 hash, generate one with --hash-password", oper.name));
+            }
+        }
+
+        let mut names = HashSet::new();
+        for service in self.service_logins.iter() {
+            if service.name.is_empty() || !service.name.chars().all(|c| c.is_ascii_alphanumeric() || "-_[]\\`^{}|".contains(c)) {
+                return invalid("service.name", format!("{:?} must be a non empty nickname", service.name));
+            }
+            if !names.insert(service.name.to_ascii_lowercase()) {
+                return invalid("service.name", format!("{:?} is listed more than once", service.name));
+            }
+            if !crate::auth::is_password_hash(&service.password) {
+                return invalid("service.password", format!("service {:?} needs an argon2 hash, generate one with --hash-password", service.name));
+            }
+        }
+
+        let mut names = HashSet::new();
+        for link in self.links.iter() {
+            if link.name.is_empty() || link.name.contains(' ') || link.name.eq_ignore_ascii_case(&self.server.name) {
+                return invalid("link.name", format!("{:?} must be non empty, contain no spaces and differ from server.name", link.name));
+            }
+            if !names.insert(link.name.to_ascii_lowercase()) {
+                return invalid("link.name", format!("{:?} is listed more than once", link.name));
+            }
+            if !crate::auth::is_password_hash(&link.password) {
+                return invalid("link.password", format!("link {:?} needs an argon2 hash, generate one with --hash-password", link.name));
+            }
+            if link.autoconnect && link.address.is_none() {
+                return invalid("link.autoconnect", format!("link {:?} needs an address to connect to", link.name));
+            }
+        }
+
+        let prefix = &self.services.guest_prefix;
+        if !prefix.starts_with(|c: char| c.is_ascii_alphabetic()) || !prefix.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return invalid("services.guest_prefix", format!("{:?} must start with a letter and only contain letters and digits", prefix));
+        }
+
+        for ban in self.bans.iter() {
+            if ban.mask.is_empty() || ban.mask.contains(' ') {
+                return invalid("ban.mask", format!("{:?} must be non empty and contain no spaces", ban.mask));
+            }
+        }
+
+        let limits = [
+            ("limits.max_clients", self.limits.max_clients),
+            ("limits.max_clients_per_ip", self.limits.max_clients_per_ip),
+            ("limits.max_channels_per_user", self.limits.max_channels_per_user),
+            ("limits.nick_length", self.limits.nick_length),
+            ("history.max_messages", self.history.max_messages),
+            ("history.query_limit", self.history.query_limit),
+        ];
+        for (field, value) in limits.iter() {
+            if *value == 0 {
+                return invalid(field, String::from("must be greater than zero"));
+            }
+        }
+        let timeouts = [
+            ("timeouts.registration", self.timeouts.registration),
+            ("timeouts.ping_interval", self.timeouts.ping_interval),
+            ("timeouts.ping_timeout", self.timeouts.ping_timeout),
+        ];
+        for (field, value) in timeouts.iter() {
+            if *value == 0 {
+                return invalid(field, String::from("must be greater than zero"));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test]
+async fn config_parse_test() {
+    let config = ServerConfig::parse(r#"
+        [server]
+        name = "irc.local"
+        listen = ["127.0.0.1:2323", "[::1]:2323"]
+
+        [[channel]]
+        name = "lobby"
+
+        [limits]
+        max_clients = 10
+    "#).unwrap();
+    assert!(config.validate().is_ok());
+    assert_eq!(config.welcome_channel(), "lobby");
+    assert_eq!(config.limits.max_clients, 10);
+    assert_eq!(config.limits.nick_length, Limits::default().nick_length);
+
+    let no_listen = ServerConfig::parse("[server]\nname = \"irc.local\"").unwrap();
+    match no_listen.validate() {
+        Err(ConfigError::Invalid(field, _)) => assert_eq!(field, "server.listen"),
+        _ => panic!("a config without listen addresses should be rejected"),
+    }
+    assert!(ServerConfig::parse("[server]\nnmae = \"typo\"").is_err());
+}
+
+#[test]
+async fn oper_block_test() {
+    let config = ServerConfig::parse(r#"
+        [server]
+        listen = ["127.0.0.1:2323"]
+
+        [[oper]]
+        name = "cardinal"
+        password = "HASH"
+        hosts = ["*@127.0.0.1"]
+    "#.replace("HASH", "$argon2id$v=19$m=19456,t=2,p=1$c29tZXNhbHQ$8l0zd5ZwnLxGC1eVlDnJtPfhxhydqiWKmwd/l6wqnjg").as_str()).unwrap();
+    assert!(config.validate().is_ok());
+    let client = |hostname: &str, ip: &str| {
+        let mut user = User::new("cardinal".to_string(), hostname.to_string(), "Julian Lazaras".to_string(), "127.0.0.1".to_string());
+        user.ip(ip.to_string());
+        user
+    };
+    assert!(config.opers[0].host_allowed(&client("hephaestus", "127.0.0.1")));
+    assert!(!config.opers[0].host_allowed(&client("hephaestus", "10.0.0.1")));
+    // sending the allowed address as the USER hostname doesn't get past the mask
+    assert!(!config.opers[0].host_allowed(&client("127.0.0.1", "10.0.0.1")));
+}
+
+#[test]
+async fn admin_config_test() {
+    let config = ServerConfig::parse("[server]\nlisten = [\"127.0.0.1:2323\"]\n[admin]\nemail = \"admin@irc.local\"").unwrap();
+    assert!(config.validate().is_ok());
+    assert_eq!(config.admin.email, "admin@irc.local");
+}
+
+#[test]
+async fn ban_config_test() {
+    let config = ServerConfig::parse("[server]\nlisten = [\"127.0.0.1:2323\"]\n[[ban]]\nmask = \"*@10.*\"\nreason = \"no spam\"").unwrap();
+    assert!(config.validate().is_ok());
+    assert!(config.find_ban("cardinal", "hephaestus", "10.0.0.1").is_some());
+    assert!(config.find_ban("cardinal", "hephaestus", "127.0.0.1").is_none());
+}
+
+#[test]
+async fn history_config_test() {
+    let config = ServerConfig::parse(r#"
+        [server]
+        listen = ["127.0.0.1:2323"]
+
+        [[channel]]
+        name = "lobby"
+        max_age = 3600
+
+        [history]
+        max_messages = 50
+    "#).unwrap();
+    assert!(config.validate().is_ok());
+    assert_eq!(config.channel_retention(), vec![("lobby", Some(Retention { max_messages: 50, max_age: Some(3600) }))]);
+}
+
+#[test]
+async fn tls_config_test() {
+    let config = ServerConfig::parse(r#"
+        [server]
+        listen = ["127.0.0.1:2323"]
+
+        [tls]
+        certificate = "server.crt"
+        key = "server.key"
+        listen = ["127.0.0.1:6697"]
+    "#).unwrap();
+    assert!(config.validate().is_ok());
+    assert!(config.all_listeners()[1].tls);
+
+    let no_key = ServerConfig::parse("[server]\nname = \"irc.local\"\n[tls]\nlisten = [\"127.0.0.1:6697\"]").unwrap();
+    match no_key.validate() {
+        Err(ConfigError::Invalid(field, _)) => assert_eq!(field, "tls.listen"),
+        _ => panic!("a TLS listener without a certificate should be rejected"),
+    }
+}
+
+#[test]
+async fn listener_config_test() {
+    let config = ServerConfig::parse(r#"
+        [server]
+        listen = ["127.0.0.1:2323"]
+
+        [[listener]]
+        path = "/run/networkingirc.sock"
+
+        [[listener]]
+        address = "[::]:6667"
+        password = "HASH"
+        class = "guests"
+
+        [[class]]
+        name = "guests"
+        max_clients_per_ip = 2
+        ping_timeout = 30
+
+        [limits]
+        max_clients = 10
+    "#.replace("HASH", "$argon2id$v=19$m=19456,t=2,p=1$c29tZXNhbHQ$8l0zd5ZwnLxGC1eVlDnJtPfhxhydqiWKmwd/l6wqnjg").as_str()).unwrap();
+    assert!(config.validate().is_ok());
+    let listeners = config.all_listeners();
+    assert_eq!(listeners.len(), 3);
+    assert_eq!(listeners[1].endpoint(), Some(ListenAddress::Unix(PathBuf::from("/run/networkingirc.sock"))));
+    let guests = config.find_listener(&ListenAddress::Tcp("[::]:6667".parse().unwrap())).unwrap();
+    let class = config.connection_class(guests.class.as_deref());
+    assert_eq!(class.max_clients_per_ip, 2);
+    assert_eq!(class.max_clients, 10);
+    assert_eq!(class.ping_timeout, 30);
+    assert_eq!(config.connection_class(None).max_clients_per_ip, Limits::default().max_clients_per_ip);
+
+    let bad_class = ServerConfig::parse("[[listener]]\naddress = \"127.0.0.1:6667\"\nclass = \"nope\"").unwrap();
+    match bad_class.validate() {
+        Err(ConfigError::Invalid(field, _)) => assert_eq!(field, "listener.class"),
+        _ => panic!("a listener naming an unknown class should be rejected"),
+    }
+}
+
+#[test]
+async fn websocket_listener_config_test() {
+    let config = ServerConfig::parse(r#"
+        [[listener]]
+        address = "127.0.0.1:8097"
+        websocket = true
+        origins = ["https://dashboard.irc.local"]
+    "#).unwrap();
+    assert!(config.validate().is_ok());
+    assert!(config.all_listeners()[0].websocket);
+
+    let plain = ServerConfig::parse("[[listener]]\naddress = \"127.0.0.1:6667\"\norigins = [\"*\"]").unwrap();
+    match plain.validate() {
+        Err(ConfigError::Invalid(field, _)) => assert_eq!(field, "listener.origins"),
+        _ => panic!("origins on a listener that isn't a WebSocket should be rejected"),
+    }
+}
+
+#[test]
+async fn services_config_test() {
+    let config = ServerConfig::parse("[server]\nlisten = [\"127.0.0.1:2323\"]\n[services]\nnick_grace = 30\nmax_memos = 5").unwrap();
+    assert!(config.validate().is_ok());
+    assert_eq!(config.services.nick_grace, 30);
+    assert_eq!(config.services.guest_prefix, "Guest");
+    assert_eq!(config.services.max_memos, 5);
+
+    let bad_prefix = ServerConfig::parse("[server]\nlisten = [\"127.0.0.1:2323\"]\n[services]\nguest_prefix = \"9 lives\"").unwrap();
+    match bad_prefix.validate() {
+        Err(ConfigError::Invalid(field, _)) => assert_eq!(field, "services.guest_prefix"),
+        _ => panic!("a guest prefix that isn't a valid nickname should be rejected"),
+    }
+}
+
+#[test]
+async fn service_block_test() {
+    let config = ServerConfig::parse(r#"
+        [server]
+        listen = ["127.0.0.1:2323"]
+
+        [[service]]
+        name = "dict"
+        password = "HASH"
+    "#.replace("HASH", "$argon2id$v=19$m=19456,t=2,p=1$c29tZXNhbHQ$8l0zd5ZwnLxGC1eVlDnJtPfhxhydqiWKmwd/l6wqnjg").as_str()).unwrap();
+    assert!(config.validate().is_ok());
+    assert!(config.find_service("DICT").unwrap().host_allowed("hephaestus", "10.0.0.1"));
+}
+
+#[test]
+async fn link_block_test() {
+    let config = ServerConfig::parse(r#"
+        [server]
+        listen = ["127.0.0.1:2323"]
+
+        [[link]]
+        name = "hub.local"
+        address = "127.0.0.1:7000"
+        password = "HASH"
+        send_password = "password"
+        autoconnect = true
+    "#.replace("HASH", "$argon2id$v=19$m=19456,t=2,p=1$c29tZXNhbHQ$8l0zd5ZwnLxGC1eVlDnJtPfhxhydqiWKmwd/l6wqnjg").as_str()).unwrap();
+    assert!(config.validate().is_ok());
+    assert!(config.find_link("HUB.local").unwrap().autoconnect);
+
+    let bad_link = ServerConfig::parse("[server]\nlisten = [\"127.0.0.1:2323\"]\n[[link]]\nname = \"hub.local\"\npassword = \"$argon2id$v=19$m=19456,t=2,p=1$c29tZXNhbHQ$8l0zd5ZwnLxGC1eVlDnJtPfhxhydqiWKmwd/l6wqnjg\"\nsend_password = \"password\"\nautoconnect = true").unwrap();
+    match bad_link.validate() {
+        Err(ConfigError::Invalid(field, _)) => assert_eq!(field, "link.autoconnect"),
+        _ => panic!("a link to autoconnect without an address should be rejected"),
+    }
+}
+
+#[test]
+async fn listener_enforcement_test() {
+    let config = ServerConfig::parse(r#"
+        [[listener]]
+        address = "[::]:6667"
+        password = "HASH"
+        class = "guests"
+
+        [[class]]
+        name = "guests"
+        max_clients = 3
+        max_clients_per_ip = 2
+
+        [limits]
+        max_clients = 4
+    "#.replace("HASH", &crate::auth::hash_password("hunter42").unwrap()).as_str()).unwrap();
+    let listener = &config.all_listeners()[0];
+    assert!(listener.verify(Some("hunter42")).await);
+    assert!(!listener.verify(Some("hunter43")).await);
+    assert!(!listener.verify(None).await);
+    assert!(ListenerBlock::default().verify(None).await);
+
+    let guest = |ip: &str| {
+        let mut user = User::new("cardinal".to_string(), "hephaestus".to_string(), "Julian Lazaras".to_string(), "127.0.0.1".to_string());
+        user.ip(ip.to_string()).class(Some("guests".to_string()));
+        user
+    };
+    let other = User::new("sibyl".to_string(), "delphi".to_string(), "Sibyl".to_string(), "127.0.0.1".to_string());
+    let class = config.connection_class(Some("guests"));
+    let clients = [guest("10.0.0.1"), guest("10.0.0.1")];
+    assert!(!class.admits(config.limits.max_clients, &clients, "10.0.0.1"));
+    assert!(class.admits(config.limits.max_clients, &clients, "10.0.0.2"));
+    // clients of other classes only count towards the server's own limit
+    let clients = [guest("10.0.0.1"), guest("10.0.0.2"), other.clone()];
+    assert!(class.admits(config.limits.max_clients, &clients, "10.0.0.3"));
+    let clients = [guest("10.0.0.1"), guest("10.0.0.2"), guest("10.0.0.3")];
+    assert!(!class.admits(config.limits.max_clients, &clients, "10.0.0.4"));
+    let clients = [guest("10.0.0.1"), other.clone(), other.clone(), other];
+    assert!(!class.admits(config.limits.max_clients, &clients, "10.0.0.2"));
+}
+
+#[test]
+async fn rehash_restart_test() {
+    let old = ServerConfig::parse("[server]\nname = \"irc.local\"\nlisten = [\"127.0.0.1:2323\"]").unwrap();
+    let same = ServerConfig::parse("[server]\nname = \"irc.local\"\nlisten = [\"127.0.0.1:2323\"]\n[limits]\nmax_clients = 10").unwrap();
+    assert!(!old.needs_restart(&same));
+
+    let renamed = ServerConfig::parse("[server]\nname = \"hub.local\"\nlisten = [\"127.0.0.1:2323\"]").unwrap();
+    assert!(old.needs_restart(&renamed));
+    let moved = ServerConfig::parse("[server]\nname = \"irc.local\"\nlisten = [\"127.0.0.1:2324\"]").unwrap();
+    assert!(old.needs_restart(&moved));
+
+    // a listener password is picked up without a restart
+    let password = ServerConfig::parse(r#"
+        [server]
+        name = "irc.local"
+
+        [[listener]]
+        address = "127.0.0.1:2323"
+        password = "$argon2id$v=19$m=19456,t=2,p=1$c29tZXNhbHQ$8l0zd5ZwnLxGC1eVlDnJtPfhxhydqiWKmwd/l6wqnjg"
+    "#).unwrap();
+    assert!(!old.needs_restart(&password));
+}
+
+#[test]
+async fn duplicate_listener_test() {
+    let twice = ServerConfig::parse("[server]\nlisten = [\"127.0.0.1:2323\"]\n[[listener]]\naddress = \"127.0.0.1:2323\"\nclass = \"guests\"\n[[class]]\nname = \"guests\"").unwrap();
+    match twice.validate() {
+        Err(ConfigError::Invalid(field, _)) => assert_eq!(field, "listener"),
+        _ => panic!("an address in both server.listen and a listener should be rejected"),
+    }
+    let twice = ServerConfig::parse("[[listener]]\npath = \"/run/irc.sock\"\n[[listener]]\npath = \"/run/irc.sock\"").unwrap();
+    assert!(twice.validate().is_err());
+    let both = ServerConfig::parse("[server]\nlisten = [\"127.0.0.1:2323\"]\n[[listener]]\naddress = \"127.0.0.1:2324\"").unwrap();
+    assert!(both.validate().is_ok());
+}
+
+#[test]
+async fn config_read_test() {
+    let path = std::env::temp_dir().join(format!("networkingirc-config-{}.toml", std::process::id()));
+    std::fs::write(&path, "[server]\nname = \"irc.local\"\nlisten = [\"127.0.0.1:2323\"]").unwrap();
+    let config = ServerConfig::read_async(&path).await.unwrap();
+    assert_eq!(config.server.name, "irc.local");
+    assert_eq!(ServerConfig::read(&path).unwrap().server.listen, config.server.listen);
+    std::fs::remove_file(&path).unwrap();
+    assert!(matches!(ServerConfig::read_async(&path).await, Err(ConfigError::Read(_, _))));
+}
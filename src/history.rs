@@ -0,0 +1,750 @@
+//! channel history, kept in memory and appended to one log file per channel
+//! so it survives a restart, the files are written by a thread of their own
+//! so callers holding the store's lock never wait on the disk
+
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+use crate::{parse_server_time, unix_time_millis};
+
+/// a single message in a channel's history
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistoryEntry {
+    /// increases by one for every message in the channel
+    pub id: u64,
+    /// unix timestamp in milliseconds of when the message was sent
+    pub time: u64,
+    pub sender: String,
+    pub text: String,
+}
+
+impl HistoryEntry {
+    /// the form history is replayed to clients in
+    pub fn display(&self) -> String {
+        format!("{} => {}", self.sender, self.text)
+    }
+
+    /// the IRCv3 msgid of the message, unique across channels
+    pub fn msgid(&self, channel: &str) -> String {
+        format!("{:08x}-{}", channel_hash(channel), self.id)
+    }
+
+    /// a tab separated log line, neither the sender nor the id can contain tabs
+    /// and IRC messages never contain line breaks
+    fn to_line(&self) -> String {
+        format!("{}\t{}\t{}\t{}\n", self.id, self.time, self.sender, self.text)
+    }
+
+    fn from_line(line: &str) -> Option<Self> {
+        let mut fields = line.splitn(4, '\t');
+        Some(Self {
+            id: fields.next()?.parse().ok()?,
+            time: millis_from_log(fields.next()?.parse().ok()?),
+            sender: fields.next()?.to_string(),
+            text: fields.next()?.to_string(),
+        })
+    }
+}
+
+/// a reference to a point in a channel's history, sent as msgid=... or timestamp=...
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageRef {
+    Id(u64),
+    /// a unix timestamp in milliseconds
+    Time(u64),
+}
+
+impl MessageRef {
+    pub fn parse(channel: &str, reference: &str) -> Option<Self> {
+        let (kind, value) = reference.split_once('=')?;
+        match kind {
+            "msgid" => {
+                let (hash, id) = value.split_once('-')?;
+                if u32::from_str_radix(hash, 16).ok()? != channel_hash(channel) {
+                    return None;
+                }
+                Some(Self::Id(id.parse().ok()?))
+            },
+            "timestamp" => Some(Self::Time(parse_server_time(value)?)),
+            _ => None,
+        }
+    }
+
+    fn is_before(&self, entry: &HistoryEntry) -> bool {
+        match self {
+            Self::Id(id) => entry.id > *id,
+            Self::Time(time) => entry.time > *time,
+        }
+    }
+
+    fn is_after(&self, entry: &HistoryEntry) -> bool {
+        match self {
+            Self::Id(id) => entry.id < *id,
+            Self::Time(time) => entry.time < *time,
+        }
+    }
+}
+
+/// a parsed IRCv3 CHATHISTORY request, the limit is always last
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HistoryQuery {
+    /// the newest messages, or only those after the reference
+    Latest(String, Option<MessageRef>, usize),
+    Before(String, MessageRef, usize),
+    After(String, MessageRef, usize),
+    Around(String, MessageRef, usize),
+    /// messages between the two references, from the first towards the second
+    Between(String, MessageRef, MessageRef, usize),
+    /// channels with messages between the two unix timestamps in milliseconds
+    Targets(u64, u64, usize),
+}
+
+/// the FAIL code and description sent back for a bad CHATHISTORY request
+#[derive(Debug, Clone, Error, PartialEq, Eq)]
+pub enum HistoryQueryError {
+    #[error(display = "NEED_MORE_PARAMS :Missing parameters")]
+    NeedMoreParams,
+    #[error(display = "UNKNOWN_COMMAND {} :Unknown command", _0)]
+    UnknownCommand(String),
+    #[error(display = "INVALID_PARAMS {} :Invalid parameter", _0)]
+    InvalidParams(String),
+}
+
+impl HistoryQuery {
+    /// parses the parameters after CHATHISTORY, limits above max_limit are lowered to it
+    pub fn parse(args: &[String], max_limit: usize) -> Result<Self, HistoryQueryError> {
+        let subcommand = args.first().ok_or(HistoryQueryError::NeedMoreParams)?.to_ascii_uppercase();
+        // the position of the limit, which is the last parameter
+        let needed = match subcommand.as_str() {
+            "BETWEEN" => 4,
+            "LATEST" | "BEFORE" | "AFTER" | "AROUND" | "TARGETS" => 3,
+            _ => return Err(HistoryQueryError::UnknownCommand(subcommand)),
+        };
+        if args.len() <= needed {
+            return Err(HistoryQueryError::NeedMoreParams);
+        }
+        let limit = args[needed].parse::<usize>()
+            .map_err(|_| HistoryQueryError::InvalidParams(args[needed].clone()))?
+            .min(max_limit);
+
+        if subcommand == "TARGETS" {
+            let time = |arg: &String| arg.strip_prefix("timestamp=")
+                .and_then(parse_server_time)
+                .ok_or_else(|| HistoryQueryError::InvalidParams(arg.clone()));
+            let (first, second) = (time(&args[1])?, time(&args[2])?);
+            return Ok(Self::Targets(first.min(second), first.max(second), limit));
+        }
+        let target = args[1].clone();
+        let reference = |arg: &String| MessageRef::parse(&target, arg)
+            .ok_or_else(|| HistoryQueryError::InvalidParams(arg.clone()));
+        Ok(match subcommand.as_str() {
+            "LATEST" if args[2] == "*" => Self::Latest(target.clone(), None, limit),
+            "LATEST" => Self::Latest(target.clone(), Some(reference(&args[2])?), limit),
+            "BEFORE" => Self::Before(target.clone(), reference(&args[2])?, limit),
+            "AFTER" => Self::After(target.clone(), reference(&args[2])?, limit),
+            "AROUND" => Self::Around(target.clone(), reference(&args[2])?, limit),
+            _ => Self::Between(target.clone(), reference(&args[2])?, reference(&args[3])?, limit),
+        })
+    }
+}
+
+/// how much history a channel keeps
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Retention {
+    /// the size of the channel's buffer, the oldest message is dropped once it is full
+    pub max_messages: usize,
+    /// in seconds, None keeps messages until they are pushed out of the buffer
+    pub max_age: Option<u64>,
+}
+
+impl Default for Retention {
+    fn default() -> Self {
+        Self {
+            max_messages: 1000,
+            max_age: None,
+        }
+    }
+}
+
+impl Retention {
+    fn expired(&self, entry: &HistoryEntry, now: u64) -> bool {
+        match self.max_age {
+            Some(max_age) => entry.time + max_age * 1000 < now,
+            None => false,
+        }
+    }
+}
+
+/// a fixed size buffer where pushing onto a full buffer drops the oldest item
+#[derive(Debug, Clone)]
+pub struct RingBuffer<T> {
+    items: VecDeque<T>,
+    capacity: usize,
+}
+
+impl<T> RingBuffer<T> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            items: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// adds an item, returning the one it pushed out if the buffer was full
+    pub fn push(&mut self, item: T) -> Option<T> {
+        if self.capacity == 0 {
+            return Some(item);
+        }
+        let dropped = if self.items.len() == self.capacity { self.items.pop_front() } else { None };
+        self.items.push_back(item);
+        dropped
+    }
+
+    /// changes the capacity, dropping the oldest items if there are now too many
+    pub fn resize(&mut self, capacity: usize) {
+        while self.items.len() > capacity {
+            self.items.pop_front();
+        }
+        self.capacity = capacity;
+    }
+
+    pub fn front(&self) -> Option<&T> {
+        self.items.front()
+    }
+
+    pub fn pop_front(&mut self) -> Option<T> {
+        self.items.pop_front()
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// oldest first
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &T> {
+        self.items.iter()
+    }
+}
+
+struct ChannelLog {
+    entries: RingBuffer<HistoryEntry>,
+    next_id: u64,
+    /// None follows the store's default
+    retention: Option<Retention>,
+    /// lines in the log file, including those retention has since dropped
+    lines_on_disk: usize,
+}
+
+impl ChannelLog {
+    fn new(capacity: usize) -> Self {
+        Self {
+            entries: RingBuffer::new(capacity),
+            next_id: 1,
+            retention: None,
+            lines_on_disk: 0,
+        }
+    }
+
+    /// sizes the buffer for the retention policy and drops expired entries
+    fn prune(&mut self, default: Retention) {
+        let retention = self.retention.unwrap_or(default);
+        if self.entries.capacity() != retention.max_messages {
+            self.entries.resize(retention.max_messages);
+        }
+        let now = unix_time_millis();
+        while let Some(oldest) = self.entries.front() {
+            if !retention.expired(oldest, now) {
+                break;
+            }
+            self.entries.pop_front();
+        }
+    }
+}
+
+/// a change to a channel's log file, queued for the writer thread
+enum LogWrite {
+    Append(String, String),
+    /// replaces the whole file, once most of it is history retention has dropped
+    Rewrite(String, String),
+    /// answered once everything queued before it has been written
+    Flush(Sender<()>),
+}
+
+/// writes the log files queued by a store, keeping each one open between messages
+fn write_logs(directory: PathBuf, writes: Receiver<LogWrite>) {
+    let mut files: HashMap<String, File> = HashMap::new();
+    for write in writes {
+        let (channel, result) = match write {
+            LogWrite::Append(channel, line) => {
+                let path = log_path(&directory, &channel);
+                let result = match files.get_mut(&channel) {
+                    Some(file) => file.write_all(line.as_bytes()),
+                    None => OpenOptions::new().create(true).append(true).open(&path).and_then(|mut file| {
+                        file.write_all(line.as_bytes())?;
+                        files.insert(channel.clone(), file);
+                        Ok(())
+                    }),
+                };
+                (channel, result)
+            },
+            LogWrite::Rewrite(channel, contents) => {
+                // the open handle still points at the file the rename replaces
+                files.remove(&channel);
+                let path = log_path(&directory, &channel);
+                let temp = path.with_extension("log.tmp");
+                let result = fs::write(&temp, contents).and_then(|_| fs::rename(&temp, &path));
+                (channel, result)
+            },
+            LogWrite::Flush(done) => {
+                let _ = done.send(());
+                continue;
+            },
+        };
+        // the message is still in memory when the log can't be written
+        if let Err(e) = result {
+            eprintln!("couldn't write history for {}: {}", channel, e);
+        }
+    }
+}
+
+/// history for every channel, backed by `<directory>/<channel>.log` when a directory is given
+pub struct HistoryStore {
+    /// queues writes for the log files, None when history is only kept in memory
+    writer: Option<Sender<LogWrite>>,
+    default_retention: Retention,
+    channels: HashMap<String, ChannelLog>,
+}
+
+impl HistoryStore {
+    /// an empty store that is only kept in memory
+    pub fn in_memory(default_retention: Retention) -> Self {
+        Self {
+            writer: None,
+            default_retention,
+            channels: HashMap::new(),
+        }
+    }
+
+    /// loads every channel log in the directory, creating it if needed, each log is sized
+    /// and pruned with its channel's own retention when it has one
+    pub fn open(directory: &Path, default_retention: Retention, channel_retention: &[(&str, Option<Retention>)]) -> io::Result<Self> {
+        fs::create_dir_all(directory)?;
+        let mut channels = HashMap::new();
+        for dir_entry in fs::read_dir(directory)? {
+            let path = dir_entry?.path();
+            let channel = match path.file_name().and_then(|name| name.to_str()).and_then(|name| name.strip_suffix(".log")) {
+                Some(name) => decode_name(name),
+                None => continue,
+            };
+            let retention = channel_retention.iter()
+                .find(|(name, _)| *name == channel)
+                .and_then(|(_, retention)| *retention);
+            let mut log = ChannelLog::new(retention.unwrap_or(default_retention).max_messages);
+            log.retention = retention;
+            for line in fs::read_to_string(&path)?.lines() {
+                log.lines_on_disk += 1;
+                // a line cut short by a crash is skipped rather than failing the whole log
+                if let Some(entry) = HistoryEntry::from_line(line) {
+                    log.next_id = log.next_id.max(entry.id + 1);
+                    log.entries.push(entry);
+                }
+            }
+            log.prune(default_retention);
+            channels.insert(channel, log);
+        }
+        let (writer, writes) = channel();
+        let directory = directory.to_path_buf();
+        std::thread::Builder::new().name(String::from("history-writer")).spawn(move || write_logs(directory, writes))?;
+        Ok(Self {
+            writer: Some(writer),
+            default_retention,
+            channels,
+        })
+    }
+
+    /// waits until every message appended so far has been written to its log
+    pub fn flush(&self) {
+        if let Some(writer) = &self.writer {
+            let (done, flushed) = channel();
+            if writer.send(LogWrite::Flush(done)).is_ok() {
+                let _ = flushed.recv();
+            }
+        }
+    }
+
+    pub fn contains(&self, channel: &str) -> bool {
+        self.channels.contains_key(channel)
+    }
+
+    /// starts an empty history for a channel, returns false when it already had one
+    pub fn create(&mut self, channel: &str) -> bool {
+        if self.contains(channel) {
+            return false;
+        }
+        self.channels.insert(channel.to_string(), ChannelLog::new(self.default_retention.max_messages));
+        true
+    }
+
+    /// the retention used for channels without their own
+    pub fn set_default_retention(&mut self, retention: Retention) {
+        self.default_retention = retention;
+        for log in self.channels.values_mut() {
+            log.prune(retention);
+        }
+    }
+
+    /// gives a channel its own retention, None returns it to the default
+    pub fn set_retention(&mut self, channel: &str, retention: Option<Retention>) {
+        self.create(channel);
+        if let Some(log) = self.channels.get_mut(channel) {
+            log.retention = retention;
+            log.prune(self.default_retention);
+        }
+    }
+
+    /// records a message, its log file is written in the background
+    pub fn append(&mut self, channel: &str, sender: &str, text: &str) -> HistoryEntry {
+        self.create(channel);
+        let log = self.channels.get_mut(channel).unwrap();
+        let entry = HistoryEntry {
+            id: log.next_id,
+            time: unix_time_millis(),
+            sender: sender.to_string(),
+            text: text.to_string(),
+        };
+        log.next_id += 1;
+        log.entries.push(entry.clone());
+        log.prune(self.default_retention);
+
+        if let Some(writer) = &self.writer {
+            // rewrite the file once most of it is history retention has dropped
+            let write = if log.lines_on_disk > 2 * log.entries.len() + 64 {
+                log.lines_on_disk = log.entries.len();
+                LogWrite::Rewrite(channel.to_string(), log.entries.iter().map(|entry| entry.to_line()).collect())
+            }else{
+                log.lines_on_disk += 1;
+                LogWrite::Append(channel.to_string(), entry.to_line())
+            };
+            let _ = writer.send(write);
+        }
+        entry
+    }
+
+    /// a channel's history, oldest first, with messages past their max age left out
+    pub fn entries(&self, channel: &str) -> Vec<&HistoryEntry> {
+        let now = unix_time_millis();
+        match self.channels.get(channel) {
+            Some(log) => {
+                let retention = log.retention.unwrap_or(self.default_retention);
+                log.entries.iter().filter(|entry| !retention.expired(entry, now)).collect()
+            },
+            None => Vec::new(),
+        }
+    }
+
+    /// answers a CHATHISTORY query for a single channel, oldest first
+    /// TARGETS spans channels so it is answered by `targets` instead
+    pub fn query(&self, query: &HistoryQuery) -> Vec<&HistoryEntry> {
+        match query {
+            HistoryQuery::Latest(channel, None, limit) => newest(self.entries(channel), *limit),
+            HistoryQuery::Latest(channel, Some(after), limit) => {
+                newest(self.entries(channel).into_iter().filter(|entry| after.is_before(entry)).collect(), *limit)
+            },
+            HistoryQuery::Before(channel, before, limit) => {
+                newest(self.entries(channel).into_iter().filter(|entry| before.is_after(entry)).collect(), *limit)
+            },
+            HistoryQuery::After(channel, after, limit) => {
+                oldest(self.entries(channel).into_iter().filter(|entry| after.is_before(entry)).collect(), *limit)
+            },
+            HistoryQuery::Around(channel, around, limit) => {
+                let (earlier, later): (Vec<&HistoryEntry>, Vec<&HistoryEntry>) = self.entries(channel).into_iter()
+                    .partition(|entry| around.is_after(entry));
+                let mut entries = newest(earlier, limit / 2);
+                let remaining = limit - entries.len();
+                entries.extend(oldest(later, remaining));
+                entries
+            },
+            HistoryQuery::Between(channel, from, to, limit) => {
+                let entries = self.entries(channel);
+                let forward = entries.iter()
+                    .filter(|entry| from.is_before(entry) && to.is_after(entry))
+                    .cloned()
+                    .collect::<Vec<&HistoryEntry>>();
+                if !forward.is_empty() {
+                    return oldest(forward, *limit);
+                }
+                // the references were given newest first
+                newest(entries.into_iter().filter(|entry| from.is_after(entry) && to.is_before(entry)).collect(), *limit)
+            },
+            HistoryQuery::Targets(..) => Vec::new(),
+        }
+    }
+
+    /// channels whose latest message falls between two unix timestamps in milliseconds
+    /// along with the time of that message, oldest first
+    pub fn targets(&self, from: u64, to: u64) -> Vec<(&str, u64)> {
+        let mut targets = self.channels.keys()
+            .filter_map(|channel| {
+                let latest = self.entries(channel).last()?.time;
+                Some((channel.as_str(), latest))
+            })
+            .filter(|(_, latest)| *latest >= from && *latest <= to)
+            .collect::<Vec<(&str, u64)>>();
+        targets.sort_by_key(|(_, latest)| *latest);
+        targets
+    }
+
+    /// the messages replayed on JOIN, at most `lines` of them and none older than `max_age` seconds
+    /// when the user has a read marker only the messages sent after the last one it covers are replayed
+    pub fn replay(&self, channel: &str, lines: Option<usize>, max_age: Option<u64>, read: Option<u64>) -> Vec<&HistoryEntry> {
+        let since = max_age.map(|max_age| unix_time_millis().saturating_sub(max_age * 1000)).unwrap_or(0);
+        let mut entries = self.entries(channel);
+        // the marker is a time, the cut is made at the id of the last message it covers
+        let last_read = read.and_then(|read| entries.iter().rev().find(|entry| entry.time <= read).map(|entry| entry.id));
+        entries.retain(|entry| entry.time >= since && last_read.map(|last_read| entry.id > last_read).unwrap_or(true));
+        newest(entries, lines.unwrap_or(usize::MAX))
+    }
+}
+
+/// log files and snapshots written before history kept milliseconds have their times in seconds,
+/// any time below this can only be seconds as in milliseconds it falls in early 1973
+const FIRST_MILLIS: u64 = 100_000_000_000;
+
+/// a timestamp read back from disk, in milliseconds whichever way it was written
+pub fn millis_from_log(time: u64) -> u64 {
+    if time < FIRST_MILLIS { time * 1000 } else { time }
+}
+
+/// how far each user has read each channel, as the unix timestamp in milliseconds of the last message read
+#[derive(Debug, Clone, Default)]
+pub struct ReadMarkers {
+    markers: HashMap<(String, String), u64>,
+}
+
+impl ReadMarkers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, account: &str, channel: &str) -> Option<u64> {
+        self.markers.get(&(account.to_string(), channel.to_string())).cloned()
+    }
+
+    /// moves a marker forward, a marker is never moved back to an earlier message
+    /// returns the marker after the update
+    pub fn set(&mut self, account: &str, channel: &str, time: u64) -> u64 {
+        let marker = self.markers.entry((account.to_string(), channel.to_string())).or_insert(time);
+        *marker = (*marker).max(time);
+        *marker
+    }
+
+    /// every (account, channel, timestamp) marker
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str, u64)> {
+        self.markers.iter().map(|((account, channel), time)| (account.as_str(), channel.as_str(), *time))
+    }
+}
+
+/// the last `limit` entries of a chronological list
+fn newest(mut entries: Vec<&HistoryEntry>, limit: usize) -> Vec<&HistoryEntry> {
+    let skip = entries.len().saturating_sub(limit);
+    entries.split_off(skip)
+}
+
+/// the first `limit` entries of a chronological list
+fn oldest(mut entries: Vec<&HistoryEntry>, limit: usize) -> Vec<&HistoryEntry> {
+    entries.truncate(limit);
+    entries
+}
+
+/// FNV-1a, used to tie a msgid to its channel, the hash needs to stay the
+/// same across builds as msgids outlive the process
+fn channel_hash(channel: &str) -> u32 {
+    channel.bytes().fold(0x811c9dc5u32, |hash, byte| (hash ^ byte as u32).wrapping_mul(0x01000193))
+}
+
+/// channel names can contain characters that aren't safe in a file name
+/// so anything other than letters, digits and a few symbols is percent encoded
+fn log_path(directory: &Path, channel: &str) -> PathBuf {
+    let mut name = String::new();
+    for byte in channel.bytes() {
+        match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'_' | b'#' | b'&' | b'+' | b'!' => name.push(byte as char),
+            _ => name.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    directory.join(format!("{}.log", name))
+}
+
+fn decode_name(name: &str) -> String {
+    let bytes = name.as_bytes();
+    let mut decoded = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = match (bytes[i], name.get(i+1..i+3)) {
+            (b'%', Some(hex)) => u8::from_str_radix(hex, 16).ok(),
+            _ => None,
+        };
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            },
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            },
+        }
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+#[test]
+async fn history_store_test() {
+    let directory = std::env::temp_dir().join(format!("networkingirc-history-{}", std::process::id()));
+    let retention = Retention { max_messages: 3, max_age: None };
+
+    let mut store = HistoryStore::open(&directory, retention, &[]).unwrap();
+    for i in 0..5 {
+        store.append("#rust/dev", "cardinal", &format!("message {}", i));
+    }
+    store.flush();
+    let texts = store.entries("#rust/dev").iter().map(|entry| entry.text.clone()).collect::<Vec<String>>();
+    assert_eq!(texts, vec!["message 2", "message 3", "message 4"]);
+
+    // a reopened store has the same history
+    let mut reopened = HistoryStore::open(&directory, retention, &[]).unwrap();
+    assert_eq!(reopened.entries("#rust/dev").len(), 3);
+    assert_eq!(reopened.entries("#rust/dev")[0].display(), "cardinal => message 2");
+    assert!(!reopened.contains("#rust"));
+
+    reopened.set_retention("#rust/dev", Some(Retention { max_messages: 1, max_age: None }));
+    reopened.set_default_retention(Retention::default());
+    assert_eq!(reopened.entries("#rust/dev").len(), 1);
+
+    let mut buffer = RingBuffer::new(2);
+    assert_eq!(buffer.push(1), None);
+    assert_eq!(buffer.push(2), None);
+    assert_eq!(buffer.push(3), Some(1));
+    assert_eq!(buffer.iter().cloned().collect::<Vec<i32>>(), vec![2, 3]);
+
+    fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+async fn history_msgid_test() {
+    let directory = std::env::temp_dir().join(format!("networkingirc-msgid-{}", std::process::id()));
+    let retention = Retention { max_messages: 3, max_age: None };
+
+    let mut store = HistoryStore::open(&directory, retention, &[]).unwrap();
+    for i in 0..5 {
+        store.append("#rust/dev", "cardinal", &format!("message {}", i));
+    }
+    store.flush();
+    // a reopened store keeps counting ids from where it left off
+    let mut reopened = HistoryStore::open(&directory, retention, &[]).unwrap();
+    let entry = reopened.append("#rust/dev", "cardinal", "message 5");
+    assert_eq!(entry.id, 6);
+    assert_eq!(entry.msgid("#rust/dev"), format!("{:08x}-6", channel_hash("#rust/dev")));
+    assert_ne!(entry.msgid("#rust/dev"), entry.msgid("#rust"));
+
+    assert_eq!(reopened.replay("#rust/dev", Some(2), None, None)[0].text, "message 4");
+    assert_eq!(reopened.replay("#rust/dev", None, Some(60), None).len(), 3);
+    assert!(reopened.replay("#rust/dev", None, None, Some(unix_time_millis())).is_empty());
+
+    fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+async fn chathistory_query_test() {
+    let mut store = HistoryStore::in_memory(Retention::default());
+    for i in 0..5 {
+        store.append("#rust/dev", "cardinal", &format!("message {}", i));
+    }
+    let args = |line: &str| line.split(' ').map(|arg| arg.to_string()).collect::<Vec<String>>();
+    let msgid = store.entries("#rust/dev")[2].msgid("#rust/dev");
+    let before = HistoryQuery::parse(&args(&format!("BEFORE #rust/dev msgid={} 10", msgid)), 100).unwrap();
+    let texts = store.query(&before).iter().map(|entry| entry.text.clone()).collect::<Vec<String>>();
+    assert_eq!(texts, vec!["message 0", "message 1"]);
+    let after = HistoryQuery::parse(&args(&format!("AFTER #rust/dev msgid={} 500", msgid)), 100).unwrap();
+    assert_eq!(after, HistoryQuery::After("#rust/dev".to_string(), MessageRef::Id(3), 100));
+    assert_eq!(store.query(&after).len(), 2);
+    let latest = HistoryQuery::parse(&args("LATEST #rust/dev * 1"), 100).unwrap();
+    assert_eq!(store.query(&latest)[0].text, "message 4");
+
+    assert_eq!(HistoryQuery::parse(&args("LATEST #rust/dev *"), 100), Err(HistoryQueryError::NeedMoreParams));
+    assert_eq!(HistoryQuery::parse(&args("LATEST #rust msgid=1-1 5"), 100), Err(HistoryQueryError::InvalidParams("msgid=1-1".to_string())));
+    assert_eq!(
+        HistoryQuery::parse(&args("TARGETS timestamp=2020-03-02T14:05:09.000Z timestamp=2020-03-01T14:05:09.000Z 5"), 100),
+        Ok(HistoryQuery::Targets(1583071509000, 1583157909000, 5)),
+    );
+}
+
+#[test]
+async fn read_marker_test() {
+    let mut markers = ReadMarkers::new();
+    assert_eq!(markers.set("cardinal", "#rust/dev", 20), 20);
+    assert_eq!(markers.set("cardinal", "#rust/dev", 10), 20);
+    assert_eq!(markers.get("cardinal", "#rust/dev"), Some(20));
+    assert_eq!(markers.get("hephaestus", "#rust/dev"), None);
+
+    // messages sent within the same second as the one read are still replayed
+    let mut store = HistoryStore::in_memory(Retention::default());
+    let read = store.append("#rust", "cardinal", "read");
+    std::thread::sleep(std::time::Duration::from_millis(5));
+    store.append("#rust", "cardinal", "unread");
+    let texts = store.replay("#rust", None, None, Some(read.time)).iter().map(|entry| entry.text.clone()).collect::<Vec<String>>();
+    assert_eq!(texts, vec!["unread"]);
+    assert_eq!(millis_from_log(1583157909), 1583157909000);
+    assert_eq!(millis_from_log(1583157909042), 1583157909042);
+}
+
+#[test]
+async fn history_restart_retention_test() {
+    let directory = std::env::temp_dir().join(format!("networkingirc-restart-{}", std::process::id()));
+    let default = Retention { max_messages: 2, max_age: None };
+    let own = [("#rust", Some(Retention { max_messages: 5, max_age: None }))];
+
+    let mut store = HistoryStore::open(&directory, default, &own).unwrap();
+    for i in 0..5 {
+        store.append("#rust", "cardinal", &format!("message {}", i));
+        store.append("#lobby", "cardinal", &format!("message {}", i));
+    }
+    store.flush();
+    // a channel keeping more than the default still has all of it after a restart
+    let reopened = HistoryStore::open(&directory, default, &own).unwrap();
+    assert_eq!(reopened.entries("#rust").len(), 5);
+    assert_eq!(reopened.entries("#lobby").len(), 2);
+
+    fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+async fn history_rewrite_test() {
+    let directory = std::env::temp_dir().join(format!("networkingirc-rewrite-{}", std::process::id()));
+    let retention = Retention { max_messages: 3, max_age: None };
+
+    let mut store = HistoryStore::open(&directory, retention, &[]).unwrap();
+    for i in 0..100 {
+        store.append("#lobby", "cardinal", &format!("message {}", i));
+    }
+    store.flush();
+    // the file was rewritten with only the retained messages once it grew past them
+    let lines = fs::read_to_string(log_path(&directory, "#lobby")).unwrap().lines().count();
+    assert!(lines < 100);
+    let reopened = HistoryStore::open(&directory, retention, &[]).unwrap();
+    let texts = reopened.entries("#lobby").iter().map(|entry| entry.text.clone()).collect::<Vec<String>>();
+    assert_eq!(texts, vec!["message 97", "message 98", "message 99"]);
+
+    fs::remove_dir_all(&directory).unwrap();
+}
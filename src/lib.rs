@@ -1,3 +1,5 @@
+// err_derive generates its impls inside an anonymous const
+#![allow(non_local_definitions)]
 #[macro_use]extern crate serde_derive;
 #[macro_use]extern crate tokio;
 #[macro_use]extern crate err_derive;
@@ -6,8 +8,21 @@ use structopt::StructOpt;
 pub mod command;
 pub mod channel;
 pub mod message;
+pub mod network;
 pub mod user;
 pub mod client;
+pub mod account;
+pub mod auth;
+pub mod capability;
+pub mod config;
+pub mod history;
+pub mod memo;
+pub mod response;
+pub mod sasl;
+pub mod service;
+pub mod snapshot;
+pub mod stats;
+pub mod tls;
 
 pub enum ChannelOp {
     KICK,
@@ -21,8 +36,15 @@ pub enum ChannelOp {
 pub struct Args {
     /// contains the address and port either to bind to
     /// or to connect to depending on wether or not running server or client
+    /// for the server this replaces the listen addresses from the config file
     #[structopt(short, long)]
-    pub address: String,
+    pub address: Option<String>,
+    /// path to the server's TOML configuration file
+    #[structopt(short, long, parse(from_os_str))]
+    pub config: Option<std::path::PathBuf>,
+    /// reads a password from stdin and prints the hash to put in an oper or service block, then exits
+    #[structopt(long)]
+    pub hash_password: bool,
 }
 
 /// this is the clients argument builder, which has a few more required arguments
@@ -43,6 +65,24 @@ pub struct ClientArgs {
     /// completely optional specifies the nickname to use when connecting to the server
     #[structopt(short, long)]
     pub nick: Option<String>,
+    /// connects over TLS, the server is checked against --ca-file or --fingerprint
+    #[structopt(long, requires = "tls-trust")]
+    pub tls: bool,
+    /// PEM bundle of the certificate authorities trusted to sign the server's certificate
+    #[structopt(long, parse(from_os_str), group = "tls-trust")]
+    pub ca_file: Option<std::path::PathBuf>,
+    /// sha256 fingerprint of the server's certificate, printed by the server at startup
+    #[structopt(long, group = "tls-trust")]
+    pub fingerprint: Option<String>,
+    /// the name the server's certificate is checked against, defaults to the address' ip
+    #[structopt(long)]
+    pub tls_name: Option<String>,
+    /// logs in to this account with SASL PLAIN while registering
+    #[structopt(long, requires = "sasl-pass")]
+    pub sasl_user: Option<String>,
+    /// the password of the --sasl-user account
+    #[structopt(long, requires = "sasl-user")]
+    pub sasl_pass: Option<String>,
 }
 
 #[derive(Debug, Error)]
@@ -59,6 +99,26 @@ pub enum IrcError {
     DoSWarning,
     #[error(display = "couldn't parse incoming message as utf8")]
     Utf8Error,
+    #[error(display = "the client didn't finish registering in time")]
+    RegistrationTimeout,
+    #[error(display = "the client didn't answer a ping in time")]
+    PingTimeout,
+    #[error(display = "the server or the client's address has too many connections")]
+    ConnectionLimit,
+    #[error(display = "the client matched a ban in the config")]
+    Banned,
+    #[error(display = "the client sent a wrong or no password for the listener")]
+    PasswordMismatch,
+    #[error(display = "the service couldn't register: {}", _0)]
+    ServiceRefused(String),
+    #[error(display = "the server link was refused: {}", _0)]
+    LinkRefused(String),
+    #[error(display = "the connection was closed by the server: {}", _0)]
+    Closed(String),
+    #[error(display = "a line was longer than the {} bytes allowed", MAX_LINE_LENGTH)]
+    LineTooLong,
+    #[error(display = "the connection fell too far behind on the lines queued for it")]
+    SendQExceeded,
 }
 
 impl From<std::io::Error> for IrcError {
@@ -67,15 +127,205 @@ impl From<std::io::Error> for IrcError {
     }
 }
 
+/// the longest line a connection may send, 8191 bytes of message tags and the 512 of the message
+pub const MAX_LINE_LENGTH: usize = 8191 + 512;
+
 use tokio::io::AsyncReadExt;
-pub async fn read_message<S: AsyncReadExt + std::marker::Unpin>(stream: &mut S) -> Result<(String, usize), IrcError> {
-    
-    let mut buffer: [u8;512] = [0;512];
-    
-    let bytes_read = stream.read(&mut buffer).await?;
-    let data = match String::from_utf8(buffer[0..bytes_read].to_vec()) {
-        Ok(string) => string,
-        Err(_) => return Err(IrcError::Utf8Error),
-    };
-    Ok((data, bytes_read))
-}
\ No newline at end of file
+
+/// splits what is read from a connection into lines, a line cut in two by the end of
+/// a read is kept until the rest of it arrives
+#[derive(Debug, Default)]
+pub struct LineBuffer {
+    pending: Vec<u8>,
+}
+
+impl LineBuffer {
+    /// the next complete line without its line ending, None once the other end has closed
+    /// the connection, nothing is lost when the future is dropped while waiting to read
+    pub async fn next_line<S: AsyncReadExt + std::marker::Unpin>(&mut self, stream: &mut S) -> Result<Option<String>, IrcError> {
+        loop {
+            if let Some(line) = self.take_line()? {
+                return Ok(Some(line));
+            }
+            if self.pending.len() > MAX_LINE_LENGTH {
+                return Err(IrcError::LineTooLong);
+            }
+            let mut buffer = [0; 4096];
+            let bytes_read = stream.read(&mut buffer).await?;
+            if bytes_read == 0 {
+                return Ok(None);
+            }
+            self.pending.extend_from_slice(&buffer[..bytes_read]);
+        }
+    }
+
+    /// the first complete line already read, if there is one
+    fn take_line(&mut self) -> Result<Option<String>, IrcError> {
+        let end = match self.pending.iter().position(|byte| *byte == b'\n') {
+            Some(end) => end,
+            None => return Ok(None),
+        };
+        let line = self.pending.drain(..=end).collect::<Vec<u8>>();
+        let line = String::from_utf8(line).map_err(|_| IrcError::Utf8Error)?;
+        Ok(Some(line.trim_end_matches(['\r', '\n']).to_string()))
+    }
+}
+
+/// seconds since the unix epoch, used for signon and idle times
+pub fn unix_time() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+/// milliseconds since the unix epoch, used where messages sent within the same second
+/// need to be told apart such as channel history and the server-time tag
+pub fn unix_time_millis() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// formats a unix timestamp as a UTC date such as "Monday March 2 2020 -- 14:05:09 +00:00"
+pub fn format_time(timestamp: u64) -> String {
+    const WEEKDAYS: [&str; 7] = ["Thursday", "Friday", "Saturday", "Sunday", "Monday", "Tuesday", "Wednesday"];
+    const MONTHS: [&str; 12] = [
+        "January", "February", "March", "April", "May", "June",
+        "July", "August", "September", "October", "November", "December",
+    ];
+    let days = timestamp / 86400;
+    let seconds = timestamp % 86400;
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{} {} {} {} -- {:02}:{:02}:{:02} +00:00",
+        WEEKDAYS[(days % 7) as usize], MONTHS[(month - 1) as usize], day, year,
+        seconds / 3600, seconds / 60 % 60, seconds % 60
+    )
+}
+
+/// formats a unix timestamp in milliseconds the way the IRCv3 server-time tag expects, "2020-03-02T14:05:09.123Z"
+pub fn format_server_time(millis: u64) -> String {
+    let timestamp = millis / 1000;
+    let seconds = timestamp % 86400;
+    let (year, month, day) = civil_from_days(timestamp / 86400);
+    format!(
+        "{}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year, month, day, seconds / 3600, seconds / 60 % 60, seconds % 60, millis % 1000
+    )
+}
+
+/// parses a server-time timestamp back into a unix timestamp in milliseconds,
+/// digits past the millisecond are dropped
+pub fn parse_server_time(time: &str) -> Option<u64> {
+    let time = time.strip_suffix('Z')?;
+    let (date, clock) = time.split_once('T')?;
+    let mut date = date.splitn(3, '-').map(|field| field.parse::<u64>());
+    let (year, month, day) = (date.next()?.ok()?, date.next()?.ok()?, date.next()?.ok()?);
+    let (clock, fraction) = clock.split_once('.').unwrap_or((clock, ""));
+    if !fraction.bytes().all(|digit| digit.is_ascii_digit()) {
+        return None;
+    }
+    let millis = format!("{:0<3}", &fraction[..fraction.len().min(3)]).parse::<u64>().ok()?;
+    let mut clock = clock.splitn(3, ':').map(|field| field.parse::<u64>());
+    let (hour, minute, second) = (clock.next()?.ok()?, clock.next()?.ok()?, clock.next()?.ok()?);
+    if year < 1970 || !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+    Some((days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second) * 1000 + millis)
+}
+
+/// converts days since 1970-01-01 into a (year, month, day) date, years are
+/// counted from March so the leap day falls at the end of the year
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let shifted = days + 719468;
+    let era = shifted / 146097;
+    let day_of_era = shifted % 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// the inverse of civil_from_days
+fn days_from_civil(year: u64, month: u64, day: u64) -> u64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let year_of_era = year % 400;
+    let month_index = if month > 2 { month - 3 } else { month + 9 };
+    let day_of_year = (153 * month_index + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// case insensitive match of an IRC mask where '*' matches any run of characters
+/// and '?' matches exactly one
+pub fn wildcard_match(mask: &str, text: &str) -> bool {
+    let mask = mask.to_ascii_lowercase().chars().collect::<Vec<char>>();
+    let text = text.to_ascii_lowercase().chars().collect::<Vec<char>>();
+    let (mut m, mut t) = (0, 0);
+    // position of the last '*' seen in the mask, and the text position it matched up to
+    let mut backtrack: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if m < mask.len() && (mask[m] == '?' || mask[m] == text[t]) {
+            m += 1;
+            t += 1;
+        }else if m < mask.len() && mask[m] == '*' {
+            backtrack = Some((m, t));
+            m += 1;
+        }else if let Some((star, matched)) = backtrack {
+            m = star + 1;
+            t = matched + 1;
+            backtrack = Some((star, matched + 1));
+        }else{
+            return false;
+        }
+    }
+    mask[m..].iter().all(|c| *c == '*')
+}
+
+#[test]
+async fn line_buffer_test() {
+    // a line split across two reads comes out whole
+    let (mut client, mut server) = tokio::io::duplex(64);
+    let mut lines = LineBuffer::default();
+    tokio::io::AsyncWriteExt::write_all(&mut client, b"NICK card").await.unwrap();
+    tokio::io::AsyncWriteExt::write_all(&mut client, b"inal\r\nJOIN #rust\nPART").await.unwrap();
+    assert_eq!(lines.next_line(&mut server).await.unwrap().as_deref(), Some("NICK cardinal"));
+    assert_eq!(lines.next_line(&mut server).await.unwrap().as_deref(), Some("JOIN #rust"));
+    // an unfinished line is dropped when the connection closes
+    std::mem::drop(client);
+    assert_eq!(lines.next_line(&mut server).await.unwrap(), None);
+
+    let (mut client, mut server) = tokio::io::duplex(MAX_LINE_LENGTH * 2);
+    tokio::io::AsyncWriteExt::write_all(&mut client, &[b'a'; MAX_LINE_LENGTH + 1]).await.unwrap();
+    assert!(matches!(LineBuffer::default().next_line(&mut server).await, Err(IrcError::LineTooLong)));
+}
+
+#[test]
+async fn wildcard_match_test() {
+    assert!(wildcard_match("*", "cardinal"));
+    assert!(wildcard_match("card*", "Cardinal"));
+    assert!(wildcard_match("*!*@hephaestus", "cardinal!cardinal@hephaestus"));
+    assert!(wildcard_match("c?rdinal", "cardinal"));
+    assert!(!wildcard_match("c?rdinal", "crdinal"));
+    assert!(!wildcard_match("*.org", "irc.local"));
+}
+
+#[test]
+async fn format_time_test() {
+    assert_eq!(format_time(0), "Thursday January 1 1970 -- 00:00:00 +00:00");
+    assert_eq!(format_time(951782400), "Tuesday February 29 2000 -- 00:00:00 +00:00");
+    assert_eq!(format_time(1583157909), "Monday March 2 2020 -- 14:05:09 +00:00");
+    assert_eq!(format_server_time(1583157909000), "2020-03-02T14:05:09.000Z");
+    assert_eq!(format_server_time(1583157909042), "2020-03-02T14:05:09.042Z");
+    assert_eq!(parse_server_time("2020-03-02T14:05:09.123Z"), Some(1583157909123));
+    assert_eq!(parse_server_time("2020-03-02T14:05:09.5Z"), Some(1583157909500));
+    assert_eq!(parse_server_time("2020-03-02T14:05:09.123456Z"), Some(1583157909123));
+    assert_eq!(parse_server_time("2000-02-29T00:00:00Z"), Some(951782400000));
+    assert_eq!(parse_server_time("2020-13-02T14:05:09.000Z"), None);
+}
@@ -0,0 +1,101 @@
+//! memos left for registered accounts to read the next time they log in, kept in the snapshot
+//! accounts are matched without regard to case and memos are numbered from 1 in the order they arrived
+
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Memo {
+    /// the account the memo was left for
+    pub account: String,
+    /// the account that sent it
+    pub from: String,
+    /// unix timestamp of when it was sent
+    pub sent: u64,
+    pub text: String,
+    #[serde(default)]
+    pub read: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Memos {
+    /// by the lowercased account name
+    memos: HashMap<String, Vec<Memo>>,
+}
+
+impl Memo {
+    pub fn new(account: String, from: String, sent: u64, text: String) -> Self {
+        Self {
+            account,
+            from,
+            sent,
+            text,
+            read: false,
+        }
+    }
+}
+
+impl Memos {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// adds a memo, returning false when the account already has `limit` memos
+    pub fn send(&mut self, memo: Memo, limit: usize) -> bool {
+        let inbox = self.memos.entry(memo.account.to_ascii_lowercase()).or_default();
+        if inbox.len() >= limit {
+            return false;
+        }
+        inbox.push(memo);
+        true
+    }
+
+    pub fn list(&self, account: &str) -> &[Memo] {
+        self.memos.get(&account.to_ascii_lowercase()).map(|inbox| inbox.as_slice()).unwrap_or_default()
+    }
+
+    pub fn unread(&self, account: &str) -> usize {
+        self.list(account).iter().filter(|memo| !memo.read).count()
+    }
+
+    /// marks a memo as read and returns it
+    pub fn read(&mut self, account: &str, number: usize) -> Option<&Memo> {
+        let memo = self.memos.get_mut(&account.to_ascii_lowercase())?.get_mut(number.checked_sub(1)?)?;
+        memo.read = true;
+        Some(memo)
+    }
+
+    /// removes a memo, the ones after it move up a number
+    pub fn remove(&mut self, account: &str, number: usize) -> Option<Memo> {
+        let inbox = self.memos.get_mut(&account.to_ascii_lowercase())?;
+        let index = number.checked_sub(1).filter(|index| *index < inbox.len())?;
+        Some(inbox.remove(index))
+    }
+
+    /// removes every memo left for an account, returning how many there were
+    pub fn clear(&mut self, account: &str) -> usize {
+        self.memos.remove(&account.to_ascii_lowercase()).map(|inbox| inbox.len()).unwrap_or(0)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Memo> {
+        self.memos.values().flatten()
+    }
+}
+
+#[test]
+async fn memos_test() {
+    let mut memos = Memos::new();
+    assert!(memos.send(Memo::new(String::from("Cardinal"), String::from("julian"), 1583157909, String::from("ping")), 2));
+    assert!(memos.send(Memo::new(String::from("cardinal"), String::from("julian"), 1583157910, String::from("pong")), 2));
+    assert!(!memos.send(Memo::new(String::from("CARDINAL"), String::from("julian"), 1583157911, String::from("full")), 2));
+    assert_eq!(memos.unread("cardinal"), 2);
+
+    assert!(memos.read("cardinal", 0).is_none());
+    assert!(memos.read("cardinal", 3).is_none());
+    assert_eq!(memos.read("Cardinal", 2).unwrap().text, "pong");
+    assert_eq!(memos.unread("cardinal"), 1);
+
+    assert_eq!(memos.remove("cardinal", 1).unwrap().text, "ping");
+    assert_eq!(memos.list("cardinal")[0].text, "pong");
+    assert_eq!(memos.clear("cardinal"), 1);
+    assert!(memos.list("cardinal").is_empty());
+}
@@ -2,6 +2,8 @@ use crate::command::{CommandParseError, Command};
 
 #[derive(Debug, Clone)]
 pub struct Message {
+    // IRCv3 tags sent before the prefix, with their values unescaped
+    tags: Vec<(String, String)>,
     // prefix stores the origin of a message
     prefix: Option<String>,
     command: Command,
@@ -13,8 +15,25 @@ pub struct Message {
 
 impl Message {
     pub fn parse(mut message_str: String) -> Result<Self, CommandParseError> {
+        // line endings are not part of the message itself
+        while message_str.ends_with('\n') || message_str.ends_with('\r') {
+            message_str.pop();
+        }
+
+        let tags = if message_str.starts_with('@') {
+            let tags_offset = match message_str.find(' ') {
+                Some(offset) => offset,
+                None => return Err(CommandParseError::PrefixOnly(message_str)),
+            };
+            let tags: String = message_str.drain(0..tags_offset+1).skip(1).collect();
+            message_str = message_str.trim_start_matches(' ').to_string();
+            parse_tags(tags.trim_end())
+        }else{
+            Vec::new()
+        };
+
         // there is a prefix so it should be extracted
-        let prefix: Option<String> = if message_str.chars().nth(0) == Some(':') {
+        let prefix: Option<String> = if message_str.starts_with(':') {
             let prefix_offset = match message_str.find(" ") {
                 Some(offset) => offset,
                 None => return Err(CommandParseError::PrefixOnly(message_str)),
@@ -27,38 +46,19 @@ impl Message {
             None
         };
 
-        let command = match Command::parse(&message_str) {
-            Ok(command) => command,
-            Err(e) => {
-                return Err(e);
-            },
-        };
+        let command = Command::parse(&message_str)?;
 
-        let (command, trailing, raw_text) = if command != Command::RAW {
-
-            let remainder = message_str.split(':').collect::<Vec<&str>>();
-            let trailing = match remainder.get(1) {
-                Some(value) => Some(value.to_string()),
-                None => None,
-            };
-
-            let command_str = match remainder.get(0) {
-                Some(command_str) => command_str,
-                None => return Err(CommandParseError::NoCommandFound(message_str)),
-            };
-
-            let command = match Command::parse(command_str) {
-                Ok(command) => command,
-                Err(e) => {
-                    return Err(e);
-                },
-            };
-            (command, trailing, command_str.to_string())
+        let (trailing, raw_text) = if command != Command::RAW {
+            match message_str.find(" :") {
+                Some(offset) => (Some(message_str[offset+2..].to_string()), message_str[..offset].to_string()),
+                None => (None, message_str),
+            }
         }else{
-            (command, None, message_str)
+            (None, message_str)
         };
 
         Ok(Self {
+            tags,
             prefix,
             command,
             trailing,
@@ -66,11 +66,17 @@ impl Message {
         })
     }
 
+    /// the value of a tag, tags sent without a value have an empty one
+    pub fn tag(&self, key: &str) -> Option<&str> {
+        self.tags.iter().find(|(name, _)| name == key).map(|(_, value)| value.as_str())
+    }
+
     pub fn prefix(&self) -> Option<&str> {
-        match &self.prefix {
-            Some(prefix) => return Some(prefix.as_str()),
-            None => return None,
-        }
+        self.prefix.as_deref()
+    }
+
+    pub fn trailing(&self) -> Option<&str> {
+        self.trailing.as_deref()
     }
 
     pub fn raw_message(&self) -> &str {
@@ -80,6 +86,46 @@ impl Message {
     pub fn command(&self) -> &Command {
         &self.command
     }
+
+    /// the command word in upper case, plain chat lines are reported as RAW
+    pub fn command_name(&self) -> String {
+        match self.command {
+            Command::RAW => String::from("RAW"),
+            _ => self.raw.split(' ').next().unwrap_or_default().to_ascii_uppercase(),
+        }
+    }
+}
+
+/// splits "a=1;b;c=x\\sy" into its keys and unescaped values
+fn parse_tags(tags: &str) -> Vec<(String, String)> {
+    tags.split(';')
+        .filter(|tag| !tag.is_empty())
+        .map(|tag| match tag.split_once('=') {
+            Some((key, value)) => (key.to_string(), unescape_tag_value(value)),
+            None => (tag.to_string(), String::new()),
+        })
+        .collect()
+}
+
+fn unescape_tag_value(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some(':') => unescaped.push(';'),
+            Some('s') => unescaped.push(' '),
+            Some('r') => unescaped.push('\r'),
+            Some('n') => unescaped.push('\n'),
+            // an unknown escape drops the backslash, a trailing one is dropped entirely
+            Some(other) => unescaped.push(other),
+            None => {},
+        }
+    }
+    unescaped
 }
 
 #[test]
@@ -98,11 +144,20 @@ async fn raw_message_test() {
     let third_str = "JOIN Welcome,myroom".to_string();
     let third = Message::parse(third_str).unwrap();
     assert!(third.command() != &Command::RAW);
+    assert_eq!(third.command_name(), "JOIN");
+    assert_eq!(second_message.command_name(), "RAW");
 
     match third.command() {
-        Command::JOIN(channels, keys, realname) => {
+        Command::JOIN(channels, _keys, _realname) => {
             assert_eq!(channels, &vec!["Welcome".to_string(), "myroom".to_string()]);
         },
         _ => panic!("unexpected type"),
     }
-}
\ No newline at end of file
+
+    let tagged = Message::parse("@time=2020-03-02T14:05:09.000Z;+draft/reply;label=a\\sb\\:c :cardinal PRIVMSG #rust :hi\r\n".to_string()).unwrap();
+    assert_eq!(tagged.tag("time"), Some("2020-03-02T14:05:09.000Z"));
+    assert_eq!(tagged.tag("+draft/reply"), Some(""));
+    assert_eq!(tagged.tag("label"), Some("a b;c"));
+    assert_eq!(tagged.prefix(), Some("cardinal"));
+    assert_eq!(tagged.command(), &Command::PRIVMSG("#rust".to_string(), "hi".to_string()));
+}
@@ -0,0 +1,271 @@
+//! the servers linked together into a network, kept as a spanning tree where every
+//! server is reached through exactly one direct link, and the users on the other servers
+//! who keep the id their own server gave them, written as 16 hex digits on links
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+
+/// a server in the network other than this one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinkedServer {
+    pub name: String,
+    /// 1 for a direct link
+    pub hops: u32,
+    /// the server it was introduced by
+    pub uplink: String,
+    pub description: String,
+    /// connection id of the direct link the server is reached through
+    pub link: u64,
+}
+
+/// a user as introduced to other servers with UID
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteUser {
+    pub nick: String,
+    pub hops: u32,
+    pub signon: u64,
+    /// user modes such as "+iw"
+    pub modes: String,
+    pub username: String,
+    pub hostname: String,
+    pub ip: String,
+    pub uid: u64,
+    pub account: Option<String>,
+    pub realname: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Network {
+    /// by the lowercased server name
+    servers: HashMap<String, LinkedServer>,
+    /// the lowercased name of the server each remote user is on
+    users: HashMap<u64, String>,
+    /// local users the rest of the network has been told about
+    announced: HashSet<u64>,
+}
+
+/// the first connection id a server hands out, the top half comes from the server's name
+/// so ids from different servers don't overlap and the bottom half counts connections
+pub fn first_id(server: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    server.to_ascii_lowercase().hash(&mut hasher);
+    (hasher.finish() << 32) | 1
+}
+
+pub fn format_uid(id: u64) -> String {
+    format!("{:016X}", id)
+}
+
+pub fn parse_uid(uid: &str) -> Option<u64> {
+    if uid.len() != 16 {
+        return None;
+    }
+    u64::from_str_radix(uid, 16).ok()
+}
+
+impl RemoteUser {
+    /// reads the parameters of a UID in the order documented on Command::UID
+    pub fn parse(params: &[String]) -> Option<Self> {
+        match params {
+            [nick, hops, signon, modes, username, hostname, ip, uid, account, realname] => Some(Self {
+                nick: nick.clone(),
+                hops: hops.parse().ok()?,
+                signon: signon.parse().ok()?,
+                modes: modes.clone(),
+                username: username.clone(),
+                hostname: hostname.clone(),
+                ip: ip.clone(),
+                uid: parse_uid(uid)?,
+                account: Some(account.clone()).filter(|account| account != "*"),
+                realname: realname.clone(),
+            }),
+            _ => None,
+        }
+    }
+
+    /// the UID line introducing the user, sent by the server they are on
+    pub fn to_line(&self, server: &str) -> String {
+        format!(
+            ":{} UID {} {} {} {} {} {} {} {} {} :{}\r\n",
+            server, self.nick, self.hops, self.signon, self.modes, self.username, self.hostname,
+            self.ip, format_uid(self.uid), self.account.as_deref().unwrap_or("*"), self.realname
+        )
+    }
+}
+
+impl Network {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&LinkedServer> {
+        self.servers.get(&name.to_ascii_lowercase())
+    }
+
+    /// adds a server, returning false when it is already in the network
+    pub fn add(&mut self, server: LinkedServer) -> bool {
+        let key = server.name.to_ascii_lowercase();
+        if self.servers.contains_key(&key) {
+            return false;
+        }
+        self.servers.insert(key, server);
+        true
+    }
+
+    /// the connection id of the direct link leading to a server
+    pub fn route(&self, name: &str) -> Option<u64> {
+        self.get(name).map(|server| server.link)
+    }
+
+    /// every server, nearest first
+    pub fn servers(&self) -> Vec<&LinkedServer> {
+        let mut servers = self.servers.values().collect::<Vec<&LinkedServer>>();
+        servers.sort_by(|a, b| a.hops.cmp(&b.hops).then_with(|| a.name.cmp(&b.name)));
+        servers
+    }
+
+    /// the servers this one is linked to directly
+    pub fn links(&self) -> Vec<&LinkedServer> {
+        self.servers().into_iter().filter(|server| server.hops == 1).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.servers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.servers.is_empty()
+    }
+
+    /// removes a server along with every server introduced through it,
+    /// returning them and the ids of the users that were on them
+    pub fn split(&mut self, name: &str) -> (Vec<LinkedServer>, Vec<u64>) {
+        let mut removed = HashSet::new();
+        if self.servers.contains_key(&name.to_ascii_lowercase()) {
+            removed.insert(name.to_ascii_lowercase());
+        }
+        loop {
+            let behind = self.servers.iter()
+                .filter(|(key, server)| !removed.contains(*key) && removed.contains(&server.uplink.to_ascii_lowercase()))
+                .map(|(key, _)| key.clone())
+                .collect::<Vec<String>>();
+            if behind.is_empty() {
+                break;
+            }
+            removed.extend(behind);
+        }
+        let users = self.users.iter()
+            .filter(|(_, server)| removed.contains(*server))
+            .map(|(uid, _)| *uid)
+            .collect::<Vec<u64>>();
+        for uid in users.iter() {
+            self.users.remove(uid);
+        }
+        let servers = removed.iter().filter_map(|key| self.servers.remove(key)).collect();
+        (servers, users)
+    }
+
+    pub fn add_user(&mut self, uid: u64, server: &str) {
+        self.users.insert(uid, server.to_ascii_lowercase());
+    }
+
+    pub fn remove_user(&mut self, uid: u64) -> bool {
+        self.users.remove(&uid).is_some()
+    }
+
+    /// the server a remote user is on, None for users on this server
+    pub fn server_of(&self, uid: u64) -> Option<&LinkedServer> {
+        self.users.get(&uid).and_then(|server| self.servers.get(server))
+    }
+
+    pub fn is_remote(&self, uid: u64) -> bool {
+        self.users.contains_key(&uid)
+    }
+
+    /// whether a remote user's server is reached through a direct link, a line naming
+    /// the user that arrives over any other link didn't come from them
+    pub fn reached_through(&self, uid: u64, link: u64) -> bool {
+        self.server_of(uid).map(|server| server.link) == Some(link)
+    }
+
+    /// how many users are on other servers
+    pub fn remote_users(&self) -> usize {
+        self.users.len()
+    }
+
+    /// marks a local user as introduced to the network
+    pub fn announce(&mut self, uid: u64) {
+        self.announced.insert(uid);
+    }
+
+    pub fn is_announced(&self, uid: u64) -> bool {
+        self.announced.contains(&uid)
+    }
+
+    /// true the first time it is called for an announced user, so a quit is only sent once
+    pub fn withdraw(&mut self, uid: u64) -> bool {
+        self.announced.remove(&uid)
+    }
+}
+
+#[test]
+async fn network_split_test() {
+    let server = |name: &str, hops: u32, uplink: &str, link: u64| LinkedServer {
+        name: name.to_string(),
+        hops,
+        uplink: uplink.to_string(),
+        description: String::new(),
+        link,
+    };
+    let mut network = Network::new();
+    assert!(network.add(server("hub.local", 1, "irc.local", 1)));
+    assert!(network.add(server("leaf.local", 2, "hub.local", 1)));
+    assert!(network.add(server("far.local", 3, "leaf.local", 1)));
+    assert!(network.add(server("other.local", 1, "irc.local", 2)));
+    assert!(!network.add(server("HUB.local", 1, "irc.local", 3)));
+    assert_eq!(network.route("far.local"), Some(1));
+    assert_eq!(network.links().len(), 2);
+
+    network.add_user(0xff, "Leaf.local");
+    network.add_user(0xa01, "other.local");
+    assert_eq!(network.server_of(0xff).unwrap().name, "leaf.local");
+    assert!(network.reached_through(0xff, 1));
+    assert!(!network.reached_through(0xff, 2));
+    // a local user is never reached through a link
+    assert!(!network.reached_through(0x10, 1));
+
+    // losing the hub takes every server behind it and their users
+    let (servers, users) = network.split("hub.local");
+    assert_eq!(servers.len(), 3);
+    assert_eq!(users, vec![0xff]);
+    assert_eq!(network.len(), 1);
+    assert!(network.is_remote(0xa01));
+
+    let user = RemoteUser {
+        nick: String::from("cardinal"),
+        hops: 1,
+        signon: 1583157909,
+        modes: String::from("+i"),
+        username: String::from("cardinal"),
+        hostname: String::from("hephaestus"),
+        ip: String::from("127.0.0.1"),
+        uid: 0xff,
+        account: None,
+        realname: String::from("Julian Lazaras"),
+    };
+    let line = user.to_line("leaf.local");
+    assert_eq!(line, ":leaf.local UID cardinal 1 1583157909 +i cardinal hephaestus 127.0.0.1 00000000000000FF * :Julian Lazaras\r\n");
+    let message = crate::message::Message::parse(line).unwrap();
+    match message.command() {
+        crate::command::Command::UID(params) => assert_eq!(RemoteUser::parse(params), Some(user)),
+        other => panic!("parsed as {:?}", other),
+    }
+}
+
+#[test]
+async fn first_id_test() {
+    assert_eq!(first_id("hub.local"), first_id("HUB.local"));
+    assert_ne!(first_id("hub.local") >> 32, first_id("leaf.local") >> 32);
+    assert_eq!(first_id("hub.local") & 0xffffffff, 1);
+}
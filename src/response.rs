@@ -0,0 +1,240 @@
+//! numeric replies sent from the server to clients
+//! see RFC 2812 section 5 for the meaning of each code
+
+use crate::command::{UserMode, WhoxQuery};
+use crate::user::User;
+
+pub const RPL_WELCOME: u16 = 1;
+pub const RPL_ISUPPORT: u16 = 5;
+pub const RPL_TRACELINK: u16 = 200;
+pub const RPL_TRACEOPERATOR: u16 = 204;
+pub const RPL_TRACEUSER: u16 = 205;
+pub const RPL_TRACESERVER: u16 = 206;
+pub const RPL_TRACESERVICE: u16 = 207;
+pub const RPL_STATSLINKINFO: u16 = 211;
+pub const RPL_STATSCOMMANDS: u16 = 212;
+pub const RPL_STATSKLINE: u16 = 216;
+pub const RPL_ENDOFSTATS: u16 = 219;
+pub const RPL_UMODEIS: u16 = 221;
+pub const RPL_SERVLIST: u16 = 234;
+pub const RPL_SERVLISTEND: u16 = 235;
+pub const RPL_STATSUPTIME: u16 = 242;
+pub const RPL_STATSOLINE: u16 = 243;
+pub const RPL_LUSERCLIENT: u16 = 251;
+pub const RPL_LUSEROP: u16 = 252;
+pub const RPL_LUSERCHANNELS: u16 = 254;
+pub const RPL_LUSERME: u16 = 255;
+pub const RPL_ADMINME: u16 = 256;
+pub const RPL_ADMINLOC1: u16 = 257;
+pub const RPL_ADMINLOC2: u16 = 258;
+pub const RPL_ADMINEMAIL: u16 = 259;
+pub const RPL_TRACEEND: u16 = 262;
+pub const RPL_AWAY: u16 = 301;
+pub const RPL_USERHOST: u16 = 302;
+pub const RPL_ISON: u16 = 303;
+pub const RPL_UNAWAY: u16 = 305;
+pub const RPL_NOWAWAY: u16 = 306;
+pub const RPL_WHOISUSER: u16 = 311;
+pub const RPL_WHOISSERVER: u16 = 312;
+pub const RPL_WHOISOPERATOR: u16 = 313;
+pub const RPL_WHOWASUSER: u16 = 314;
+pub const RPL_ENDOFWHO: u16 = 315;
+pub const RPL_WHOISIDLE: u16 = 317;
+pub const RPL_ENDOFWHOIS: u16 = 318;
+pub const RPL_WHOISCHANNELS: u16 = 319;
+pub const RPL_CHANNELMODEIS: u16 = 324;
+pub const RPL_NOTOPIC: u16 = 331;
+pub const RPL_TOPIC: u16 = 332;
+pub const RPL_VERSION: u16 = 351;
+pub const RPL_WHOREPLY: u16 = 352;
+pub const RPL_NAMREPLY: u16 = 353;
+/// WHOX reply, only carries the fields the client asked for
+pub const RPL_WHOSPCRPL: u16 = 354;
+pub const RPL_LINKS: u16 = 364;
+pub const RPL_ENDOFLINKS: u16 = 365;
+pub const RPL_ENDOFNAMES: u16 = 366;
+pub const RPL_BANLIST: u16 = 367;
+pub const RPL_ENDOFBANLIST: u16 = 368;
+pub const RPL_ENDOFWHOWAS: u16 = 369;
+pub const RPL_INFO: u16 = 371;
+pub const RPL_MOTD: u16 = 372;
+pub const RPL_ENDOFINFO: u16 = 374;
+pub const RPL_MOTDSTART: u16 = 375;
+pub const RPL_ENDOFMOTD: u16 = 376;
+pub const RPL_YOUREOPER: u16 = 381;
+pub const RPL_REHASHING: u16 = 382;
+pub const RPL_YOURESERVICE: u16 = 383;
+pub const RPL_TIME: u16 = 391;
+pub const RPL_WHOISSECURE: u16 = 671;
+pub const RPL_LOGGEDIN: u16 = 900;
+pub const RPL_LOGGEDOUT: u16 = 901;
+pub const RPL_SASLSUCCESS: u16 = 903;
+pub const RPL_SASLMECHS: u16 = 908;
+
+pub const ERR_NOSUCHNICK: u16 = 401;
+pub const ERR_NOSUCHSERVER: u16 = 402;
+pub const ERR_NOSUCHCHANNEL: u16 = 403;
+pub const ERR_CANNOTSENDTOCHAN: u16 = 404;
+pub const ERR_TOOMANYCHANNELS: u16 = 405;
+pub const ERR_WASNOSUCHNICK: u16 = 406;
+pub const ERR_NOSUCHSERVICE: u16 = 408;
+pub const ERR_INVALIDCAPCMD: u16 = 410;
+pub const ERR_NOMOTD: u16 = 422;
+pub const ERR_NOADMININFO: u16 = 423;
+pub const ERR_NONICKNAMEGIVEN: u16 = 431;
+pub const ERR_ERRONEUSNICKNAME: u16 = 432;
+pub const ERR_NICKNAMEINUSE: u16 = 433;
+pub const ERR_USERNOTINCHANNEL: u16 = 441;
+pub const ERR_NOTONCHANNEL: u16 = 442;
+pub const ERR_NEEDMOREPARAMS: u16 = 461;
+pub const ERR_ALREADYREGISTRED: u16 = 462;
+pub const ERR_PASSWDMISMATCH: u16 = 464;
+pub const ERR_YOUREBANNEDCREEP: u16 = 465;
+pub const ERR_UNKNOWNMODE: u16 = 472;
+pub const ERR_BANNEDFROMCHAN: u16 = 474;
+pub const ERR_NOPRIVILEGES: u16 = 481;
+pub const ERR_CHANOPRIVSNEEDED: u16 = 482;
+pub const ERR_RESTRICTED: u16 = 484;
+pub const ERR_NOOPERHOST: u16 = 491;
+pub const ERR_UMODEUNKNOWNFLAG: u16 = 501;
+pub const ERR_USERSDONTMATCH: u16 = 502;
+pub const ERR_NICKLOCKED: u16 = 902;
+pub const ERR_SASLFAIL: u16 = 904;
+pub const ERR_SASLTOOLONG: u16 = 905;
+pub const ERR_SASLABORTED: u16 = 906;
+pub const ERR_SASLALREADY: u16 = 907;
+
+/// formats a numeric reply, `params` is everything after the target
+/// and should already contain the ':' before any trailing parameter
+pub fn numeric(server: &str, code: u16, target: &str, params: &str) -> String {
+    format!(":{} {:03} {} {}\r\n", server, code, target, params)
+}
+
+/// where a user in a WHO reply is, the channel the reply is for with their status prefix
+/// in it and the server they are on with how many hops away it is
+pub struct WhoContext<'a> {
+    /// "*" when the reply isn't for a channel
+    pub channel: &'a str,
+    /// "@" for operators, "+" for voiced members and "" otherwise
+    pub status: &'a str,
+    pub server: &'a str,
+    /// 0 for users on this server
+    pub hops: u32,
+}
+
+/// the H(ere) / G(one) and operator flags shown in WHO replies, followed by the channel status
+pub fn who_flags(user: &User, status: &str) -> String {
+    let mut flags = String::from(if user.has_mode(&UserMode::Away) { "G" } else { "H" });
+    if user.has_mode(&UserMode::Operator) {
+        flags.push('*');
+    }
+    flags.push_str(status);
+    flags
+}
+
+/// a WHO reply for one user, or a WHOX reply with only the requested fields when `whox` is set
+pub fn who_reply(server: &str, target: &str, user: &User, context: &WhoContext, whox: Option<&WhoxQuery>) -> String {
+    let whox = match whox {
+        Some(whox) => whox,
+        None => return numeric(server, RPL_WHOREPLY, target, &format!("{} {} {} {} {} {} :{} {}",
+            context.channel, user.username(), user.visible_host(), context.server, user.display_nick(),
+            who_flags(user, context.status), context.hops, user.realname())),
+    };
+
+    // WHOX fields are always sent in this order no matter how they were requested
+    let mut fields: Vec<String> = Vec::new();
+    for field in "tcuihsnfdlaor".chars().filter(|field| whox.has(*field)) {
+        fields.push(match field {
+            't' => whox.token.clone().unwrap_or_else(|| String::from("0")),
+            'c' => context.channel.to_string(),
+            'u' => user.username().to_string(),
+            'i' => user.visible_ip().to_string(),
+            'h' => user.visible_host(),
+            's' => context.server.to_string(),
+            'n' => user.display_nick().to_string(),
+            'f' => who_flags(user, context.status),
+            'd' => context.hops.to_string(),
+            'l' => user.idle().to_string(),
+            'a' => user.account().clone().unwrap_or_else(|| String::from("0")),
+            'o' => String::from("n/a"),
+            _ => format!(":{}", user.realname()),
+        });
+    }
+    numeric(server, RPL_WHOSPCRPL, target, &fields.join(" "))
+}
+
+/// the automatic reply to someone messaging an away user, never sent in response to a NOTICE
+pub fn away_reply(server: &str, target: &str, kind: &str, recipient: &User) -> Option<String> {
+    match recipient.away_message() {
+        Some(away) if kind == "PRIVMSG" => Some(numeric(server, RPL_AWAY, target, &format!("{} :{}", recipient.display_nick(), away))),
+        _ => None,
+    }
+}
+
+/// the comment and quit reason for a KILL, the comment defaults to the killer's nick
+pub fn kill_reason<'a>(killer: &'a str, comment: &'a str) -> (&'a str, String) {
+    let comment = if comment.is_empty() { killer } else { comment };
+    (comment, format!("Killed ({} ({}))", killer, comment))
+}
+
+#[test]
+async fn who_reply_test() {
+    let mut user = User::new("cardinal".to_string(), "hephaestus".to_string(), "Julian Lazaras".to_string(), "127.0.0.1".to_string());
+    user.nick("card".to_string()).ip("127.0.0.1".to_string());
+    let lobby = WhoContext { channel: "#lobby", status: "", server: "irc.local", hops: 0 };
+    assert_eq!(who_flags(&user, ""), "H");
+    assert_eq!(who_reply("irc.local", "me", &user, &lobby, None),
+        ":irc.local 352 me #lobby cardinal hephaestus irc.local card H :0 Julian Lazaras\r\n");
+
+    user.add_mode(UserMode::Operator);
+    user.away(Some("lunch".to_string()));
+    assert_eq!(who_flags(&user, "@"), "G*@");
+    // channel operators are marked and users on other servers have their distance as the hopcount
+    let remote = WhoContext { channel: "#lobby", status: "@", server: "hub.local", hops: 2 };
+    assert_eq!(who_reply("irc.local", "me", &user, &remote, None),
+        ":irc.local 352 me #lobby cardinal hephaestus hub.local card G*@ :2 Julian Lazaras\r\n");
+}
+
+#[test]
+async fn whox_reply_test() {
+    let mut user = User::new("cardinal".to_string(), "hephaestus".to_string(), "Julian Lazaras".to_string(), "127.0.0.1".to_string());
+    user.nick("card".to_string()).ip("127.0.0.1".to_string());
+    let lobby = WhoContext { channel: "#lobby", status: "", server: "irc.local", hops: 0 };
+    let unlisted = WhoContext { channel: "*", status: "", server: "irc.local", hops: 0 };
+
+    // fields come back in the fixed order, not the order they were asked for
+    let whox = WhoxQuery::parse("rnuc");
+    assert_eq!(who_reply("irc.local", "me", &user, &lobby, Some(&whox)),
+        ":irc.local 354 me #lobby cardinal card :Julian Lazaras\r\n");
+
+    let whox = WhoxQuery::parse("tnao,42");
+    assert_eq!(who_reply("irc.local", "me", &user, &unlisted, Some(&whox)), ":irc.local 354 me 42 card 0 n/a\r\n");
+    user.login(Some("cardinal".to_string()));
+    assert_eq!(who_reply("irc.local", "me", &user, &unlisted, Some(&whox)), ":irc.local 354 me 42 card cardinal n/a\r\n");
+    // a token wasn't given so 0 is sent in its place
+    assert_eq!(who_reply("irc.local", "me", &user, &unlisted, Some(&WhoxQuery::parse("tn"))), ":irc.local 354 me 0 card\r\n");
+    assert_eq!(who_reply("irc.local", "me", &user, &unlisted, Some(&WhoxQuery::parse("l"))), ":irc.local 354 me 0\r\n");
+
+    user.add_mode(UserMode::MaskedHost);
+    assert_eq!(who_reply("irc.local", "me", &user, &unlisted, Some(&WhoxQuery::parse("i"))), ":irc.local 354 me 255.255.255.255\r\n");
+    // users on other servers are shown with their own server and its distance
+    let remote = WhoContext { channel: "#lobby", status: "+", server: "hub.local", hops: 2 };
+    assert_eq!(who_reply("irc.local", "me", &user, &remote, Some(&WhoxQuery::parse("snfd"))), ":irc.local 354 me hub.local card H+ 2\r\n");
+}
+
+#[test]
+async fn away_reply_test() {
+    let mut user = User::new("cardinal".to_string(), "hephaestus".to_string(), "Julian Lazaras".to_string(), "127.0.0.1".to_string());
+    user.nick("card".to_string());
+    assert_eq!(away_reply("irc.local", "me", "PRIVMSG", &user), None);
+
+    user.away(Some("gone fishing".to_string()));
+    assert_eq!(away_reply("irc.local", "me", "PRIVMSG", &user), Some(String::from(":irc.local 301 me card :gone fishing\r\n")));
+    assert_eq!(away_reply("irc.local", "me", "NOTICE", &user), None);
+}
+
+#[test]
+async fn kill_reason_test() {
+    assert_eq!(kill_reason("oper", "spamming"), ("spamming", String::from("Killed (oper (spamming))")));
+    assert_eq!(kill_reason("oper", ""), ("oper", String::from("Killed (oper (oper))")));
+}
@@ -0,0 +1,154 @@
+//! the parts of SASL over AUTHENTICATE that both binaries share, the payload
+//! is base64 and sent in chunks of at most 400 bytes
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+
+/// advertised as the value of the sasl capability
+pub const MECHANISMS: &str = "PLAIN,EXTERNAL";
+/// the longest AUTHENTICATE argument, a chunk this long means another one follows
+pub const CHUNK_SIZE: usize = 400;
+/// no mechanism the server supports needs a payload longer than this
+const MAX_PAYLOAD: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mechanism {
+    /// an account name and password
+    Plain,
+    /// the fingerprint of the TLS client certificate
+    External,
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum SaslError {
+    #[error(display = "a chunk was longer than {} bytes", CHUNK_SIZE)]
+    ChunkTooLong,
+    #[error(display = "the payload was longer than {} bytes", MAX_PAYLOAD)]
+    PayloadTooLong,
+    #[error(display = "the payload isn't valid base64")]
+    Base64,
+}
+
+/// what a client sends for PLAIN, the identity to act as, the account and its password
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlainCredentials {
+    pub authzid: String,
+    pub authcid: String,
+    pub password: String,
+}
+
+/// collects AUTHENTICATE chunks until the last one arrives
+#[derive(Debug, Clone, Default)]
+pub struct PayloadBuffer {
+    data: String,
+}
+
+impl std::str::FromStr for Mechanism {
+    type Err = ();
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_ascii_uppercase().as_str() {
+            "PLAIN" => Ok(Mechanism::Plain),
+            "EXTERNAL" => Ok(Mechanism::External),
+            _ => Err(()),
+        }
+    }
+}
+
+impl std::fmt::Display for Mechanism {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Mechanism::Plain => write!(f, "PLAIN"),
+            Mechanism::External => write!(f, "EXTERNAL"),
+        }
+    }
+}
+
+impl PlainCredentials {
+    /// the payload is "authzid\0authcid\0password", an empty authzid means the authcid
+    pub fn parse(payload: &[u8]) -> Option<Self> {
+        let payload = std::str::from_utf8(payload).ok()?;
+        let mut fields = payload.splitn(3, '\0');
+        let (authzid, authcid, password) = (fields.next()?, fields.next()?, fields.next()?);
+        if authcid.is_empty() {
+            return None;
+        }
+        Some(Self {
+            authzid: authzid.to_string(),
+            authcid: authcid.to_string(),
+            password: password.to_string(),
+        })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        format!("{}\0{}\0{}", self.authzid, self.authcid, self.password).into_bytes()
+    }
+}
+
+impl PayloadBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// adds a chunk, returning the decoded payload once a chunk shorter than
+    /// CHUNK_SIZE arrives, "+" stands for an empty chunk
+    pub fn push(&mut self, chunk: &str) -> Result<Option<Vec<u8>>, SaslError> {
+        if chunk.len() > CHUNK_SIZE {
+            return Err(SaslError::ChunkTooLong);
+        }
+        if chunk != "+" {
+            self.data.push_str(chunk);
+        }
+        if self.data.len() > MAX_PAYLOAD {
+            return Err(SaslError::PayloadTooLong);
+        }
+        if chunk.len() == CHUNK_SIZE {
+            return Ok(None);
+        }
+        let data = std::mem::take(&mut self.data);
+        STANDARD.decode(data).map(Some).map_err(|_| SaslError::Base64)
+    }
+}
+
+/// splits a payload into the AUTHENTICATE arguments to send, ending with "+"
+/// when the last chunk would otherwise be exactly CHUNK_SIZE long
+pub fn encode_payload(payload: &[u8]) -> Vec<String> {
+    let encoded = STANDARD.encode(payload);
+    let mut chunks = encoded.as_bytes()
+        .chunks(CHUNK_SIZE)
+        .map(|chunk| String::from_utf8_lossy(chunk).to_string())
+        .collect::<Vec<String>>();
+    if chunks.last().map(|chunk| chunk.len() == CHUNK_SIZE).unwrap_or(true) {
+        chunks.push(String::from("+"));
+    }
+    chunks
+}
+
+#[test]
+async fn sasl_payload_test() {
+    let credentials = PlainCredentials {
+        authzid: String::new(),
+        authcid: String::from("cardinal"),
+        password: String::from("hunter42"),
+    };
+    let chunks = encode_payload(&credentials.encode());
+    assert_eq!(chunks, vec![String::from("AGNhcmRpbmFsAGh1bnRlcjQy")]);
+    let mut buffer = PayloadBuffer::new();
+    let payload = buffer.push(&chunks[0]).unwrap().unwrap();
+    assert_eq!(PlainCredentials::parse(&payload), Some(credentials));
+    assert!(PlainCredentials::parse(b"\0\0hunter42").is_none());
+
+    // a payload that fills whole chunks is ended with "+"
+    let chunks = encode_payload(&[0; 600]);
+    assert_eq!(chunks.len(), 3);
+    assert_eq!(chunks[2], "+");
+    assert_eq!(buffer.push(&chunks[0]), Ok(None));
+    assert_eq!(buffer.push(&chunks[1]), Ok(None));
+    assert_eq!(buffer.push(&chunks[2]), Ok(Some(vec![0; 600])));
+
+    assert_eq!(encode_payload(b""), vec![String::from("+")]);
+    assert_eq!(buffer.push("+"), Ok(Some(Vec::new())));
+    assert_eq!(buffer.push(&"A".repeat(CHUNK_SIZE + 1)), Err(SaslError::ChunkTooLong));
+    assert_eq!(buffer.push("!!!"), Err(SaslError::Base64));
+    assert_eq!("external".parse::<Mechanism>(), Ok(Mechanism::External));
+}
@@ -0,0 +1,67 @@
+//! external services, programs that register with SERVICE instead of USER and are
+//! reached with SQUERY rather than joining channels like users
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Service {
+    name: String,
+    /// mask of the server names the service is visible to
+    distribution: String,
+    /// the service type, reserved by RFC 2812 for later use
+    kind: String,
+    info: String,
+    /// unix timestamp of when the service registered
+    registered: u64,
+}
+
+impl Service {
+    pub fn new(name: String, distribution: String, kind: String, info: String, registered: u64) -> Self {
+        Self {
+            name,
+            distribution,
+            kind,
+            info,
+            registered,
+        }
+    }
+    pub fn name(&self) -> &str {
+        self.name.as_str()
+    }
+    pub fn distribution(&self) -> &str {
+        self.distribution.as_str()
+    }
+    pub fn kind(&self) -> &str {
+        self.kind.as_str()
+    }
+    pub fn info(&self) -> &str {
+        self.info.as_str()
+    }
+    pub fn registered(&self) -> u64 {
+        self.registered
+    }
+    /// the nick!user@host its messages come from
+    pub fn sig(&self, server: &str) -> String {
+        format!("{}!{}@{}", self.name, self.name, server)
+    }
+    /// whether a SERVLIST with the given name mask and type lists the service
+    pub fn listed(&self, mask: Option<&str>, kind: Option<&str>) -> bool {
+        mask.map(|mask| crate::wildcard_match(mask, &self.name)).unwrap_or(true)
+            && kind.map(|kind| kind == "*" || kind == self.kind).unwrap_or(true)
+    }
+    /// whether a server of this name can see the service
+    pub fn visible_to(&self, server: &str) -> bool {
+        crate::wildcard_match(&self.distribution, server)
+    }
+}
+
+#[test]
+async fn service_listed_test() {
+    let service = Service::new(String::from("dict"), String::from("*.local"), String::from("0"), String::from("French dictionary"), 1583157909);
+    assert!(service.listed(None, None));
+    assert!(service.listed(Some("d*"), Some("*")));
+    assert!(service.listed(Some("DICT"), Some("0")));
+    assert!(!service.listed(Some("n*"), None));
+    assert!(!service.listed(None, Some("1")));
+    assert!(service.visible_to("irc.local"));
+    assert!(!service.visible_to("irc.example.org"));
+    assert_eq!(service.sig("irc.local"), "dict!dict@irc.local");
+}
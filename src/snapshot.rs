@@ -0,0 +1,155 @@
+//! server state saved to disk so a restart doesn't lose it,
+//! channel history has its own log files, see crate::history
+
+use std::path::{Path, PathBuf};
+
+use crate::account::{Account, Accounts};
+use crate::channel::ChannelMeta;
+use crate::history::{millis_from_log, ReadMarkers};
+use crate::memo::{Memo, Memos};
+
+#[derive(Debug, Error)]
+pub enum SnapshotError {
+    #[error(display = "couldn't read snapshot {:?}: {}", _0, _1)]
+    Read(PathBuf, std::io::Error),
+    #[error(display = "couldn't parse snapshot {:?}: {}", _0, _1)]
+    Parse(PathBuf, toml::de::Error),
+    #[error(display = "couldn't serialize snapshot: {}", _0)]
+    Serialize(toml::ser::Error),
+    #[error(display = "couldn't write snapshot {:?}: {}", _0, _1)]
+    Write(PathBuf, std::io::Error),
+}
+
+/// every section defaults to empty so snapshots from older versions still load
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Snapshot {
+    /// unix timestamp of when the snapshot was taken
+    pub taken: u64,
+    #[serde(rename = "channel")]
+    pub channels: Vec<ChannelMeta>,
+    #[serde(rename = "read_marker")]
+    pub read_markers: Vec<ReadMarker>,
+    #[serde(rename = "account")]
+    pub accounts: Vec<Account>,
+    #[serde(rename = "memo")]
+    pub memos: Vec<Memo>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReadMarker {
+    pub account: String,
+    pub channel: String,
+    /// unix timestamp in milliseconds of the last message read
+    pub time: u64,
+}
+
+impl Snapshot {
+    /// returns None when no snapshot has been saved yet
+    pub fn load(path: &Path) -> Result<Option<Self>, SnapshotError> {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(SnapshotError::Read(path.to_path_buf(), e)),
+        };
+        toml::from_str(&contents)
+            .map(Some)
+            .map_err(|e| SnapshotError::Parse(path.to_path_buf(), e))
+    }
+
+    /// writes to a temporary file first so a crash mid write leaves the last snapshot intact
+    pub fn save(&self, path: &Path) -> Result<(), SnapshotError> {
+        let contents = toml::to_string(self).map_err(SnapshotError::Serialize)?;
+        let mut temp = path.as_os_str().to_owned();
+        temp.push(".tmp");
+        let temp = PathBuf::from(temp);
+        std::fs::write(&temp, contents).map_err(|e| SnapshotError::Write(temp.clone(), e))?;
+        std::fs::rename(&temp, path).map_err(|e| SnapshotError::Write(path.to_path_buf(), e))
+    }
+
+    pub fn set_read_markers(&mut self, markers: &ReadMarkers) {
+        self.read_markers = markers.iter()
+            .map(|(account, channel, time)| ReadMarker {
+                account: account.to_string(),
+                channel: channel.to_string(),
+                time,
+            })
+            .collect();
+    }
+
+    pub fn set_accounts(&mut self, accounts: &Accounts) {
+        self.accounts = accounts.iter().cloned().collect();
+        self.accounts.sort_by(|a, b| a.name.cmp(&b.name));
+    }
+
+    pub fn accounts(&self) -> Accounts {
+        let mut accounts = Accounts::new();
+        for account in self.accounts.iter() {
+            accounts.insert(account.clone());
+        }
+        accounts
+    }
+
+    /// memos for each account stay in the order they were sent
+    pub fn set_memos(&mut self, memos: &Memos) {
+        self.memos = memos.iter().cloned().collect();
+        self.memos.sort_by_key(|memo| memo.account.to_ascii_lowercase());
+    }
+
+    pub fn memos(&self) -> Memos {
+        let mut memos = Memos::new();
+        for memo in self.memos.iter() {
+            memos.send(memo.clone(), usize::MAX);
+        }
+        memos
+    }
+
+    pub fn read_markers(&self) -> ReadMarkers {
+        let mut markers = ReadMarkers::new();
+        for marker in self.read_markers.iter() {
+            markers.set(&marker.account, &marker.channel, millis_from_log(marker.time));
+        }
+        markers
+    }
+}
+
+#[test]
+async fn snapshot_round_trip_test() {
+    use crate::command::ChannelMode;
+
+    let path = std::env::temp_dir().join(format!("networkingirc-snapshot-{}.toml", std::process::id()));
+    assert!(Snapshot::load(&path).unwrap().is_none());
+
+    let mut channel = ChannelMeta::new("#rust".to_string(), 42);
+    channel.set_topic(Some("borrow checking".to_string()));
+    channel.set_modes(vec![ChannelMode::SecretChannel, ChannelMode::Unknown('z')]);
+    let mut markers = ReadMarkers::new();
+    markers.set("cardinal", "#rust", 1583157909042);
+
+    let mut snapshot = Snapshot {
+        taken: 1583157909,
+        channels: vec![channel],
+        read_markers: Vec::new(),
+        accounts: Vec::new(),
+        memos: Vec::new(),
+    };
+    snapshot.set_read_markers(&markers);
+    let mut memos = Memos::new();
+    memos.send(Memo::new(String::from("cardinal"), String::from("julian"), 1583157909, String::from("first")), 10);
+    memos.send(Memo::new(String::from("cardinal"), String::from("julian"), 1583157910, String::from("second")), 10);
+    snapshot.set_memos(&memos);
+    let mut accounts = Accounts::new();
+    accounts.insert(Account::new(String::from("cardinal"), String::from("$argon2id$v=19$..."), 1583157909));
+    snapshot.set_accounts(&accounts);
+    snapshot.save(&path).unwrap();
+
+    let loaded = Snapshot::load(&path).unwrap().unwrap();
+    assert_eq!(loaded.channels[0].topic(), &Some("borrow checking".to_string()));
+    assert_eq!(loaded.channels[0].modes(), &vec![ChannelMode::SecretChannel, ChannelMode::Unknown('z')]);
+    // members are never restored, they have to join again
+    assert!(loaded.channels[0].users().is_empty());
+    assert_eq!(loaded.read_markers().get("cardinal", "#rust"), Some(1583157909042));
+    assert_eq!(loaded.accounts().get("Cardinal").unwrap().registered, 1583157909);
+    assert_eq!(loaded.memos().list("cardinal")[1].text, "second");
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,113 @@
+//! counters behind the STATS command
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::unix_time;
+
+/// traffic over a single client connection
+/// updated from inside reads and writes so the counters are atomic rather than locked
+#[derive(Debug)]
+pub struct LinkStats {
+    /// unix timestamp of when the connection was accepted
+    opened: u64,
+    sent_messages: AtomicU64,
+    sent_bytes: AtomicU64,
+    received_messages: AtomicU64,
+    received_bytes: AtomicU64,
+}
+
+impl LinkStats {
+    pub fn new() -> Self {
+        Self {
+            opened: unix_time(),
+            sent_messages: AtomicU64::new(0),
+            sent_bytes: AtomicU64::new(0),
+            received_messages: AtomicU64::new(0),
+            received_bytes: AtomicU64::new(0),
+        }
+    }
+
+    /// counts written data, every line feed ends a message
+    pub fn record_sent(&self, data: &[u8]) {
+        self.sent_bytes.fetch_add(data.len() as u64, Ordering::Relaxed);
+        self.sent_messages.fetch_add(count_lines(data), Ordering::Relaxed);
+    }
+
+    pub fn record_received(&self, data: &[u8]) {
+        self.received_bytes.fetch_add(data.len() as u64, Ordering::Relaxed);
+        self.received_messages.fetch_add(count_lines(data), Ordering::Relaxed);
+    }
+
+    pub fn sent_messages(&self) -> u64 {
+        self.sent_messages.load(Ordering::Relaxed)
+    }
+
+    pub fn sent_bytes(&self) -> u64 {
+        self.sent_bytes.load(Ordering::Relaxed)
+    }
+
+    pub fn received_messages(&self) -> u64 {
+        self.received_messages.load(Ordering::Relaxed)
+    }
+
+    pub fn received_bytes(&self) -> u64 {
+        self.received_bytes.load(Ordering::Relaxed)
+    }
+
+    /// seconds since the connection was accepted
+    pub fn open_for(&self) -> u64 {
+        unix_time().saturating_sub(self.opened)
+    }
+}
+
+impl Default for LinkStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn count_lines(data: &[u8]) -> u64 {
+    data.iter().filter(|byte| **byte == b'\n').count() as u64
+}
+
+/// how often a command has been used and how many bytes those messages took up
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CommandStats {
+    pub count: u64,
+    pub bytes: u64,
+    /// uses relayed by other servers, which aren't part of count or bytes
+    pub remote: u64,
+}
+
+impl CommandStats {
+    pub fn record(&mut self, bytes: usize) {
+        self.count += 1;
+        self.bytes += bytes as u64;
+    }
+
+    pub fn record_remote(&mut self) {
+        self.remote += 1;
+    }
+}
+
+/// formats a number of seconds as "days hours:minutes:seconds" for STATS u
+pub fn format_uptime(seconds: u64) -> String {
+    format!("{} days {}:{:02}:{:02}", seconds / 86400, seconds / 3600 % 24, seconds / 60 % 60, seconds % 60)
+}
+
+#[test]
+async fn link_stats_test() {
+    let stats = LinkStats::new();
+    stats.record_sent(b":irc.local PONG irc.local :1\r\n:irc.local 422 cardinal :MOTD File is missing\r\n");
+    stats.record_received(b"PING 1\r\nhalf a mess");
+    assert_eq!(stats.sent_messages(), 2);
+    assert_eq!(stats.received_messages(), 1);
+    assert_eq!(stats.received_bytes(), 19);
+
+    let mut command = CommandStats::default();
+    command.record(10);
+    command.record(5);
+    command.record_remote();
+    assert_eq!(command, CommandStats { count: 2, bytes: 15, remote: 1 });
+    assert_eq!(format_uptime(90061), "1 days 1:01:01");
+}
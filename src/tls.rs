@@ -0,0 +1,211 @@
+//! loading certificates for the server's TLS listeners and checking the
+//! server's certificate on the client side, shared by both binaries
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use rustls_pki_types::pem::PemObject;
+use rustls_pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use sha2::{Digest, Sha256};
+use tokio_rustls::rustls;
+use tokio_rustls::rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use tokio_rustls::rustls::crypto::{verify_tls12_signature, verify_tls13_signature, CryptoProvider};
+use tokio_rustls::rustls::server::danger::{ClientCertVerified, ClientCertVerifier};
+use tokio_rustls::rustls::{CertificateError, DigitallySignedStruct, DistinguishedName, RootCertStore, SignatureScheme};
+
+#[derive(Debug, Error)]
+pub enum TlsError {
+    #[error(display = "couldn't read {:?}: {}", _0, _1)]
+    Pem(PathBuf, rustls_pki_types::pem::Error),
+    #[error(display = "{:?} contains no certificates", _0)]
+    NoCertificates(PathBuf),
+    #[error(display = "{:?} is not a valid sha256 fingerprint", _0)]
+    InvalidFingerprint(String),
+    #[error(display = "{}", _0)]
+    Rustls(rustls::Error),
+}
+
+impl From<rustls::Error> for TlsError {
+    fn from(error: rustls::Error) -> TlsError {
+        TlsError::Rustls(error)
+    }
+}
+
+/// every certificate in a PEM file, in the order they appear
+pub fn load_certificates(path: &Path) -> Result<Vec<CertificateDer<'static>>, TlsError> {
+    let certificates = CertificateDer::pem_file_iter(path)
+        .and_then(|certificates| certificates.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| TlsError::Pem(path.to_path_buf(), e))?;
+    if certificates.is_empty() {
+        return Err(TlsError::NoCertificates(path.to_path_buf()));
+    }
+    Ok(certificates)
+}
+
+/// the server side of TLS, the certificate file holds the server's certificate followed by its chain
+/// clients may present a certificate of their own, which SASL EXTERNAL identifies by fingerprint
+pub fn server_config(certificate: &Path, key: &Path) -> Result<Arc<rustls::ServerConfig>, TlsError> {
+    let certificates = load_certificates(certificate)?;
+    let key = PrivateKeyDer::from_pem_file(key).map_err(|e| TlsError::Pem(key.to_path_buf(), e))?;
+    let verifier = AnyClientCertificate {
+        provider: rustls::crypto::ring::default_provider(),
+    };
+    let config = rustls::ServerConfig::builder()
+        .with_client_cert_verifier(Arc::new(verifier))
+        .with_single_cert(certificates, key)?;
+    Ok(Arc::new(config))
+}
+
+/// trusts servers whose certificate is signed by one in the CA bundle
+pub fn client_config_with_ca(bundle: &Path) -> Result<Arc<rustls::ClientConfig>, TlsError> {
+    let mut roots = RootCertStore::empty();
+    for certificate in load_certificates(bundle)? {
+        roots.add(certificate)?;
+    }
+    let config = rustls::ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    Ok(Arc::new(config))
+}
+
+/// trusts only a server presenting the certificate with this fingerprint,
+/// which suits self signed certificates
+pub fn client_config_with_fingerprint(fingerprint: &str) -> Result<Arc<rustls::ClientConfig>, TlsError> {
+    let pinned = parse_fingerprint(fingerprint).ok_or_else(|| TlsError::InvalidFingerprint(fingerprint.to_string()))?;
+    let verifier = PinnedCertificate {
+        fingerprint: pinned,
+        provider: rustls::crypto::ring::default_provider(),
+    };
+    let config = rustls::ClientConfig::builder()
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(verifier))
+        .with_no_client_auth();
+    Ok(Arc::new(config))
+}
+
+/// the sha256 of a DER certificate as colon separated hex, such as "3f:a9:..."
+pub fn fingerprint(certificate: &[u8]) -> String {
+    Sha256::digest(certificate).iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<Vec<String>>()
+        .join(":")
+}
+
+/// accepts the fingerprint in either case, with or without colons
+pub fn parse_fingerprint(fingerprint: &str) -> Option<[u8; 32]> {
+    let hex = fingerprint.chars().filter(|c| *c != ':').collect::<String>();
+    if hex.len() != 64 || !hex.is_ascii() {
+        return None;
+    }
+    let mut bytes = [0; 32];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(bytes)
+}
+
+#[derive(Debug)]
+struct PinnedCertificate {
+    fingerprint: [u8; 32],
+    provider: CryptoProvider,
+}
+
+impl ServerCertVerifier for PinnedCertificate {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        // the pin stands in for the chain, name and expiry checks
+        if Sha256::digest(end_entity.as_ref())[..] == self.fingerprint[..] {
+            Ok(ServerCertVerified::assertion())
+        }else{
+            Err(rustls::Error::InvalidCertificate(CertificateError::ApplicationVerificationFailure))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        certificate: &CertificateDer<'_>,
+        signature: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, certificate, signature, &self.provider.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        certificate: &CertificateDer<'_>,
+        signature: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, certificate, signature, &self.provider.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+/// accepts any client certificate, or none, the fingerprint is what identifies
+/// the client so there is no chain to check
+#[derive(Debug)]
+struct AnyClientCertificate {
+    provider: CryptoProvider,
+}
+
+impl ClientCertVerifier for AnyClientCertificate {
+    fn client_auth_mandatory(&self) -> bool {
+        false
+    }
+
+    fn root_hint_subjects(&self) -> &[DistinguishedName] {
+        &[]
+    }
+
+    fn verify_client_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _now: UnixTime,
+    ) -> Result<ClientCertVerified, rustls::Error> {
+        Ok(ClientCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        certificate: &CertificateDer<'_>,
+        signature: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, certificate, signature, &self.provider.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        certificate: &CertificateDer<'_>,
+        signature: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, certificate, signature, &self.provider.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+#[test]
+async fn fingerprint_test() {
+    let certificate = b"not really a certificate";
+    let printed = fingerprint(certificate);
+    assert_eq!(printed.len(), 32 * 3 - 1);
+    assert_eq!(parse_fingerprint(&printed).unwrap()[..], Sha256::digest(certificate)[..]);
+    assert_eq!(parse_fingerprint(&printed.replace(':', "").to_uppercase()), parse_fingerprint(&printed));
+    assert!(parse_fingerprint("3f:a9").is_none());
+    assert!(parse_fingerprint(&"zz".repeat(32)).is_none());
+    assert!(client_config_with_fingerprint("nope").is_err());
+}
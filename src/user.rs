@@ -1,5 +1,11 @@
-use std::collections::hash_map::DefaultHasher;
-use std::hash::{Hash, Hasher};
+use std::collections::VecDeque;
+use std::net::IpAddr;
+
+use sha2::{Digest, Sha256};
+
+use crate::capability::EnabledCaps;
+use crate::command::{ModeTrait, UserMode};
+use crate::unix_time;
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Hash)]
 pub struct User{
@@ -8,6 +14,26 @@ pub struct User{
     realname: String,
     server: String,
     nick: Option<String>,
+    /// address the user connected from
+    ip: String,
+    /// whether the user connected over TLS
+    secure: bool,
+    /// connection class of the listener the user connected through
+    class: Option<String>,
+    /// IRCv3 capabilities the connection negotiated
+    caps: EnabledCaps,
+    /// account the user is logged in to, if any
+    account: Option<String>,
+    modes: Vec<UserMode>,
+    /// message sent to anyone messaging the user while they are away
+    away: Option<String>,
+    /// unix timestamp of when the user registered
+    signon: u64,
+    /// unix timestamp of the last message the user sent, used for idle time
+    last_active: u64,
+    /// the host shown while the user is +x
+    #[serde(default)]
+    cloak: String,
 }
 
 impl User {
@@ -17,12 +43,23 @@ impl User {
         realname: String,
         server: String
     ) -> Self {
+        let now = unix_time();
         Self {
             username,
             hostname,
             realname,
             server,
             nick: None,
+            ip: String::new(),
+            secure: false,
+            class: None,
+            caps: EnabledCaps::default(),
+            account: None,
+            modes: Vec::new(),
+            away: None,
+            signon: now,
+            last_active: now,
+            cloak: String::new(),
         }
     }
 
@@ -35,17 +72,64 @@ impl User {
         &self.nick
     }
 
-    pub fn gen_hash(&self) -> u64 {
-        let mut hasher = DefaultHasher::new();
-        self.hash(&mut hasher);
-        hasher.finish()
+    /// the name other users see, users that never sent NICK are known by their username
+    pub fn display_nick(&self) -> &str {
+        match &self.nick {
+            Some(nick) => nick.as_str(),
+            None => self.username.as_str(),
+        }
+    }
+
+    pub fn ip(&mut self, ip: String) -> &mut Self {
+        self.ip = ip;
+        self
+    }
+
+    pub fn ip_address(&self) -> &str {
+        self.ip.as_str()
+    }
+
+    pub fn secure(&mut self, secure: bool) -> &mut Self {
+        self.secure = secure;
+        self
+    }
+
+    pub fn is_secure(&self) -> bool {
+        self.secure
+    }
+
+    pub fn class(&mut self, class: Option<String>) -> &mut Self {
+        self.class = class;
+        self
+    }
+
+    pub fn connection_class(&self) -> Option<&str> {
+        self.class.as_deref()
+    }
+
+    pub fn caps(&mut self, caps: EnabledCaps) -> &mut Self {
+        self.caps = caps;
+        self
+    }
+
+    pub fn enabled_caps(&self) -> &EnabledCaps {
+        &self.caps
+    }
+
+    pub fn account(&self) -> &Option<String> {
+        &self.account
+    }
+
+    pub fn login(&mut self, account: Option<String>) -> &mut Self {
+        self.account = account;
+        self
     }
 
     pub fn sig(&self) -> String {
         if let Some(nick) = &self.nick {
-            format!("{}!{}@{}", nick, self.username, self.hostname)
+            format!("{}!{}@{}", nick, self.username, self.visible_host())
         }else{
-            format!("{}@{}", self.username, self.hostname)
+            format!("{}@{}", self.username, self.visible_host())
         }
     }
 
@@ -53,13 +137,220 @@ impl User {
         self.username.as_str()
     }
 
-    pub fn parse_sig(sig: &str) -> (Option<String>, String, String) {
-        /*if let Some(nick_offset) = sig.find("!") {
-            None
+    pub fn hostname(&self) -> &str {
+        self.hostname.as_str()
+    }
+
+    /// works out the host shown while the user is +x from the server's secret key
+    pub fn cloak(&mut self, key: &str) -> &mut Self {
+        self.cloak = cloak_host(&self.hostname, key);
+        self
+    }
+
+    /// the hostname shown to other users, hidden behind a keyed hash while the user is +x
+    pub fn visible_host(&self) -> String {
+        match self.has_mode(&UserMode::MaskedHost) {
+            true if self.cloak.is_empty() => cloak_host(&self.hostname, ""),
+            true => self.cloak.clone(),
+            false => self.hostname.clone(),
+        }
+    }
+
+    /// the address shown to other users, hidden while the user is +x
+    pub fn visible_ip(&self) -> &str {
+        if self.has_mode(&UserMode::MaskedHost) {
+            "255.255.255.255"
         }else{
-            None
-        };*/
+            self.ip.as_str()
+        }
+    }
+
+    pub fn realname(&self) -> &str {
+        self.realname.as_str()
+    }
+
+    pub fn server(&self) -> &str {
+        self.server.as_str()
+    }
+
+    pub fn modes(&self) -> &Vec<UserMode> {
+        &self.modes
+    }
+
+    pub fn has_mode(&self, mode: &UserMode) -> bool {
+        self.modes.contains(mode)
+    }
+
+    /// returns false when the user already had the mode
+    pub fn add_mode(&mut self, mode: UserMode) -> bool {
+        if self.has_mode(&mode) {
+            return false;
+        }
+        self.modes.push(mode);
+        true
+    }
+
+    /// returns false when the user didn't have the mode
+    pub fn remove_mode(&mut self, mode: &UserMode) -> bool {
+        let before = self.modes.len();
+        self.modes.retain(|other| other != mode);
+        before != self.modes.len()
+    }
 
-        unimplemented!()
+    /// the user's modes as a string such as "+iw"
+    pub fn mode_string(&self) -> String {
+        let mut out = String::from("+");
+        out.extend(self.modes.iter().map(|mode| mode.to_char()));
+        out
+    }
+
+    /// sets or clears the away message, keeping the away mode in step with it
+    pub fn away(&mut self, message: Option<String>) -> &mut Self {
+        self.modes.retain(|mode| *mode != UserMode::Away);
+        if message.is_some() {
+            self.modes.push(UserMode::Away);
+        }
+        self.away = message;
+        self
     }
-}
\ No newline at end of file
+
+    pub fn away_message(&self) -> &Option<String> {
+        &self.away
+    }
+
+    pub fn signon(&self) -> u64 {
+        self.signon
+    }
+
+    /// keeps the signon time a user on another server was introduced with
+    pub fn signed_on(&mut self, signon: u64) -> &mut Self {
+        self.signon = signon;
+        self
+    }
+
+    /// marks the user as active, resetting their idle time
+    pub fn touch(&mut self) {
+        self.last_active = unix_time();
+    }
+
+    /// seconds since the user last sent a message
+    pub fn idle(&self) -> u64 {
+        unix_time().saturating_sub(self.last_active)
+    }
+
+    /// splits a signature of the form nick!user@host into its parts
+    pub fn parse_sig(sig: &str) -> (Option<String>, String, String) {
+        let (nick, rest) = match sig.find('!') {
+            Some(offset) => (Some(sig[..offset].to_string()), &sig[offset+1..]),
+            None => (None, sig),
+        };
+        match rest.find('@') {
+            Some(offset) => (nick, rest[..offset].to_string(), rest[offset+1..].to_string()),
+            None => (nick, rest.to_string(), String::new()),
+        }
+    }
+}
+
+/// a record of a nickname that is no longer in use, kept for WHOWAS
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WhowasEntry {
+    pub nick: String,
+    pub username: String,
+    pub hostname: String,
+    pub realname: String,
+    pub server: String,
+    /// unix timestamp of when the nickname was given up
+    pub logoff: u64,
+}
+
+/// bounded history of nicknames, the oldest entries are dropped once full
+pub struct NickHistory {
+    entries: VecDeque<WhowasEntry>,
+    capacity: usize,
+}
+
+impl NickHistory {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// records the current nickname of a user who is changing nick or leaving
+    pub fn record(&mut self, user: &User, server: &str) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(WhowasEntry {
+            nick: user.display_nick().to_string(),
+            username: user.username().to_string(),
+            hostname: user.visible_host(),
+            realname: user.realname().to_string(),
+            server: server.to_string(),
+            logoff: unix_time(),
+        });
+    }
+
+    /// returns the most recent entries for a nickname, newest first
+    /// a count of None returns every entry
+    pub fn lookup(&self, nick: &str, count: Option<usize>) -> Vec<&WhowasEntry> {
+        self.entries.iter()
+            .rev()
+            .filter(|entry| entry.nick.eq_ignore_ascii_case(nick))
+            .take(count.unwrap_or(usize::MAX))
+            .collect()
+    }
+}
+
+/// hides a hostname behind a hash of it and a secret key, so the host can't be found by
+/// hashing guesses without the key; the domain is kept as other ircds do so bans on it still work
+pub fn cloak_host(hostname: &str, key: &str) -> String {
+    let digest = Sha256::digest(format!("{}:{}", key, hostname));
+    let hash = digest[..6].iter().map(|byte| format!("{:02x}", byte)).collect::<String>();
+    match hostname.split_once('.') {
+        Some((_, domain)) if hostname.parse::<IpAddr>().is_err() => format!("{}.{}", hash, domain),
+        _ => format!("{}.masked", hash),
+    }
+}
+
+#[test]
+async fn cloak_host_test() {
+    assert!(cloak_host("hephaestus.example.com", "secret").ends_with(".example.com"));
+    assert_ne!(cloak_host("hephaestus.example.com", "secret"), cloak_host("hephaestus.example.com", "other"));
+    assert_ne!(cloak_host("hephaestus.example.com", "secret"), cloak_host("athena.example.com", "secret"));
+    // addresses and bare names have no domain to keep
+    assert!(cloak_host("10.0.0.1", "secret").ends_with(".masked"));
+    assert!(!cloak_host("10.0.0.1", "secret").contains("10.0"));
+    assert!(cloak_host("hephaestus", "secret").ends_with(".masked"));
+
+    let mut user = User::new("cardinal".to_string(), "hephaestus.example.com".to_string(), "Julian Lazaras".to_string(), "127.0.0.1".to_string());
+    user.cloak("secret").add_mode(UserMode::MaskedHost);
+    assert_eq!(user.visible_host(), cloak_host("hephaestus.example.com", "secret"));
+}
+
+#[test]
+async fn nick_history_test() {
+    let mut user = User::new("cardinal".to_string(), "hephaestus".to_string(), "Julian Lazaras".to_string(), "127.0.0.1".to_string());
+    let mut history = NickHistory::new(2);
+    user.nick("first".to_string());
+    history.record(&user, "irc.local");
+    user.nick("second".to_string());
+    history.record(&user, "irc.local");
+    history.record(&user, "irc.local");
+
+    // the first entry has been pushed out by the bound
+    assert!(history.lookup("first", None).is_empty());
+    assert_eq!(history.lookup("SECOND", None).len(), 2);
+    assert_eq!(history.lookup("second", Some(1)).len(), 1);
+
+    user.add_mode(UserMode::MaskedHost);
+    assert!(!user.sig().contains("hephaestus"));
+    assert!(user.remove_mode(&UserMode::MaskedHost));
+    assert!(user.sig().ends_with("@hephaestus"));
+
+    assert_eq!(User::parse_sig("nick!user@host"), (Some("nick".to_string()), "user".to_string(), "host".to_string()));
+}
@@ -0,0 +1,147 @@
+//! two server processes linked to each other, checking that a user's changes on one
+//! server are seen by the users of the other
+
+#[macro_use]extern crate tokio;
+
+use std::path::Path;
+use std::process::Stdio;
+
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::TcpStream;
+use tokio::process::{Child, Command};
+use tokio::time::{sleep, timeout, Duration, Instant};
+
+use networkingirc::auth::hash_password;
+
+const WAIT: Duration = Duration::from_secs(15);
+
+/// a port nothing is listening on right now
+fn free_port() -> u16 {
+    std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+}
+
+/// starts the server binary, it is killed when the test ends
+fn start_server(directory: &Path, name: &str, config: &str) -> Child {
+    let path = directory.join(format!("{}.toml", name));
+    std::fs::write(&path, config).unwrap();
+    Command::new(env!("CARGO_BIN_EXE_server"))
+        .arg("-c")
+        .arg(&path)
+        .stdout(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .unwrap()
+}
+
+struct Client {
+    lines: Lines<BufReader<OwnedReadHalf>>,
+    writer: OwnedWriteHalf,
+}
+
+impl Client {
+    /// connects once the server is listening and registers the nickname
+    async fn register(port: u16, nick: &str) -> Self {
+        let deadline = Instant::now() + WAIT;
+        let stream = loop {
+            match TcpStream::connect(("127.0.0.1", port)).await {
+                Ok(stream) => break stream,
+                Err(e) if Instant::now() > deadline => panic!("couldn't connect to port {}: {}", port, e),
+                Err(_) => sleep(Duration::from_millis(100)).await,
+            }
+        };
+        let (reader, writer) = stream.into_split();
+        let mut client = Self { lines: BufReader::new(reader).lines(), writer };
+        client.send(&format!("NICK {}\r\nUSER {} 0 * :{}\r\n", nick, nick, nick)).await;
+        client.read_until(" 001 ").await;
+        client
+    }
+
+    async fn send(&mut self, line: &str) {
+        self.writer.write_all(line.as_bytes()).await.unwrap();
+    }
+
+    /// the lines up to and including the first one containing `end`, PINGs are answered
+    async fn read_until(&mut self, end: &str) -> Vec<String> {
+        let mut lines = Vec::new();
+        loop {
+            let line = timeout(WAIT, self.lines.next_line()).await
+                .unwrap_or_else(|_| panic!("no line containing {:?} after {:?}", end, lines))
+                .unwrap()
+                .expect("the server closed the connection");
+            if let Some(token) = line.strip_prefix("PING ") {
+                self.send(&format!("PONG {}\r\n", token)).await;
+                continue;
+            }
+            let done = line.contains(end);
+            lines.push(line);
+            if done {
+                return lines;
+            }
+        }
+    }
+
+    /// repeats a query until its reply passes the check
+    async fn poll(&mut self, query: &str, end: &str, check: impl Fn(&[String]) -> bool) -> Vec<String> {
+        let deadline = Instant::now() + WAIT;
+        loop {
+            self.send(&format!("{}\r\n", query)).await;
+            let reply = self.read_until(end).await;
+            if check(&reply) {
+                return reply;
+            }
+            if Instant::now() > deadline {
+                panic!("{} never gave the expected reply, last was {:?}", query, reply);
+            }
+            sleep(Duration::from_millis(100)).await;
+        }
+    }
+}
+
+fn has_numeric(lines: &[String], code: &str) -> bool {
+    lines.iter().any(|line| line.split(' ').nth(1) == Some(code))
+}
+
+#[test]
+async fn user_mode_and_away_reach_linked_server_test() {
+    let directory = std::env::temp_dir().join(format!("networkingirc-link-test-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    let link_hash = hash_password("linkpass").unwrap();
+    let oper_hash = hash_password("operpass").unwrap();
+    let (port_a, port_b) = (free_port(), free_port());
+
+    let _b = start_server(&directory, "b", &format!(
+        "[server]\nname = \"b.local\"\nlisten = [\"127.0.0.1:{}\"]\n\
+        [[link]]\nname = \"a.local\"\npassword = \"{}\"\nsend_password = \"linkpass\"\n",
+        port_b, link_hash));
+    let mut bob = Client::register(port_b, "bob").await;
+    // so bob doesn't share the welcome channel with alice
+    bob.send("PART Welcome\r\nNAMES Welcome\r\n").await;
+    bob.read_until(" 366 ").await;
+    let _a = start_server(&directory, "a", &format!(
+        "[server]\nname = \"a.local\"\nlisten = [\"127.0.0.1:{}\"]\n\
+        [[oper]]\nname = \"op\"\npassword = \"{}\"\nhosts = [\"*@127.0.0.1\"]\n\
+        [[link]]\nname = \"b.local\"\naddress = \"127.0.0.1:{}\"\npassword = \"{}\"\nsend_password = \"linkpass\"\nautoconnect = true\n",
+        port_a, oper_hash, port_b, link_hash));
+    let mut alice = Client::register(port_a, "alice").await;
+
+    // alice is only known on b once the link is up
+    bob.poll("WHOIS alice", " 318 ", |reply| has_numeric(reply, "311")).await;
+
+    alice.send("MODE alice +i\r\nAWAY :gone fishing\r\nOPER op operpass\r\n").await;
+    alice.read_until(" 381 ").await;
+    let whois = bob.poll("WHOIS alice", " 318 ", |reply| has_numeric(reply, "301") && has_numeric(reply, "313")).await;
+    assert!(whois.iter().any(|line| line.contains(" 301 ") && line.ends_with(":gone fishing")));
+    // invisible users aren't listed to those who don't share a channel with them
+    let who = bob.poll("WHO alice", " 315 ", |reply| !has_numeric(reply, "352")).await;
+    assert_eq!(who.len(), 1);
+
+    alice.send("AWAY\r\nMODE alice -i\r\n").await;
+    alice.read_until(" 305 ").await;
+    let who = bob.poll("WHO alice", " 315 ", |reply| has_numeric(reply, "352")).await;
+    assert!(who[0].contains(" alice H* :"));
+    let whois = bob.poll("WHOIS alice", " 318 ", |reply| !has_numeric(reply, "301")).await;
+    assert!(has_numeric(&whois, "313"));
+
+    std::fs::remove_dir_all(&directory).ok();
+}